@@ -21,11 +21,42 @@ in {
       description = ''
         Path to the GitHub webhook secret file. This is loaded with systemd
         LoadCredential and exposed to the service as
-        GITHUB_SECRET_FILE=/run/credentials/%n/github_secret_file.
+        GITHUB_SECRET_FILE=$CREDENTIALS_DIRECTORY/github_secret_file.
         Avoid builtins.readFile; pass the file path (e.g., from agenix).
       '';
     };
 
+    extraGithubSecretFiles = mkOption {
+      type = types.listOf types.path;
+      default = [];
+      example = [ "/run/agenix/github_webhook_secret_next" ];
+      description = ''
+        Additional GitHub webhook secret files that are also accepted. Use
+        this while rotating the webhook secret, then drop the old file once
+        the logs show it no longer matches any deliveries.
+      '';
+    };
+
+    repositorySecretFiles = mkOption {
+      type = types.attrsOf types.path;
+      default = {};
+      example = { "my-org/my-repo" = "/run/agenix/my_repo_webhook_secret"; };
+      description = ''
+        Secret files keyed by repository full name. Deliveries from these
+        repositories are only verified against their own secret.
+      '';
+    };
+
+    organizationSecretFiles = mkOption {
+      type = types.attrsOf types.path;
+      default = {};
+      example = { "my-org" = "/run/agenix/my_org_webhook_secret"; };
+      description = ''
+        Secret files keyed by organization login. Used for repositories in the
+        organization that have no entry in repositorySecretFiles.
+      '';
+    };
+
     jenkinsUrl = mkOption {
       type = types.str;
       example = "https://jenkins.example.com/github-webhook/";
@@ -35,6 +66,115 @@ in {
       '';
     };
 
+    extraJenkinsUrls = mkOption {
+      type = types.listOf types.str;
+      default = [];
+      description = ''
+        Additional Jenkins endpoints that every webhook is also relayed to.
+      '';
+    };
+
+    fanOutMode = mkOption {
+      type = types.enum [ "first-success" "all" "quorum" ];
+      default = "all";
+      description = ''
+        When a webhook counts as delivered with several Jenkins endpoints.
+        Passed to --fan-out-mode.
+      '';
+    };
+
+    configFile = mkOption {
+      type = types.nullOr types.path;
+      default = null;
+      description = ''
+        TOML config file. Passed to --config. Options set here take
+        precedence over the file.
+      '';
+    };
+
+    routesFile = mkOption {
+      type = types.nullOr types.path;
+      default = null;
+      description = ''
+        TOML file with routing rules. Passed to --routes-file.
+      '';
+    };
+
+    queue = {
+      enable = mkOption {
+        type = types.bool;
+        default = false;
+        description = ''
+          Acknowledge verified webhooks right away and deliver them to Jenkins
+          from a durable queue under the service's state directory, retrying
+          while Jenkins is unavailable.
+        '';
+      };
+
+      maxAge = mkOption {
+        type = types.ints.positive;
+        default = 86400;
+        description = ''
+          Seconds to keep retrying a delivery before it becomes a dead letter.
+          Passed to --queue-max-age.
+        '';
+      };
+    };
+
+    jenkinsTls = {
+      caBundle = mkOption {
+        type = types.nullOr types.path;
+        default = null;
+        description = ''
+          PEM file of CA certificates to trust for Jenkins. Passed to
+          --jenkins-ca-bundle.
+        '';
+      };
+
+      clientCertFile = mkOption {
+        type = types.nullOr types.path;
+        default = null;
+        description = ''
+          PEM client certificate to present to Jenkins, loaded with
+          LoadCredential.
+        '';
+      };
+
+      clientKeyFile = mkOption {
+        type = types.nullOr types.path;
+        default = null;
+        description = ''
+          PEM private key for clientCertFile, loaded with LoadCredential.
+        '';
+      };
+    };
+
+    jenkinsUser = mkOption {
+      type = types.nullOr types.str;
+      default = null;
+      description = ''
+        Jenkins user to authenticate as on jenkinsUrl and extraJenkinsUrls.
+        Passed to --jenkins-user.
+      '';
+    };
+
+    jenkinsApiTokenFile = mkOption {
+      type = types.nullOr types.path;
+      default = null;
+      description = ''
+        File with the API token of jenkinsUser, loaded with LoadCredential.
+      '';
+    };
+
+    adminTokenFile = mkOption {
+      type = types.nullOr types.path;
+      default = null;
+      description = ''
+        File with the bearer token for the /admin endpoints, loaded with
+        LoadCredential. The endpoints are disabled when this is null.
+      '';
+    };
+
     host = mkOption {
       type = types.str;
       default = "127.0.0.1";
@@ -47,12 +187,68 @@ in {
       description = "TCP port to listen on. Passed to --port (default 8080).";
     };
 
+    tls = {
+      certFile = mkOption {
+        type = types.nullOr types.path;
+        default = null;
+        description = ''
+          PEM certificate (chain) to serve HTTPS with, loaded with
+          LoadCredential. Credentials are copied again when the service is
+          reloaded (systemd 258 or newer), so list it in
+          security.acme.certs.<name>.reloadServices to pick up renewals
+          without dropping deliveries.
+        '';
+      };
+
+      keyFile = mkOption {
+        type = types.nullOr types.path;
+        default = null;
+        description = ''
+          PEM private key for certFile, loaded with LoadCredential.
+        '';
+      };
+
+      httpsPort = mkOption {
+        type = types.nullOr types.port;
+        default = null;
+        description = ''
+          Serve HTTPS on this port and keep plain HTTP on port. When null and
+          certFile is set, port itself serves HTTPS. Passed to --https-port.
+        '';
+      };
+    };
+
+    metricsPort = mkOption {
+      type = types.nullOr types.port;
+      default = null;
+      description = ''
+        Serve /metrics on this port instead of the webhook port. Passed to
+        --metrics-port.
+      '';
+    };
+
+    otlpEndpoint = mkOption {
+      type = types.nullOr types.str;
+      default = null;
+      example = "http://localhost:4318";
+      description = ''
+        OTLP/HTTP collector to export a trace of every webhook to. Passed to
+        --otlp-endpoint.
+      '';
+    };
+
     logLevel = mkOption {
       type = types.enum [ "trace" "debug" "info" "warn" "error" ];
       default = "info";
       description = "Log level for the service. Passed to --log-level.";
     };
 
+    logFormat = mkOption {
+      type = types.enum [ "full" "compact" "pretty" "json" ];
+      default = "full";
+      description = "Log format for the service. Passed to --log-format.";
+    };
+
     environmentFile = mkOption {
       type = types.nullOr types.path;
       default = null;
@@ -80,14 +276,92 @@ in {
     (let
       cfg = config.services.github-to-jenkins-webhook;
       bin = lib.getExe cfg.package;
+      extraSecretNames = lib.imap0
+        (i: _: "github_secret_file_${toString i}")
+        cfg.extraGithubSecretFiles;
+      credentialName = prefix: key:
+        "${prefix}_${lib.replaceStrings [ "/" ] [ "__" ] key}";
+      scopedSecrets = flag: prefix: files: lib.mapAttrsToList
+        (key: path: {
+          arg = [ flag "${key}=%d/${credentialName prefix key}" ];
+          credential = "${credentialName prefix key}:${path}";
+        })
+        files;
+      allScopedSecrets =
+        scopedSecrets "--repository-secret-file" "repository_secret"
+          cfg.repositorySecretFiles
+        ++ scopedSecrets "--organization-secret-file" "organization_secret"
+          cfg.organizationSecretFiles;
       args = [
         "--host" cfg.host
         "--port" (toString cfg.port)
         "--log-level" cfg.logLevel
+        "--log-format" cfg.logFormat
         "--jenkins-url" cfg.jenkinsUrl
-        "--github-secret-file" "/run/credentials/%n/github_secret_file"
-      ] ++ cfg.extraArgs;
+        "--fan-out-mode" cfg.fanOutMode
+        "--github-secret-file" "%d/github_secret_file"
+      ] ++ lib.optionals cfg.queue.enable [
+        "--queue-dir" "/var/lib/github-to-jenkins-webhook/queue"
+        "--queue-max-age" (toString cfg.queue.maxAge)
+      ]
+      ++ [
+        "--dedup-file" "/var/lib/github-to-jenkins-webhook/delivery-ids"
+      ]
+      ++ lib.optionals (cfg.tls.certFile != null) [
+        "--tls-cert" "%d/tls_cert"
+        "--tls-key" "%d/tls_key"
+      ]
+      ++ lib.optionals (cfg.tls.httpsPort != null)
+        [ "--https-port" (toString cfg.tls.httpsPort) ]
+      ++ lib.optionals (cfg.metricsPort != null)
+        [ "--metrics-port" (toString cfg.metricsPort) ]
+      ++ lib.optionals (cfg.otlpEndpoint != null)
+        [ "--otlp-endpoint" cfg.otlpEndpoint ]
+      ++ lib.optionals (cfg.jenkinsTls.caBundle != null)
+        [ "--jenkins-ca-bundle" (toString cfg.jenkinsTls.caBundle) ]
+      ++ lib.optionals (cfg.jenkinsTls.clientCertFile != null) [
+        "--jenkins-client-cert" "%d/jenkins_client_cert"
+        "--jenkins-client-key" "%d/jenkins_client_key"
+      ]
+      ++ lib.optionals (cfg.jenkinsUser != null) [
+        "--jenkins-user" cfg.jenkinsUser
+        "--jenkins-api-token-file" "%d/jenkins_api_token"
+      ]
+      ++ lib.optionals (cfg.adminTokenFile != null)
+        [ "--admin-token-file" "%d/admin_token_file" ]
+      ++ lib.optionals (cfg.configFile != null)
+        [ "--config" (toString cfg.configFile) ]
+      ++ lib.optionals (cfg.routesFile != null)
+        [ "--routes-file" (toString cfg.routesFile) ]
+      ++ lib.concatMap (url: [ "--jenkins-url" url ]) cfg.extraJenkinsUrls
+      ++ lib.concatMap
+        (name: [ "--github-secret-file" "%d/${name}" ])
+        extraSecretNames
+      ++ lib.concatMap (s: s.arg) allScopedSecrets
+      ++ cfg.extraArgs;
+      opt = "services.github-to-jenkins-webhook";
     in {
+      assertions = [
+        {
+          assertion = (cfg.jenkinsTls.clientCertFile == null)
+            == (cfg.jenkinsTls.clientKeyFile == null);
+          message = "${opt}.jenkinsTls.clientCertFile and"
+            + " ${opt}.jenkinsTls.clientKeyFile must be set together.";
+        }
+        {
+          assertion = (cfg.tls.certFile == null)
+            == (cfg.tls.keyFile == null);
+          message = "${opt}.tls.certFile and ${opt}.tls.keyFile"
+            + " must be set together.";
+        }
+        {
+          assertion = (cfg.jenkinsUser == null)
+            == (cfg.jenkinsApiTokenFile == null);
+          message = "${opt}.jenkinsUser and ${opt}.jenkinsApiTokenFile"
+            + " must be set together.";
+        }
+      ];
+
       systemd.services.github-to-jenkins-webhook = {
         description = "GitHub -> Jenkins webhook relay";
         after = [ "network-online.target" ];
@@ -96,17 +370,40 @@ in {
 
         serviceConfig = {
           ExecStart = lib.escapeShellArgs ([ bin ] ++ args);
+          ExecReload = "${pkgs.coreutils}/bin/kill -HUP $MAINPID";
+          # Copy the credentials into $CREDENTIALS_DIRECTORY (%d) again before
+          # the SIGHUP, so that a reload re-reads rotated secrets and
+          # certificates instead of the copies made at start.
+          RefreshOnReload = "credentials";
 
           Environment = [
-            "GITHUB_SECRET_FILE=/run/credentials/%n/github_secret_file"
+            "GITHUB_SECRET_FILE=%d/github_secret_file"
           ] ++ (lib.mapAttrsToList (k: v: "${k}=${v}") cfg.extraEnvironment);
           EnvironmentFile = lib.mkIf
             (cfg.environmentFile != null)
             cfg.environmentFile
           ;
-          LoadCredential = [ "github_secret_file:${cfg.githubSecretFile}" ];
+          LoadCredential = [ "github_secret_file:${cfg.githubSecretFile}" ]
+            ++ lib.zipListsWith
+              (name: path: "${name}:${path}")
+              extraSecretNames
+              cfg.extraGithubSecretFiles
+            ++ map (s: s.credential) allScopedSecrets
+            ++ lib.optional (cfg.jenkinsUser != null)
+              "jenkins_api_token:${cfg.jenkinsApiTokenFile}"
+            ++ lib.optional (cfg.adminTokenFile != null)
+              "admin_token_file:${cfg.adminTokenFile}"
+            ++ lib.optionals (cfg.jenkinsTls.clientCertFile != null) [
+              "jenkins_client_cert:${cfg.jenkinsTls.clientCertFile}"
+              "jenkins_client_key:${cfg.jenkinsTls.clientKeyFile}"
+            ]
+            ++ lib.optionals (cfg.tls.certFile != null) [
+              "tls_cert:${cfg.tls.certFile}"
+              "tls_key:${cfg.tls.keyFile}"
+            ];
           # Sandboxing; keep it reasonable for a small HTTP service
           DynamicUser = true;
+          StateDirectory = "github-to-jenkins-webhook";
           ProtectSystem = "strict";
           ProtectHome = true;
           PrivateTmp = true;
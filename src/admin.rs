@@ -0,0 +1,163 @@
+use actix_web::{web, HttpRequest, HttpResponse, Scope};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use tracing::{info, warn};
+
+use crate::drift::SchemaDrift;
+use crate::error::ProxyError;
+use crate::queue::DeliveryQueue;
+use crate::state::StateHandle;
+
+/**
+ * Operator endpoints, mounted under `/admin`.  Every request needs
+ * `Authorization: Bearer <token>` matching `--admin-token-file`, and the
+ * whole scope answers 404 when no token is configured.
+ */
+pub fn scope() -> Scope {
+  web::scope("/admin")
+    .route("/dead-letters", web::get().to(list_dead_letters))
+    .route("/dead-letters/replay", web::post().to(replay_all))
+    .route("/dead-letters/{id}/replay", web::post().to(replay_one))
+    .route("/schema-drift", web::get().to(list_schema_drift))
+    .route("/schema-drift", web::delete().to(clear_schema_drift))
+}
+
+#[derive(Serialize)]
+struct DeadLetter {
+  id: String,
+  delivery_id: Option<String>,
+  event: String,
+  repository: Option<String>,
+  targets: Vec<String>,
+  attempts: u32,
+  last_error: Option<String>,
+  received_at: DateTime<Utc>,
+}
+
+#[derive(Serialize)]
+struct ReplayResult {
+  id: String,
+  delivered: bool,
+  error: Option<String>,
+}
+
+fn authorize(req: &HttpRequest, state: &StateHandle) -> Result<(), ProxyError> {
+  let Some(token) = &state.current().admin_token else {
+    return Err(ProxyError::NotFound(req.path().to_string()));
+  };
+  let presented = req
+    .headers()
+    .get("Authorization")
+    .and_then(|h| h.to_str().ok())
+    .and_then(|h| h.strip_prefix("Bearer "));
+  match presented {
+    Some(presented) if constant_time_eq(presented, token) => Ok(()),
+    _ => {
+      warn!(
+        "Rejected unauthorized admin request {} {} from {:?}",
+        req.method(),
+        req.path(),
+        req.connection_info().peer_addr(),
+      );
+      Err(ProxyError::Unauthorized)
+    }
+  }
+}
+
+fn constant_time_eq(a: &str, b: &str) -> bool {
+  a.len() == b.len()
+    && a
+      .bytes()
+      .zip(b.bytes())
+      .fold(0u8, |acc, (x, y)| acc | (x ^ y))
+      == 0
+}
+
+fn require_queue(
+  queue: Option<web::Data<DeliveryQueue>>,
+) -> Result<web::Data<DeliveryQueue>, ProxyError> {
+  queue.ok_or_else(|| {
+    ProxyError::NotFound("The delivery queue is not enabled".to_string())
+  })
+}
+
+async fn list_dead_letters(
+  req: HttpRequest,
+  state: web::Data<StateHandle>,
+  queue: Option<web::Data<DeliveryQueue>>,
+) -> Result<HttpResponse, ProxyError> {
+  authorize(&req, &state)?;
+  let queue = require_queue(queue)?;
+  let dead_letters: Vec<DeadLetter> = queue
+    .dead_letters()
+    .into_iter()
+    .map(|d| DeadLetter {
+      id: d.id,
+      delivery_id: d.delivery_id,
+      event: d.event,
+      repository: d.repository,
+      targets: d.targets.into_iter().map(|t| t.name).collect(),
+      attempts: d.attempts,
+      last_error: d.last_error,
+      received_at: d.received_at,
+    })
+    .collect();
+  Ok(HttpResponse::Ok().json(dead_letters))
+}
+
+async fn replay_one(
+  req: HttpRequest,
+  path: web::Path<String>,
+  state: web::Data<StateHandle>,
+  queue: Option<web::Data<DeliveryQueue>>,
+) -> Result<HttpResponse, ProxyError> {
+  authorize(&req, &state)?;
+  let queue = require_queue(queue)?;
+  let summary = queue.replay(&state, &path).await?;
+  if summary.delivered {
+    Ok(HttpResponse::Ok().json(summary))
+  } else {
+    Ok(HttpResponse::BadGateway().json(summary))
+  }
+}
+
+async fn replay_all(
+  req: HttpRequest,
+  state: web::Data<StateHandle>,
+  queue: Option<web::Data<DeliveryQueue>>,
+) -> Result<HttpResponse, ProxyError> {
+  authorize(&req, &state)?;
+  let queue = require_queue(queue)?;
+  let dead_letters = queue.dead_letters();
+  info!("Replaying {} dead letter(s)", dead_letters.len());
+  let mut results = vec![];
+  for dead_letter in dead_letters {
+    let result = queue.replay(&state, &dead_letter.id).await;
+    results.push(ReplayResult {
+      id: dead_letter.id,
+      delivered: result.as_ref().is_ok_and(|s| s.delivered),
+      error: result.err().map(|e| e.to_string()),
+    });
+  }
+  Ok(HttpResponse::Ok().json(results))
+}
+
+async fn list_schema_drift(
+  req: HttpRequest,
+  state: web::Data<StateHandle>,
+  drift: web::Data<SchemaDrift>,
+) -> Result<HttpResponse, ProxyError> {
+  authorize(&req, &state)?;
+  Ok(HttpResponse::Ok().json(drift.entries()))
+}
+
+async fn clear_schema_drift(
+  req: HttpRequest,
+  state: web::Data<StateHandle>,
+  drift: web::Data<SchemaDrift>,
+) -> Result<HttpResponse, ProxyError> {
+  authorize(&req, &state)?;
+  let cleared = drift.clear();
+  info!("Cleared {} schema mismatch(es)", cleared);
+  Ok(HttpResponse::Ok().json(serde_json::json!({ "cleared": cleared })))
+}
@@ -1,91 +1,904 @@
-use clap::Parser;
-use std::fs;
-use std::path::PathBuf;
+use clap::builder::BoolishValueParser;
+use clap::{Parser, Subcommand};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 use tracing::Level;
 
-#[derive(Parser, Debug)]
+use crate::config::{FileConfig, TargetConfig};
+use crate::filters::EventFilter;
+use crate::jenkins::{Endpoint, FanOutMode, JenkinsTarget, TargetMode};
+use crate::jenkins_auth::JenkinsAuth;
+use crate::routing::{RouteTable, RoutesConfig};
+use crate::secrets::{
+  read_secret_dir, read_secret_file, SecretStore, WebhookSecret,
+};
+use crate::telemetry::LogFormat;
+use crate::webhook::Validation;
+
+#[derive(Parser, Clone, Debug)]
 #[clap(name = "github-jenkins-proxy")]
 #[clap(
   about = "A secure proxy between GitHub webhooks and Jenkins",
   long_about = None,
 )]
 pub struct Args {
+  #[clap(
+    short = 'c',
+    long = "config",
+    env = "CONFIG_FILE",
+    help = "TOML config file. Command line flags and environment variables \
+            take precedence over its values"
+  )]
+  pub config: Option<PathBuf>,
+
   #[clap(
     short = 's',
     long = "github-secret",
     env = "GITHUB_SECRET",
     help = "GitHub webhook secret for HMAC validation",
-    conflicts_with = "github_secret_file"
+    conflicts_with_all = ["github_secret_file", "github_secret_dir"]
   )]
   pub github_secret: Option<String>,
 
   #[clap(
     long = "github-secret-file",
-    help = "Path to file containing GitHub webhook secret for HMAC validation",
+    help = "Path to file containing GitHub webhook secret for HMAC validation. \
+            May be repeated to accept several secrets during rotation",
+    conflicts_with = "github_secret"
+  )]
+  pub github_secret_file: Vec<PathBuf>,
+
+  #[clap(
+    long = "github-secret-dir",
+    help = "Directory whose files each contain a GitHub webhook secret for \
+            HMAC validation",
     conflicts_with = "github_secret"
   )]
-  pub github_secret_file: Option<PathBuf>,
+  pub github_secret_dir: Option<PathBuf>,
+
+  #[clap(
+    long = "repository-secret-file",
+    value_name = "OWNER/REPO=PATH",
+    value_parser = parse_key_path,
+    help = "Secret file used only for deliveries from the given repository. \
+            May be repeated"
+  )]
+  pub repository_secret_file: Vec<(String, PathBuf)>,
+
+  #[clap(
+    long = "organization-secret-file",
+    value_name = "ORG=PATH",
+    value_parser = parse_key_path,
+    help = "Secret file used only for deliveries from repositories in the \
+            given organization. May be repeated"
+  )]
+  pub organization_secret_file: Vec<(String, PathBuf)>,
+
+  #[clap(
+    long = "allow-legacy-sha1-signature",
+    env = "ALLOW_LEGACY_SHA1_SIGNATURE",
+    num_args = 0..=1,
+    require_equals = true,
+    default_missing_value = "true",
+    value_parser = BoolishValueParser::new(),
+    help = "Accept the legacy X-Hub-Signature (HMAC-SHA1) header when \
+            X-Hub-Signature-256 is absent. Only for older GitHub Enterprise \
+            Server instances"
+  )]
+  pub allow_legacy_sha1_signature: Option<bool>,
 
   #[clap(
     short = 'j',
     long = "jenkins-url",
     env = "JENKINS_URL",
-    help = "Jenkins server URL to forward webhooks to"
+    value_delimiter = ',',
+    help = "Jenkins server URL to forward webhooks to. May be repeated (or \
+            comma separated) to relay each webhook to several controllers"
+  )]
+  pub jenkins_url: Vec<String>,
+
+  #[clap(
+    long = "fan-out-mode",
+    env = "FAN_OUT_MODE",
+    help = "When a webhook counts as delivered with several Jenkins URLs \
+            (first-success, all, quorum) [default: all]"
+  )]
+  pub fan_out_mode: Option<String>,
+
+  #[clap(
+    long = "fan-out-quorum",
+    env = "FAN_OUT_QUORUM",
+    help = "Number of Jenkins URLs that must accept a webhook in quorum mode. \
+            Defaults to a majority"
+  )]
+  pub fan_out_quorum: Option<usize>,
+
+  #[clap(
+    long = "jenkins-user",
+    env = "JENKINS_USER",
+    requires = "jenkins_api_token_file",
+    help = "Jenkins user to authenticate as on the --jenkins-url targets"
+  )]
+  pub jenkins_user: Option<String>,
+
+  #[clap(
+    long = "jenkins-api-token-file",
+    env = "JENKINS_API_TOKEN_FILE",
+    requires = "jenkins_user",
+    help = "Path to file containing the API token (or password) of \
+            --jenkins-user"
+  )]
+  pub jenkins_api_token_file: Option<PathBuf>,
+
+  #[clap(
+    long = "jenkins-connect-timeout",
+    env = "JENKINS_CONNECT_TIMEOUT",
+    help = "Seconds to wait for a connection to Jenkins [default: 10]"
   )]
-  pub jenkins_url: String,
+  pub jenkins_connect_timeout: Option<u64>,
+
+  #[clap(
+    long = "jenkins-read-timeout",
+    env = "JENKINS_READ_TIMEOUT",
+    help = "Seconds to wait for Jenkins to send more of its response \
+            [default: 30]"
+  )]
+  pub jenkins_read_timeout: Option<u64>,
+
+  #[clap(
+    long = "jenkins-pool-size",
+    env = "JENKINS_POOL_SIZE",
+    help = "Idle connections to keep open to each Jenkins host [default: 32]"
+  )]
+  pub jenkins_pool_size: Option<usize>,
+
+  #[clap(
+    long = "jenkins-http-version",
+    env = "JENKINS_HTTP_VERSION",
+    help = "HTTP version to talk to Jenkins with (auto, http1, http2). auto \
+            negotiates HTTP/2 over TLS, http2 also uses it over plain HTTP \
+            [default: auto]"
+  )]
+  pub jenkins_http_version: Option<String>,
+
+  #[clap(
+    long = "jenkins-proxy",
+    env = "JENKINS_PROXY",
+    help = "Proxy URL to reach Jenkins through, or 'none' to ignore the \
+            HTTP_PROXY and HTTPS_PROXY environment variables"
+  )]
+  pub jenkins_proxy: Option<String>,
+
+  #[clap(
+    long = "jenkins-ca-bundle",
+    env = "JENKINS_CA_BUNDLE",
+    help = "PEM file of CA certificates to trust for Jenkins, in addition to \
+            the built-in roots"
+  )]
+  pub jenkins_ca_bundle: Option<PathBuf>,
+
+  #[clap(
+    long = "jenkins-client-cert",
+    env = "JENKINS_CLIENT_CERT",
+    requires = "jenkins_client_key",
+    help = "PEM client certificate (chain) to present to Jenkins"
+  )]
+  pub jenkins_client_cert: Option<PathBuf>,
+
+  #[clap(
+    long = "jenkins-client-key",
+    env = "JENKINS_CLIENT_KEY",
+    requires = "jenkins_client_cert",
+    help = "PEM private key for --jenkins-client-cert"
+  )]
+  pub jenkins_client_key: Option<PathBuf>,
+
+  #[clap(
+    long = "jenkins-insecure-skip-verify",
+    env = "JENKINS_INSECURE_SKIP_VERIFY",
+    num_args = 0..=1,
+    require_equals = true,
+    default_missing_value = "true",
+    value_parser = BoolishValueParser::new(),
+    help = "Do not verify the TLS certificates of Jenkins. For lab setups \
+            only: anyone in the network path can read and forge webhooks"
+  )]
+  pub jenkins_insecure_skip_verify: Option<bool>,
+
+  #[clap(
+    long = "routes-file",
+    env = "ROUTES_FILE",
+    help = "TOML file with rules that pick Jenkins targets per event, \
+            repository, ref, base branch and organization"
+  )]
+  pub routes_file: Option<PathBuf>,
+
+  #[clap(
+    long = "forward-unknown-events",
+    env = "FORWARD_UNKNOWN_EVENTS",
+    num_args = 0..=1,
+    require_equals = true,
+    default_missing_value = "true",
+    value_parser = BoolishValueParser::new(),
+    help = "Route and forward event types the proxy has no payload type for, \
+            instead of rejecting them"
+  )]
+  pub forward_unknown_events: Option<bool>,
+
+  #[clap(
+    long = "validation",
+    env = "VALIDATION",
+    help = "How much of a payload must match the expected schema (strict, \
+            lenient, off) [default: strict]"
+  )]
+  pub validation: Option<String>,
+
+  #[clap(
+    long = "reject-invalid-payloads",
+    env = "REJECT_INVALID_PAYLOADS",
+    num_args = 0..=1,
+    require_equals = true,
+    default_missing_value = "true",
+    value_parser = BoolishValueParser::new(),
+    help = "With --validation strict, reject payloads that do not match the \
+            schema instead of forwarding them"
+  )]
+  pub reject_invalid_payloads: Option<bool>,
+
+  #[clap(
+    long = "schema-drift-file",
+    env = "SCHEMA_DRIFT_FILE",
+    help = "File to keep the payload schema mismatches found by strict \
+            validation in, for the schema-drift command"
+  )]
+  pub schema_drift_file: Option<PathBuf>,
+
+  #[clap(
+    long = "skip-draft-pull-requests",
+    env = "SKIP_DRAFT_PULL_REQUESTS",
+    num_args = 0..=1,
+    require_equals = true,
+    default_missing_value = "true",
+    value_parser = BoolishValueParser::new(),
+    help = "Do not forward events about draft pull requests"
+  )]
+  pub skip_draft_pull_requests: Option<bool>,
+
+  #[clap(
+    long = "skip-bot-senders",
+    env = "SKIP_BOT_SENDERS",
+    num_args = 0..=1,
+    require_equals = true,
+    default_missing_value = "true",
+    value_parser = BoolishValueParser::new(),
+    help = "Do not forward events sent by bot accounts, such as Dependabot"
+  )]
+  pub skip_bot_senders: Option<bool>,
+
+  #[clap(
+    long = "honor-skip-ci",
+    env = "HONOR_SKIP_CI",
+    num_args = 0..=1,
+    require_equals = true,
+    default_missing_value = "true",
+    value_parser = BoolishValueParser::new(),
+    help = "Do not forward pushes where any commit message contains \
+            [skip ci], [ci skip] or [no ci]"
+  )]
+  pub honor_skip_ci: Option<bool>,
+
+  #[clap(
+    long = "include-path",
+    env = "INCLUDE_PATHS",
+    value_delimiter = ',',
+    help = "Only forward pushes that change a path matching this glob. May \
+            be repeated (or comma separated)"
+  )]
+  pub include_path: Vec<String>,
+
+  #[clap(
+    long = "exclude-path",
+    env = "EXCLUDE_PATHS",
+    value_delimiter = ',',
+    help = "Do not forward pushes that only change paths matching this \
+            glob. May be repeated (or comma separated)"
+  )]
+  pub exclude_path: Vec<String>,
+
+  #[clap(
+    long = "queue-dir",
+    env = "QUEUE_DIR",
+    help = "Directory for a durable delivery queue. When set, verified \
+            webhooks are acknowledged right away and delivered to Jenkins in \
+            the background, with retries"
+  )]
+  pub queue_dir: Option<PathBuf>,
+
+  #[clap(
+    long = "queue-max-age",
+    env = "QUEUE_MAX_AGE",
+    help = "Seconds to keep retrying a queued delivery before moving it to \
+            the dead letters [default: 86400]"
+  )]
+  pub queue_max_age: Option<u64>,
+
+  #[clap(
+    long = "admin-token-file",
+    env = "ADMIN_TOKEN_FILE",
+    help = "File containing the bearer token for the /admin endpoints. The \
+            endpoints are disabled without it"
+  )]
+  pub admin_token_file: Option<PathBuf>,
+
+  #[clap(
+    long = "dedup-ttl",
+    env = "DEDUP_TTL",
+    help = "Seconds to remember X-GitHub-Delivery IDs, so a redelivery does \
+            not reach Jenkins twice, 0 to disable [default: 86400]"
+  )]
+  pub dedup_ttl: Option<u64>,
+
+  #[clap(
+    long = "dedup-capacity",
+    env = "DEDUP_CAPACITY",
+    help = "Most delivery IDs to remember [default: 10000]"
+  )]
+  pub dedup_capacity: Option<usize>,
+
+  #[clap(
+    long = "dedup-file",
+    env = "DEDUP_FILE",
+    help = "File to persist remembered delivery IDs in across restarts"
+  )]
+  pub dedup_file: Option<PathBuf>,
+
+  #[clap(
+    long = "readiness-timeout",
+    env = "READINESS_TIMEOUT",
+    help = "Seconds to wait for each Jenkins target to answer a /readyz \
+            probe [default: 5]"
+  )]
+  pub readiness_timeout: Option<u64>,
+
+  #[clap(
+    long = "readiness-cache",
+    env = "READINESS_CACHE",
+    help = "Seconds to reuse the result of a /readyz probe [default: 10]"
+  )]
+  pub readiness_cache: Option<u64>,
+
+  #[clap(
+    long = "reload-interval",
+    env = "RELOAD_INTERVAL",
+    help = "Seconds between checks of the config and secret files for \
+            changes, 0 to only reload on SIGHUP [default: 5]"
+  )]
+  pub reload_interval: Option<u64>,
 
   #[clap(
     short = 'H',
     long = "host",
     env = "HOST",
-    default_value = "0.0.0.0",
-    help = "Host address to bind the server to"
+    help = "Host address to bind the server to [default: 0.0.0.0]"
   )]
-  pub host: String,
+  pub host: Option<String>,
 
   #[clap(
     short = 'p',
     long = "port",
     env = "PORT",
-    default_value = "8080",
-    help = "Port to bind the server to"
+    help = "Port to bind the server to [default: 8080]"
+  )]
+  pub port: Option<u16>,
+
+  #[clap(
+    long = "tls-cert",
+    env = "TLS_CERT",
+    requires = "tls_key",
+    help = "PEM certificate (chain) to serve HTTPS with. Reloaded when the \
+            file changes"
+  )]
+  pub tls_cert: Option<PathBuf>,
+
+  #[clap(
+    long = "tls-key",
+    env = "TLS_KEY",
+    requires = "tls_cert",
+    help = "PEM private key for --tls-cert"
+  )]
+  pub tls_key: Option<PathBuf>,
+
+  #[clap(
+    long = "https-port",
+    env = "HTTPS_PORT",
+    help = "Serve HTTPS on this port and keep plain HTTP on --port. Without \
+            it, --port itself serves HTTPS when --tls-cert is set"
+  )]
+  pub https_port: Option<u16>,
+
+  #[clap(
+    long = "metrics-host",
+    env = "METRICS_HOST",
+    help = "Host address for a separate /metrics listener [default: the \
+            --host address]"
+  )]
+  pub metrics_host: Option<String>,
+
+  #[clap(
+    long = "metrics-port",
+    env = "METRICS_PORT",
+    help = "Serve /metrics on this port instead of alongside the webhook \
+            endpoint"
+  )]
+  pub metrics_port: Option<u16>,
+
+  #[clap(
+    long = "otlp-endpoint",
+    env = "OTLP_ENDPOINT",
+    help = "Base URL of an OTLP/HTTP collector, such as \
+            http://localhost:4318, to export a trace of every webhook to"
   )]
-  pub port: u16,
+  pub otlp_endpoint: Option<String>,
+
+  #[clap(
+    long = "otel-service-name",
+    env = "OTEL_SERVICE_NAME",
+    help = "Service name reported with exported traces \
+            [default: github-to-jenkins-webhook]"
+  )]
+  pub otel_service_name: Option<String>,
 
   #[clap(
     short = 'l',
     long = "log-level",
     env = "LOG_LEVEL",
-    default_value = "info",
-    help = "Log level (trace, debug, info, warn, error)"
+    help = "Log level (trace, debug, info, warn, error) [default: info]"
+  )]
+  pub log_level: Option<String>,
+
+  #[clap(
+    long = "log-format",
+    env = "LOG_FORMAT",
+    help = "Log format (full, compact, pretty, json) [default: full]"
   )]
-  pub log_level: String,
+  pub log_format: Option<String>,
+
+  #[clap(subcommand)]
+  pub command: Option<Command>,
+
+  #[clap(skip)]
+  pub targets: BTreeMap<String, TargetConfig>,
+
+  #[clap(skip)]
+  pub routes: Option<RoutesConfig>,
+}
+
+/**
+ * Without a command, the proxy runs.
+ */
+#[derive(Subcommand, Clone, Debug)]
+pub enum Command {
+  /// Print the payload schema mismatches recorded in --schema-drift-file
+  SchemaDrift,
 }
 
 impl Args {
-  pub fn get_github_secret(&self) -> Result<String, String> {
+  /**
+   * Fills in anything the command line and environment left unset from the
+   * `--config` file, if there is one.  Secret sources are taken as a whole:
+   * if any are given on the command line, none are read from the file.
+   */
+  pub fn merge_config_file(mut self) -> Result<Args, String> {
+    let Some(path) = &self.config else {
+      return Ok(self);
+    };
+    let file = FileConfig::load(path)?;
+
+    if self.github_secret.is_none()
+      && self.github_secret_file.is_empty()
+      && self.github_secret_dir.is_none()
+    {
+      self.github_secret = file.github_secret;
+      self.github_secret_file = file.github_secret_file;
+      self.github_secret_dir = file.github_secret_dir;
+    }
+    self.repository_secret_file =
+      merge_keyed(file.repository_secret_file, self.repository_secret_file);
+    self.organization_secret_file =
+      merge_keyed(file.organization_secret_file, self.organization_secret_file);
+    self.allow_legacy_sha1_signature = self
+      .allow_legacy_sha1_signature
+      .or(file.allow_legacy_sha1_signature);
+    if self.jenkins_url.is_empty() {
+      self.jenkins_url = file.jenkins_url;
+    }
+    // The user and token only make sense as a pair.
+    if self.jenkins_user.is_none() {
+      self.jenkins_user = file.jenkins_user;
+      self.jenkins_api_token_file = file.jenkins_api_token_file;
+    }
+    self.fan_out_mode = self.fan_out_mode.or(file.fan_out_mode);
+    self.fan_out_quorum = self.fan_out_quorum.or(file.fan_out_quorum);
+    self.jenkins_connect_timeout = self
+      .jenkins_connect_timeout
+      .or(file.jenkins_connect_timeout);
+    self.jenkins_read_timeout =
+      self.jenkins_read_timeout.or(file.jenkins_read_timeout);
+    self.jenkins_pool_size = self.jenkins_pool_size.or(file.jenkins_pool_size);
+    self.jenkins_http_version =
+      self.jenkins_http_version.or(file.jenkins_http_version);
+    self.jenkins_proxy = self.jenkins_proxy.or(file.jenkins_proxy);
+    self.jenkins_ca_bundle = self.jenkins_ca_bundle.or(file.jenkins_ca_bundle);
+    // The certificate and key only make sense as a pair.
+    if self.jenkins_client_cert.is_none() {
+      self.jenkins_client_cert = file.jenkins_client_cert;
+      self.jenkins_client_key = file.jenkins_client_key;
+    }
+    self.jenkins_insecure_skip_verify = self
+      .jenkins_insecure_skip_verify
+      .or(file.jenkins_insecure_skip_verify);
+    self.routes_file = self.routes_file.or(file.routes_file);
+    self.forward_unknown_events =
+      self.forward_unknown_events.or(file.forward_unknown_events);
+    self.validation = self.validation.or(file.validation);
+    self.reject_invalid_payloads = self
+      .reject_invalid_payloads
+      .or(file.reject_invalid_payloads);
+    self.schema_drift_file = self.schema_drift_file.or(file.schema_drift_file);
+    self.skip_draft_pull_requests = self
+      .skip_draft_pull_requests
+      .or(file.skip_draft_pull_requests);
+    self.skip_bot_senders = self.skip_bot_senders.or(file.skip_bot_senders);
+    self.honor_skip_ci = self.honor_skip_ci.or(file.honor_skip_ci);
+    if self.include_path.is_empty() {
+      self.include_path = file.include_path;
+    }
+    if self.exclude_path.is_empty() {
+      self.exclude_path = file.exclude_path;
+    }
+    self.reload_interval = self.reload_interval.or(file.reload_interval);
+    self.queue_dir = self.queue_dir.or(file.queue_dir);
+    self.queue_max_age = self.queue_max_age.or(file.queue_max_age);
+    self.admin_token_file = self.admin_token_file.or(file.admin_token_file);
+    self.dedup_ttl = self.dedup_ttl.or(file.dedup_ttl);
+    self.dedup_capacity = self.dedup_capacity.or(file.dedup_capacity);
+    self.dedup_file = self.dedup_file.or(file.dedup_file);
+    self.readiness_timeout = self.readiness_timeout.or(file.readiness_timeout);
+    self.readiness_cache = self.readiness_cache.or(file.readiness_cache);
+    self.host = self.host.or(file.host);
+    self.port = self.port.or(file.port);
+    if self.tls_cert.is_none() {
+      self.tls_cert = file.tls_cert;
+      self.tls_key = file.tls_key;
+    }
+    self.https_port = self.https_port.or(file.https_port);
+    self.metrics_host = self.metrics_host.or(file.metrics_host);
+    self.metrics_port = self.metrics_port.or(file.metrics_port);
+    self.otlp_endpoint = self.otlp_endpoint.or(file.otlp_endpoint);
+    self.otel_service_name = self.otel_service_name.or(file.otel_service_name);
+    self.log_level = self.log_level.or(file.log_level);
+    self.log_format = self.log_format.or(file.log_format);
+    self.targets = file.targets;
+    self.routes = file.routes;
+    Ok(self)
+  }
+
+  pub fn get_reload_interval(&self) -> Option<Duration> {
+    match self.reload_interval.unwrap_or(5) {
+      0 => None,
+      secs => Some(Duration::from_secs(secs)),
+    }
+  }
+
+  pub fn get_admin_token(&self) -> Result<Option<String>, String> {
+    self
+      .admin_token_file
+      .as_ref()
+      .map(|path| read_secret_file(path).map(|secret| secret.value))
+      .transpose()
+  }
+
+  pub fn get_queue_max_age(&self) -> Duration {
+    Duration::from_secs(self.queue_max_age.unwrap_or(24 * 60 * 60))
+  }
+
+  pub fn get_dedup_ttl(&self) -> Option<Duration> {
+    match self.dedup_ttl.unwrap_or(24 * 60 * 60) {
+      0 => None,
+      secs => Some(Duration::from_secs(secs)),
+    }
+  }
+
+  pub fn get_dedup_capacity(&self) -> usize {
+    self.dedup_capacity.unwrap_or(10_000)
+  }
+
+  pub fn get_readiness_timeout(&self) -> Duration {
+    Duration::from_secs(self.readiness_timeout.unwrap_or(5))
+  }
+
+  pub fn get_readiness_cache(&self) -> Duration {
+    Duration::from_secs(self.readiness_cache.unwrap_or(10))
+  }
+
+  pub fn get_otel_service_name(&self) -> String {
+    self
+      .otel_service_name
+      .clone()
+      .unwrap_or_else(|| env!("CARGO_PKG_NAME").to_string())
+  }
+
+  /** The address of a separate HTTPS listener, if there is one. */
+  pub fn https_bind_address(&self) -> Option<String> {
+    self.https_port.map(|port| {
+      format!("{}:{}", self.host.as_deref().unwrap_or("0.0.0.0"), port)
+    })
+  }
+
+  /** The address of the separate metrics listener, if there is one. */
+  pub fn metrics_bind_address(&self) -> Option<String> {
+    self.metrics_port.map(|port| {
+      format!(
+        "{}:{}",
+        self
+          .metrics_host
+          .as_deref()
+          .or(self.host.as_deref())
+          .unwrap_or("0.0.0.0"),
+        port,
+      )
+    })
+  }
+
+  pub fn bind_address(&self) -> String {
+    format!(
+      "{}:{}",
+      self.host.as_deref().unwrap_or("0.0.0.0"),
+      self.port.unwrap_or(8080),
+    )
+  }
+
+  pub fn get_github_secrets(&self) -> Result<Vec<WebhookSecret>, String> {
     if let Some(secret) = &self.github_secret {
-      Ok(secret.clone())
-    } else if let Some(path) = &self.github_secret_file {
-      fs::read_to_string(path)
-        .map(|s| s.trim().to_string())
-        .map_err(|e| {
+      return Ok(vec![WebhookSecret {
+        key_id: "github-secret".to_string(),
+        value: secret.clone(),
+      }]);
+    }
+    let mut secrets = self
+      .github_secret_file
+      .iter()
+      .map(|path| read_secret_file(path))
+      .collect::<Result<Vec<_>, _>>()?;
+    if let Some(dir) = &self.github_secret_dir {
+      secrets.extend(read_secret_dir(dir)?);
+    }
+    Ok(secrets)
+  }
+
+  pub fn get_secret_store(&self) -> Result<SecretStore, String> {
+    let mut store = SecretStore {
+      default: self.get_github_secrets()?,
+      ..Default::default()
+    };
+    for (name, path) in &self.repository_secret_file {
+      store.add_repository_secret(name, read_secret_file(path)?);
+    }
+    for (name, path) in &self.organization_secret_file {
+      store.add_organization_secret(name, read_secret_file(path)?);
+    }
+    if store.is_empty() {
+      Err(
+        "At least one secret must be provided via --github-secret, \
+         --github-secret-file, --github-secret-dir, --repository-secret-file \
+         or --organization-secret-file"
+          .to_string(),
+      )
+    } else {
+      Ok(store)
+    }
+  }
+
+  pub fn get_jenkins_targets(&self) -> Result<Vec<JenkinsTarget>, String> {
+    let auth = jenkins_auth(
+      "jenkins_user and jenkins_api_token_file",
+      self.jenkins_user.as_deref(),
+      self.jenkins_api_token_file.as_deref(),
+    )?;
+    self
+      .jenkins_url
+      .iter()
+      .map(|url| {
+        JenkinsTarget::from_base_url(url)
+          .map(|target| target.with_auth(auth.clone(), None))
+          .map_err(|e| format!("Jenkins URL '{}': {}", url, e))
+      })
+      .collect()
+  }
+
+  /**
+   * The one client every request to Jenkins goes through, so that connections
+   * (and TLS sessions) are reused across webhooks.
+   */
+  pub fn get_jenkins_client(&self) -> Result<reqwest::Client, String> {
+    let mut builder = reqwest::Client::builder()
+      .connect_timeout(Duration::from_secs(
+        self.jenkins_connect_timeout.unwrap_or(10),
+      ))
+      .read_timeout(Duration::from_secs(
+        self.jenkins_read_timeout.unwrap_or(30),
+      ))
+      .pool_max_idle_per_host(self.jenkins_pool_size.unwrap_or(32));
+    let http_version = self.jenkins_http_version.as_deref().unwrap_or("auto");
+    builder = match http_version.to_lowercase().as_str() {
+      "auto" => builder,
+      "http1" => builder.http1_only(),
+      "http2" => builder.http2_prior_knowledge(),
+      _ => {
+        return Err(format!(
+          "Invalid Jenkins HTTP version '{}'. Valid options are: auto, http1, \
+           http2",
+          http_version
+        ))
+      }
+    };
+    if let Some(path) = &self.jenkins_ca_bundle {
+      let pem = std::fs::read(path).map_err(|e| {
+        format!("Failed to read CA bundle '{}': {}", path.display(), e)
+      })?;
+      let certs = reqwest::Certificate::from_pem_bundle(&pem).map_err(|e| {
+        format!("Invalid CA bundle '{}': {}", path.display(), e)
+      })?;
+      if certs.is_empty() {
+        return Err(format!(
+          "CA bundle '{}' contains no certificates",
+          path.display()
+        ));
+      }
+      builder = builder.tls_certs_merge(certs);
+    }
+    match (&self.jenkins_client_cert, &self.jenkins_client_key) {
+      (Some(cert), Some(key)) => {
+        let mut pem = std::fs::read(cert).map_err(|e| {
+          format!(
+            "Failed to read client certificate '{}': {}",
+            cert.display(),
+            e
+          )
+        })?;
+        pem.push(b'\n');
+        pem.extend(std::fs::read(key).map_err(|e| {
+          format!("Failed to read client key '{}': {}", key.display(), e)
+        })?);
+        let identity = reqwest::Identity::from_pem(&pem).map_err(|e| {
           format!(
-            "Failed to read GitHub secret from file '{}': {}",
-            path.display(),
+            "Invalid client certificate '{}' or key '{}': {}",
+            cert.display(),
+            key.display(),
             e,
           )
-        })
-    } else {
-      Err(
-        "Either --github-secret or --github-secret-file must be provided"
+        })?;
+        builder = builder.identity(identity);
+      }
+      (None, None) => {}
+      _ => {
+        return Err(
+          "jenkins_client_cert and jenkins_client_key must be given together"
+            .to_string(),
+        )
+      }
+    }
+    if self.jenkins_insecure_skip_verify.unwrap_or(false) {
+      builder = builder.tls_danger_accept_invalid_certs(true);
+    }
+    builder = match self.jenkins_proxy.as_deref() {
+      None => builder,
+      Some("none") => builder.no_proxy(),
+      Some(url) => builder.proxy(
+        reqwest::Proxy::all(url)
+          .map_err(|e| format!("Invalid Jenkins proxy '{}': {}", url, e))?,
+      ),
+    };
+    builder
+      .build()
+      .map_err(|e| format!("Failed to build the Jenkins HTTP client: {}", e))
+  }
+
+  pub fn get_named_targets(
+    &self,
+  ) -> Result<BTreeMap<String, JenkinsTarget>, String> {
+    self
+      .targets
+      .iter()
+      .map(|(name, target)| {
+        let auth = jenkins_auth(
+          "user and api_token_file",
+          target.user.as_deref(),
+          target.api_token_file.as_deref(),
+        )
+        .map_err(|e| format!("Target '{}': {}", name, e))?;
+        let mode = target_mode(target.mode.as_deref())
+          .map_err(|e| format!("Target '{}': {}", name, e))?;
+        let endpoint = match mode {
+          TargetMode::GitHubWebhook => Endpoint::GitHubWebhook,
+          TargetMode::GenericWebhookTrigger => {
+            Endpoint::GenericWebhookTrigger {
+              token: None,
+              flatten: false,
+            }
+          }
+          TargetMode::BuildWithParameters => Endpoint::BuildWithParameters {
+            parameters: BTreeMap::new(),
+          },
+        };
+        JenkinsTarget::for_endpoint(name, &target.url, endpoint)
+          .map(|t| (name.clone(), t.with_auth(auth, target.crumb)))
+          .map_err(|e| format!("Target '{}': {}", name, e))
+      })
+      .collect()
+  }
+
+  /**
+   * A `--routes-file` wins over routes in the config file.  Without either,
+   * everything is forwarded to the `--jenkins-url` targets.
+   */
+  pub fn get_route_table(
+    &self,
+    default_targets: &[JenkinsTarget],
+  ) -> Result<RouteTable, String> {
+    let named_targets = self.get_named_targets()?;
+    match (&self.routes_file, &self.routes) {
+      (Some(path), _) => {
+        RouteTable::load(path, default_targets, &named_targets)
+      }
+      (None, Some(routes)) => {
+        RouteTable::from_config(routes, default_targets, &named_targets)
+      }
+      (None, None) if default_targets.is_empty() => Err(
+        "At least one Jenkins URL must be provided via --jenkins-url, or \
+         routes must say where events go"
           .to_string(),
-      )
+      ),
+      (None, None) => Ok(RouteTable::forward_all(default_targets.to_vec())),
+    }
+  }
+
+  pub fn get_event_filter(&self) -> Result<EventFilter, String> {
+    EventFilter::new(
+      self.skip_draft_pull_requests.unwrap_or(false),
+      self.skip_bot_senders.unwrap_or(false),
+      self.honor_skip_ci.unwrap_or(false),
+      &self.include_path,
+      &self.exclude_path,
+    )
+  }
+
+  pub fn get_fan_out_mode(&self) -> Result<FanOutMode, String> {
+    let targets = self.jenkins_url.len();
+    let fan_out_mode = self.fan_out_mode.as_deref().unwrap_or("all");
+    match fan_out_mode.to_lowercase().as_str() {
+      "first-success" => Ok(FanOutMode::FirstSuccess),
+      "all" => Ok(FanOutMode::All),
+      "quorum" => match self.fan_out_quorum.unwrap_or(targets / 2 + 1) {
+        n if n == 0 || (targets > 0 && n > targets) => Err(format!(
+          "Fan-out quorum must be between 1 and the number of Jenkins URLs \
+           ({}), got {}",
+          targets, n,
+        )),
+        n => Ok(FanOutMode::Quorum(n)),
+      },
+      _ => Err(format!(
+        "Invalid fan-out mode '{}'. Valid options are: first-success, all, \
+         quorum",
+        fan_out_mode
+      )),
     }
   }
 
   pub fn get_log_level(&self) -> Result<Level, String> {
-    match self.log_level.to_lowercase().as_str() {
+    let log_level = self.log_level.as_deref().unwrap_or("info");
+    match log_level.to_lowercase().as_str() {
       "trace" => Ok(Level::TRACE),
       "debug" => Ok(Level::DEBUG),
       "info" => Ok(Level::INFO),
@@ -93,8 +906,256 @@ impl Args {
       "error" => Ok(Level::ERROR),
       _ => Err(format!(
         "Invalid log level '{}'. Valid options are: trace, debug, info, warn, error",
-        self.log_level
+        log_level
+      )),
+    }
+  }
+
+  pub fn get_validation(&self) -> Result<Validation, String> {
+    let validation = self.validation.as_deref().unwrap_or("strict");
+    match validation.to_lowercase().as_str() {
+      "strict" => Ok(Validation::Strict),
+      "lenient" => Ok(Validation::Lenient),
+      "off" => Ok(Validation::Off),
+      _ => Err(format!(
+        "Invalid validation mode '{}'. Valid options are: strict, lenient, off",
+        validation
+      )),
+    }
+  }
+
+  pub fn get_log_format(&self) -> Result<LogFormat, String> {
+    let log_format = self.log_format.as_deref().unwrap_or("full");
+    match log_format.to_lowercase().as_str() {
+      "full" => Ok(LogFormat::Full),
+      "compact" => Ok(LogFormat::Compact),
+      "pretty" => Ok(LogFormat::Pretty),
+      "json" => Ok(LogFormat::Json),
+      _ => Err(format!(
+        "Invalid log format '{}'. Valid options are: full, compact, pretty, \
+         json",
+        log_format
       )),
     }
   }
 }
+
+fn target_mode(mode: Option<&str>) -> Result<TargetMode, String> {
+  let mode = mode.unwrap_or("github-webhook");
+  match mode.to_lowercase().as_str() {
+    "github-webhook" => Ok(TargetMode::GitHubWebhook),
+    "generic-webhook-trigger" => Ok(TargetMode::GenericWebhookTrigger),
+    "build-with-parameters" => Ok(TargetMode::BuildWithParameters),
+    _ => Err(format!(
+      "Invalid target mode '{}'. Valid options are: github-webhook, \
+       generic-webhook-trigger, build-with-parameters",
+      mode
+    )),
+  }
+}
+
+/**
+ * Combines keyed secret files from the config file with those from the
+ * command line.  A key given on the command line replaces the file's entry
+ * for it entirely.
+ */
+fn merge_keyed(
+  file: BTreeMap<String, Vec<PathBuf>>,
+  cli: Vec<(String, PathBuf)>,
+) -> Vec<(String, PathBuf)> {
+  let mut merged: Vec<(String, PathBuf)> = file
+    .into_iter()
+    .filter(|(key, _)| !cli.iter().any(|(k, _)| k == key))
+    .flat_map(|(key, paths)| paths.into_iter().map(move |p| (key.clone(), p)))
+    .collect();
+  merged.extend(cli);
+  merged
+}
+
+fn parse_key_path(s: &str) -> Result<(String, PathBuf), String> {
+  match s.split_once('=') {
+    Some((key, path)) if !key.is_empty() && !path.is_empty() => {
+      Ok((key.to_string(), PathBuf::from(path)))
+    }
+    _ => Err(format!("Expected KEY=PATH, got '{}'", s)),
+  }
+}
+
+fn jenkins_auth(
+  keys: &str,
+  user: Option<&str>,
+  token_file: Option<&Path>,
+) -> Result<Option<JenkinsAuth>, String> {
+  match (user, token_file) {
+    (Some(user), Some(token_file)) => {
+      JenkinsAuth::load(user, token_file).map(Some)
+    }
+    (None, None) => Ok(None),
+    _ => Err(format!("{} must be given together", keys)),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::test_support::TempDir;
+  use clap::CommandFactory;
+  use std::ffi::OsStr;
+
+  fn merged(dir: &TempDir, file: &str, flags: &[&str]) -> Args {
+    let config = dir.write("config.toml", file);
+    let config = config.to_str().unwrap();
+    let args = ["github-to-jenkins-webhook", "--config", config];
+    Args::try_parse_from(args.iter().chain(flags))
+      .unwrap()
+      .merge_config_file()
+      .unwrap()
+  }
+
+  #[test]
+  fn command_line_wins_over_the_file() {
+    let dir = TempDir::new();
+    let args = merged(
+      &dir,
+      r#"
+      jenkins_url = ["https://file.example.com"]
+      port = 9000
+      log_level = "debug"
+      honor_skip_ci = true
+      skip_draft_pull_requests = false
+      "#,
+      &[
+        "--port",
+        "8081",
+        "--honor-skip-ci=false",
+        "--skip-draft-pull-requests",
+      ],
+    );
+    assert_eq!(args.port, Some(8081));
+    assert_eq!(args.log_level.as_deref(), Some("debug"));
+    assert_eq!(args.jenkins_url, ["https://file.example.com"]);
+    assert_eq!(args.honor_skip_ci, Some(false));
+    assert_eq!(args.skip_draft_pull_requests, Some(true));
+  }
+
+  #[test]
+  fn environment_wins_over_the_file() {
+    // clap reads SKIP_BOT_SENDERS into the same field as the flag, so the
+    // environment is merged like the command line.  The process environment
+    // is shared with the tests running alongside, so the value is set as
+    // clap would have set it instead of through the variable.
+    let command = Args::command();
+    let flag = command
+      .get_arguments()
+      .find(|arg| arg.get_id() == "skip_bot_senders")
+      .unwrap();
+    assert_eq!(flag.get_env(), Some(OsStr::new("SKIP_BOT_SENDERS")));
+
+    let dir = TempDir::new();
+    let config = dir.write("config.toml", "skip_bot_senders = true");
+    let mut args = Args::try_parse_from([
+      "github-to-jenkins-webhook",
+      "--config",
+      config.to_str().unwrap(),
+    ])
+    .unwrap();
+    args.skip_bot_senders = Some(false);
+    let args = args.merge_config_file().unwrap();
+    assert_eq!(args.skip_bot_senders, Some(false));
+
+    let args = merged(&dir, "skip_bot_senders = true", &[]);
+    assert_eq!(args.skip_bot_senders, Some(true));
+  }
+
+  #[test]
+  fn unset_switches_stay_unset() {
+    let dir = TempDir::new();
+    let args = merged(&dir, "", &[]);
+    assert_eq!(args.reject_invalid_payloads, None);
+    assert_eq!(args.jenkins_insecure_skip_verify, None);
+  }
+
+  #[test]
+  fn secret_sources_are_taken_as_a_whole() {
+    let dir = TempDir::new();
+    let args = merged(
+      &dir,
+      r#"
+      github_secret_dir = "/run/secrets/github"
+      "#,
+      &["--github-secret-file", "/run/secrets/webhook"],
+    );
+    assert_eq!(args.github_secret_dir, None);
+    assert_eq!(
+      args.github_secret_file,
+      [PathBuf::from("/run/secrets/webhook")]
+    );
+  }
+
+  #[test]
+  fn keyed_secret_files_are_replaced_per_key() {
+    let dir = TempDir::new();
+    let args = merged(
+      &dir,
+      r#"
+      [repository_secret_file]
+      "octo-org/backend" = ["/file/backend-old", "/file/backend-new"]
+      "octo-org/frontend" = ["/file/frontend"]
+      "#,
+      &["--repository-secret-file", "octo-org/backend=/cli/backend"],
+    );
+    assert_eq!(
+      args.repository_secret_file,
+      [
+        (
+          "octo-org/frontend".to_string(),
+          PathBuf::from("/file/frontend")
+        ),
+        (
+          "octo-org/backend".to_string(),
+          PathBuf::from("/cli/backend")
+        ),
+      ]
+    );
+  }
+
+  #[test]
+  fn every_target_mode_picks_its_endpoint() {
+    let dir = TempDir::new();
+    let args = merged(
+      &dir,
+      r#"
+      [targets.plain]
+      url = "https://ci.example.com/"
+      [targets.webhook]
+      url = "https://ci.example.com/"
+      mode = "github-webhook"
+      [targets.generic]
+      url = "https://ci.example.com/"
+      mode = "Generic-Webhook-Trigger"
+      [targets.build]
+      url = "https://ci.example.com/"
+      mode = "build-with-parameters"
+      "#,
+      &[],
+    );
+    let targets = args.get_named_targets().unwrap();
+    let endpoint = |name: &str| targets[name].endpoint.clone();
+    assert!(matches!(endpoint("plain"), Endpoint::GitHubWebhook));
+    assert!(matches!(endpoint("webhook"), Endpoint::GitHubWebhook));
+    assert!(matches!(
+      endpoint("generic"),
+      Endpoint::GenericWebhookTrigger { token: None, .. },
+    ));
+    assert!(matches!(
+      endpoint("build"),
+      Endpoint::BuildWithParameters { .. },
+    ));
+  }
+
+  #[test]
+  fn unknown_target_modes_are_rejected() {
+    assert_eq!(target_mode(None), Ok(TargetMode::GitHubWebhook));
+    assert!(target_mode(Some("ghprb")).is_err());
+  }
+}
@@ -0,0 +1,197 @@
+use serde::de::{DeserializeOwned, Deserializer, Error as _, Unexpected};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::routing::RoutesConfig;
+
+/**
+ * The `--config` file.  Top-level keys mirror the command line flags (with
+ * underscores instead of dashes), and anything set on the command line or in
+ * the environment takes precedence over the file.  The structured sections
+ * have no flag equivalent.
+ */
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FileConfig {
+  pub github_secret: Option<String>,
+  #[serde(default)]
+  pub github_secret_file: Vec<PathBuf>,
+  pub github_secret_dir: Option<PathBuf>,
+  pub allow_legacy_sha1_signature: Option<bool>,
+  #[serde(default)]
+  pub jenkins_url: Vec<String>,
+  pub jenkins_user: Option<String>,
+  pub jenkins_api_token_file: Option<PathBuf>,
+  #[serde(default, deserialize_with = "fan_out_mode")]
+  pub fan_out_mode: Option<String>,
+  pub fan_out_quorum: Option<usize>,
+  pub jenkins_connect_timeout: Option<u64>,
+  pub jenkins_read_timeout: Option<u64>,
+  pub jenkins_pool_size: Option<usize>,
+  #[serde(default, deserialize_with = "jenkins_http_version")]
+  pub jenkins_http_version: Option<String>,
+  pub jenkins_proxy: Option<String>,
+  pub jenkins_ca_bundle: Option<PathBuf>,
+  pub jenkins_client_cert: Option<PathBuf>,
+  pub jenkins_client_key: Option<PathBuf>,
+  pub jenkins_insecure_skip_verify: Option<bool>,
+  pub routes_file: Option<PathBuf>,
+  pub forward_unknown_events: Option<bool>,
+  #[serde(default, deserialize_with = "validation")]
+  pub validation: Option<String>,
+  pub reject_invalid_payloads: Option<bool>,
+  pub schema_drift_file: Option<PathBuf>,
+  pub skip_draft_pull_requests: Option<bool>,
+  pub skip_bot_senders: Option<bool>,
+  pub honor_skip_ci: Option<bool>,
+  #[serde(default)]
+  pub include_path: Vec<String>,
+  #[serde(default)]
+  pub exclude_path: Vec<String>,
+  pub reload_interval: Option<u64>,
+  pub queue_dir: Option<PathBuf>,
+  pub queue_max_age: Option<u64>,
+  pub admin_token_file: Option<PathBuf>,
+  pub dedup_ttl: Option<u64>,
+  pub dedup_capacity: Option<usize>,
+  pub dedup_file: Option<PathBuf>,
+  pub readiness_timeout: Option<u64>,
+  pub readiness_cache: Option<u64>,
+  pub host: Option<String>,
+  pub port: Option<u16>,
+  pub tls_cert: Option<PathBuf>,
+  pub tls_key: Option<PathBuf>,
+  pub https_port: Option<u16>,
+  pub metrics_host: Option<String>,
+  pub metrics_port: Option<u16>,
+  pub otlp_endpoint: Option<String>,
+  pub otel_service_name: Option<String>,
+  #[serde(default, deserialize_with = "log_level")]
+  pub log_level: Option<String>,
+  #[serde(default, deserialize_with = "log_format")]
+  pub log_format: Option<String>,
+
+  /** Secret files keyed by repository `full_name`. */
+  #[serde(default)]
+  pub repository_secret_file: BTreeMap<String, Vec<PathBuf>>,
+  /** Secret files keyed by organization login. */
+  #[serde(default)]
+  pub organization_secret_file: BTreeMap<String, Vec<PathBuf>>,
+  /** Named Jenkins targets that routes can refer to. */
+  #[serde(default)]
+  pub targets: BTreeMap<String, TargetConfig>,
+  /** Inline routing table, used when no `routes_file` is given. */
+  pub routes: Option<RoutesConfig>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TargetConfig {
+  pub url: String,
+  /**
+   * `github-webhook` (the default), `generic-webhook-trigger` or
+   * `build-with-parameters`, the Jenkins endpoint to post to.
+   */
+  #[serde(default, deserialize_with = "target_mode")]
+  pub mode: Option<String>,
+  pub user: Option<String>,
+  pub api_token_file: Option<PathBuf>,
+  /** Whether POSTs carry a CSRF crumb.  Defaults to whether `user` is set. */
+  pub crumb: Option<bool>,
+}
+
+/**
+ * Reads a TOML file, reporting the path of the offending key on failure the
+ * same way `from_slice_with_path` does for webhook payloads.
+ */
+pub fn from_toml_file_with_path<T: DeserializeOwned>(
+  path: &Path,
+  description: &str,
+) -> Result<T, String> {
+  let contents = fs::read_to_string(path).map_err(|e| {
+    format!("Failed to read {} '{}': {}", description, path.display(), e)
+  })?;
+  let de = toml::Deserializer::parse(&contents).map_err(|e| {
+    format!(
+      "Failed to parse {} '{}': {}",
+      description,
+      path.display(),
+      e
+    )
+  })?;
+  serde_path_to_error::deserialize(de).map_err(|e| {
+    format!(
+      "Invalid {} '{}' at {}: {}",
+      description,
+      path.display(),
+      e.path(),
+      e.inner(),
+    )
+  })
+}
+
+impl FileConfig {
+  pub fn load(path: &Path) -> Result<FileConfig, String> {
+    from_toml_file_with_path(path, "config file")
+  }
+}
+
+fn one_of<'de, D: Deserializer<'de>>(
+  d: D,
+  valid: &'static [&'static str],
+) -> Result<Option<String>, D::Error> {
+  let value = String::deserialize(d)?;
+  if valid.contains(&value.to_lowercase().as_str()) {
+    Ok(Some(value))
+  } else {
+    Err(D::Error::invalid_value(
+      Unexpected::Str(&value),
+      &format!("one of: {}", valid.join(", ")).as_str(),
+    ))
+  }
+}
+
+fn fan_out_mode<'de, D: Deserializer<'de>>(
+  d: D,
+) -> Result<Option<String>, D::Error> {
+  one_of(d, &["first-success", "all", "quorum"])
+}
+
+fn target_mode<'de, D: Deserializer<'de>>(
+  d: D,
+) -> Result<Option<String>, D::Error> {
+  one_of(
+    d,
+    &[
+      "github-webhook",
+      "generic-webhook-trigger",
+      "build-with-parameters",
+    ],
+  )
+}
+
+fn jenkins_http_version<'de, D: Deserializer<'de>>(
+  d: D,
+) -> Result<Option<String>, D::Error> {
+  one_of(d, &["auto", "http1", "http2"])
+}
+
+fn log_level<'de, D: Deserializer<'de>>(
+  d: D,
+) -> Result<Option<String>, D::Error> {
+  one_of(d, &["trace", "debug", "info", "warn", "error"])
+}
+
+fn validation<'de, D: Deserializer<'de>>(
+  d: D,
+) -> Result<Option<String>, D::Error> {
+  one_of(d, &["strict", "lenient", "off"])
+}
+
+fn log_format<'de, D: Deserializer<'de>>(
+  d: D,
+) -> Result<Option<String>, D::Error> {
+  one_of(d, &["full", "compact", "pretty", "json"])
+}
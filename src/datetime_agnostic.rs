@@ -6,6 +6,7 @@ use serde::{
 use std::fmt;
 
 #[derive(Clone, Debug)]
+#[allow(dead_code)]
 pub struct FlexibleDateTime(pub DateTime<Utc>);
 
 impl<'de> Deserialize<'de> for FlexibleDateTime {
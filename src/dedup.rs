@@ -0,0 +1,322 @@
+use actix_web::web;
+use chrono::{DateTime, Utc};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tracing::{info, warn};
+
+/**
+ * Remembers `X-GitHub-Delivery` IDs that have already been handled, so a
+ * redelivery does not start a second Jenkins build.  Entries expire after
+ * `ttl`, and only the most recent `capacity` IDs are kept.  With a `file`, the
+ * IDs are appended to it once they are delivered and read back on startup, so
+ * a restart does not forget them.
+ */
+pub struct Deduplicator {
+  ttl: Duration,
+  capacity: usize,
+  file: Option<PathBuf>,
+  seen: Mutex<Seen>,
+  /** Lines in the file, to know when it is worth compacting. */
+  file_lines: Mutex<usize>,
+}
+
+#[derive(Default)]
+struct Seen {
+  by_id: HashMap<String, DateTime<Utc>>,
+  order: VecDeque<(String, DateTime<Utc>)>,
+  /** IDs whose delivery is still under way, which are not persisted yet. */
+  claimed: HashSet<String>,
+}
+
+/**
+ * A delivery ID taken by one request.  Dropping the claim forgets the ID
+ * again, so that a delivery that failed can be retried; `keep` holds on to it.
+ */
+pub struct Claim {
+  dedup: Arc<Deduplicator>,
+  id: String,
+  at: DateTime<Utc>,
+  kept: bool,
+}
+
+impl Claim {
+  /**
+   * Keeps the ID for good, writing it to the file on the blocking thread
+   * pool.  Persisting is best effort: a failure is logged, and the ID is
+   * still remembered in memory.
+   */
+  pub async fn keep(mut self) {
+    self.kept = true;
+    self.dedup.seen.lock().unwrap().claimed.remove(&self.id);
+    let dedup = self.dedup.clone();
+    let id = self.id.clone();
+    if dedup.file.is_some() {
+      let _ = web::block(move || dedup.persist(&id)).await;
+    }
+  }
+}
+
+impl Drop for Claim {
+  fn drop(&mut self) {
+    if !self.kept {
+      let mut seen = self.dedup.seen.lock().unwrap();
+      seen.claimed.remove(&self.id);
+      seen.remove(&self.id, self.at);
+    }
+  }
+}
+
+impl Seen {
+  fn insert(&mut self, id: String, at: DateTime<Utc>, capacity: usize) {
+    self.by_id.insert(id.clone(), at);
+    self.order.push_back((id, at));
+    while self.order.len() > capacity {
+      self.pop_front();
+    }
+  }
+
+  fn expire(&mut self, cutoff: DateTime<Utc>) {
+    while self.order.front().is_some_and(|(_, at)| *at < cutoff) {
+      self.pop_front();
+    }
+  }
+
+  fn remove(&mut self, id: &str, at: DateTime<Utc>) {
+    if self.by_id.get(id) == Some(&at) {
+      self.by_id.remove(id);
+    }
+    self.order.retain(|entry| entry.0 != id || entry.1 != at);
+  }
+
+  fn pop_front(&mut self) {
+    if let Some((id, at)) = self.order.pop_front() {
+      // An ID recorded again later has a newer entry further back.
+      if self.by_id.get(&id) == Some(&at) {
+        self.by_id.remove(&id);
+      }
+    }
+  }
+}
+
+impl Deduplicator {
+  pub fn new(
+    ttl: Duration,
+    capacity: usize,
+    file: Option<&Path>,
+  ) -> Result<Deduplicator, String> {
+    let dedup = Deduplicator {
+      ttl,
+      capacity,
+      file: file.map(Path::to_path_buf),
+      seen: Mutex::new(Seen::default()),
+      file_lines: Mutex::new(0),
+    };
+    if let Some(path) = file {
+      dedup.load(path).map_err(|e| {
+        format!(
+          "Failed to load delivery IDs from '{}': {}",
+          path.display(),
+          e,
+        )
+      })?;
+    }
+    Ok(dedup)
+  }
+
+  fn cutoff(&self) -> DateTime<Utc> {
+    Utc::now() - chrono::Duration::from_std(self.ttl).unwrap_or_default()
+  }
+
+  fn load(&self, path: &Path) -> io::Result<()> {
+    let contents = match fs::read_to_string(path) {
+      Ok(contents) => contents,
+      Err(e) if e.kind() == io::ErrorKind::NotFound => String::new(),
+      Err(e) => return Err(e),
+    };
+    let mut seen = self.seen.lock().unwrap();
+    for line in contents.lines() {
+      let parsed = line.split_once(' ').and_then(|(at, id)| {
+        DateTime::parse_from_rfc3339(at)
+          .ok()
+          .map(|at| (id.to_string(), at.with_timezone(&Utc)))
+      });
+      match parsed {
+        Some((id, at)) => seen.insert(id, at, self.capacity),
+        None => warn!("Skipping malformed delivery ID line: {}", line),
+      }
+    }
+    seen.expire(self.cutoff());
+    info!("Loaded {} recent delivery IDs", seen.by_id.len());
+    let entries = seen.order.clone();
+    drop(seen);
+    self.compact(&entries, &mut self.file_lines.lock().unwrap())
+  }
+
+  /**
+   * Rewrites the file with only the live entries.
+   */
+  fn compact(
+    &self,
+    entries: &VecDeque<(String, DateTime<Utc>)>,
+    file_lines: &mut usize,
+  ) -> io::Result<()> {
+    let Some(path) = &self.file else {
+      return Ok(());
+    };
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".tmp");
+    let mut file = File::create(&tmp)?;
+    for (id, at) in entries {
+      writeln!(file, "{} {}", at.to_rfc3339(), id)?;
+    }
+    file.sync_all()?;
+    fs::rename(&tmp, path)?;
+    *file_lines = entries.len();
+    Ok(())
+  }
+
+  /**
+   * Takes a delivery ID for the calling request, or returns `None` when it
+   * was seen before, including while another request is still delivering it.
+   * Checking and taking the ID happen under one lock, so of two concurrent
+   * redeliveries only one gets the claim.
+   */
+  pub fn claim(self: &Arc<Self>, id: &str) -> Option<Claim> {
+    let at = Utc::now();
+    let mut seen = self.seen.lock().unwrap();
+    seen.expire(self.cutoff());
+    if seen.by_id.contains_key(id) {
+      return None;
+    }
+    seen.insert(id.to_string(), at, self.capacity);
+    seen.claimed.insert(id.to_string());
+    Some(Claim {
+      dedup: self.clone(),
+      id: id.to_string(),
+      at,
+      kept: false,
+    })
+  }
+
+  /**
+   * Appends an ID to the file, or compacts the file once it has grown to
+   * twice the capacity.  The memory lock is only held to copy the entries, so
+   * requests are not kept waiting on the disk.
+   */
+  fn persist(&self, id: &str) {
+    let Some(path) = &self.file else {
+      return;
+    };
+    let mut file_lines = self.file_lines.lock().unwrap();
+    let result = if *file_lines >= self.capacity * 2 {
+      let entries = {
+        let mut seen = self.seen.lock().unwrap();
+        seen.expire(self.cutoff());
+        let mut entries = seen.order.clone();
+        entries.retain(|(id, _)| !seen.claimed.contains(id));
+        entries
+      };
+      self.compact(&entries, &mut file_lines)
+    } else {
+      OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .and_then(|mut file| {
+          writeln!(file, "{} {}", Utc::now().to_rfc3339(), id)
+        })
+        .map(|_| *file_lines += 1)
+    };
+    if let Err(e) = result {
+      warn!(
+        "Failed to persist delivery ID to '{}': {}",
+        path.display(),
+        e,
+      );
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::test_support::TempDir;
+
+  const DAY: Duration = Duration::from_secs(24 * 60 * 60);
+
+  fn dedup(
+    ttl: Duration,
+    capacity: usize,
+    file: Option<&Path>,
+  ) -> Arc<Deduplicator> {
+    Arc::new(Deduplicator::new(ttl, capacity, file).unwrap())
+  }
+
+  #[test]
+  fn a_claimed_id_is_a_duplicate_until_released() {
+    let dedup = dedup(DAY, 10, None);
+
+    let claim = dedup.claim("72d3162e").unwrap();
+    assert!(dedup.claim("72d3162e").is_none());
+    assert!(dedup.claim("72d3162f").is_some());
+
+    drop(claim);
+    assert!(dedup.claim("72d3162e").is_some());
+  }
+
+  #[tokio::test]
+  async fn a_kept_id_stays_a_duplicate() {
+    let dedup = dedup(DAY, 10, None);
+
+    dedup.claim("72d3162e").unwrap().keep().await;
+
+    assert!(dedup.claim("72d3162e").is_none());
+  }
+
+  #[test]
+  fn ids_expire_after_the_ttl() {
+    let now = Utc::now();
+    let mut seen = Seen::default();
+    seen.insert("old".to_string(), now - chrono::Duration::hours(2), 10);
+    seen.insert("new".to_string(), now, 10);
+
+    seen.expire(now - chrono::Duration::hours(1));
+
+    assert!(!seen.by_id.contains_key("old"));
+    assert!(seen.by_id.contains_key("new"));
+    assert_eq!(seen.order.len(), 1);
+  }
+
+  #[test]
+  fn only_the_latest_ids_are_kept() {
+    let dedup = dedup(DAY, 2, None);
+    let _claims: Vec<_> =
+      ["a", "b", "c"].map(|id| dedup.claim(id).unwrap()).into();
+
+    assert!(dedup.claim("a").is_some());
+    assert!(dedup.claim("c").is_none());
+  }
+
+  #[tokio::test]
+  async fn kept_ids_survive_a_restart_until_they_expire() {
+    let dir = TempDir::new();
+    let file = dir.path().join("delivery-ids");
+    let stale = (Utc::now() - chrono::Duration::days(2)).to_rfc3339();
+    fs::write(&file, format!("{} stale\nnot a line\n", stale)).unwrap();
+
+    let first = dedup(DAY, 10, Some(&file));
+    first.claim("kept").unwrap().keep().await;
+    drop(first.claim("failed").unwrap());
+
+    let restarted = dedup(DAY, 10, Some(&file));
+    assert!(restarted.claim("kept").is_none());
+    assert!(restarted.claim("failed").is_some());
+    assert!(restarted.claim("stale").is_some());
+    let lines = fs::read_to_string(&file).unwrap();
+    assert_eq!(lines.lines().count(), 1);
+  }
+}
@@ -0,0 +1,413 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::cmp::Reverse;
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tracing::{info, warn};
+
+/**
+ * Past this many distinct mismatches new ones are no longer stored, so a
+ * payload that is wrong in a new way on every delivery cannot grow the store
+ * without bound.
+ */
+const MAX_MISMATCHES: usize = 1000;
+
+/** How often changes to the store are written to `--schema-drift-file`. */
+const FLUSH_INTERVAL: Duration = Duration::from_secs(10);
+
+/**
+ * One way a payload did not fit its type in `github_types`, without the
+ * values involved.
+ */
+#[derive(
+  Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize,
+)]
+pub struct Mismatch {
+  pub event: String,
+  /** The JSON path, with array indices left out (`commits[].id`). */
+  pub path: String,
+  pub expected: String,
+  pub actual: String,
+}
+
+impl Mismatch {
+  /**
+   * Picks apart the message of a `SerdePath` error.  serde_json only says
+   * which types were involved in its message, so this depends on its
+   * wording; anything it does not recognize is kept whole as `actual`.
+   */
+  pub fn new(event: &str, path: &str, error: &serde_json::Error) -> Mismatch {
+    let message = error.to_string();
+    let message = message
+      .rsplit_once(" at line ")
+      .map_or(message.as_str(), |(message, _)| message);
+    let path = without_indices(path);
+    let (path, expected, actual) = if let Some(field) = message
+      .strip_prefix("missing field `")
+      .and_then(|rest| rest.strip_suffix('`'))
+    {
+      let path = match path.as_str() {
+        "." | "" => field.to_string(),
+        parent => format!("{}.{}", parent, field),
+      };
+      (path, "a value".to_string(), "missing".to_string())
+    } else if let Some((actual, expected)) = message
+      .strip_prefix("invalid type: ")
+      .or_else(|| message.strip_prefix("invalid value: "))
+      .and_then(|rest| rest.split_once(", expected "))
+    {
+      // Only the kind of value ("string", "integer", "null", ...), so that
+      // the same drift on different deliveries is the same mismatch.
+      let actual = actual.split(' ').next().unwrap_or(actual);
+      (path, expected.to_string(), actual.to_string())
+    } else if let Some((variant, expected)) = message
+      .strip_prefix("unknown variant ")
+      .and_then(|rest| rest.split_once(", expected "))
+    {
+      (path, expected.to_string(), format!("variant {}", variant))
+    } else {
+      (path, String::new(), message.to_string())
+    };
+    Mismatch {
+      event: event.to_string(),
+      path,
+      expected,
+      actual,
+    }
+  }
+}
+
+fn without_indices(path: &str) -> String {
+  let mut out = String::with_capacity(path.len());
+  let mut in_index = false;
+  for c in path.chars() {
+    match c {
+      '[' => {
+        in_index = true;
+        out.push(c);
+      }
+      ']' => {
+        in_index = false;
+        out.push(c);
+      }
+      _ if in_index && c.is_ascii_digit() => {}
+      _ => out.push(c),
+    }
+  }
+  out
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct DriftEntry {
+  #[serde(flatten)]
+  pub mismatch: Mismatch,
+  pub first_seen: DateTime<Utc>,
+  pub last_seen: DateTime<Utc>,
+  pub count: u64,
+}
+
+/**
+ * Every distinct way payloads have failed strict parsing, with when each was
+ * first and last seen and how often.  The counts are kept in memory.  With
+ * a `file`, changes are written to it by `flush`, which `spawn_flusher` calls
+ * every few seconds, and read back on startup, so they outlive restarts and
+ * can be printed with the `schema-drift` command.
+ */
+pub struct SchemaDrift {
+  file: Option<PathBuf>,
+  entries: Mutex<BTreeMap<Mismatch, DriftEntry>>,
+  /** Whether `entries` has changed since it was last written. */
+  dirty: AtomicBool,
+  /** Held while writing, so two flushes do not share the temporary file. */
+  writing: Mutex<()>,
+}
+
+impl SchemaDrift {
+  pub fn new(file: Option<&Path>) -> Result<SchemaDrift, String> {
+    let entries = match file {
+      Some(path) => read_entries(path)?,
+      None => vec![],
+    };
+    if !entries.is_empty() {
+      info!("Loaded {} known schema mismatch(es)", entries.len());
+    }
+    Ok(SchemaDrift {
+      file: file.map(Path::to_path_buf),
+      entries: Mutex::new(
+        entries
+          .into_iter()
+          .map(|entry| (entry.mismatch.clone(), entry))
+          .collect(),
+      ),
+      dirty: AtomicBool::new(false),
+      writing: Mutex::new(()),
+    })
+  }
+
+  /**
+   * Counts a mismatch.  It is written to the file on the next `flush`.
+   */
+  pub fn record(&self, mismatch: Mismatch) {
+    let now = Utc::now();
+    let mut entries = self.entries.lock().unwrap();
+    let recorded = entries.len();
+    match entries.get_mut(&mismatch) {
+      Some(entry) => {
+        entry.last_seen = now;
+        entry.count += 1;
+      }
+      None if recorded >= MAX_MISMATCHES => {
+        warn!(
+          path = %mismatch.path,
+          "Not recording schema mismatch, {} are recorded already",
+          MAX_MISMATCHES,
+        );
+        return;
+      }
+      None => {
+        info!(
+          path = %mismatch.path,
+          expected = %mismatch.expected,
+          actual = %mismatch.actual,
+          "New schema mismatch in {} payloads",
+          mismatch.event,
+        );
+        entries.insert(
+          mismatch.clone(),
+          DriftEntry {
+            mismatch,
+            first_seen: now,
+            last_seen: now,
+            count: 1,
+          },
+        );
+      }
+    }
+    self.dirty.store(true, Ordering::Release);
+  }
+
+  /**
+   * The most recently seen first.
+   */
+  pub fn entries(&self) -> Vec<DriftEntry> {
+    let mut entries: Vec<DriftEntry> =
+      self.entries.lock().unwrap().values().cloned().collect();
+    entries.sort_by_key(|entry| Reverse(entry.last_seen));
+    entries
+  }
+
+  /**
+   * Forgets every mismatch, for once `github_types` has caught up.
+   */
+  pub fn clear(&self) -> usize {
+    let mut entries = self.entries.lock().unwrap();
+    let cleared = entries.len();
+    entries.clear();
+    self.dirty.store(true, Ordering::Release);
+    cleared
+  }
+
+  /**
+   * Writes the store to the file if it has changed since the last flush.
+   * This blocks on the disk, so it belongs on a blocking thread.  Writing is
+   * best effort: a failure is logged, and retried on the next flush.
+   */
+  pub fn flush(&self) {
+    let Some(path) = &self.file else {
+      return;
+    };
+    let _writing = self.writing.lock().unwrap();
+    if !self.dirty.swap(false, Ordering::AcqRel) {
+      return;
+    }
+    let entries: Vec<DriftEntry> =
+      self.entries.lock().unwrap().values().cloned().collect();
+    if let Err(e) = write_entries(path, &entries) {
+      self.dirty.store(true, Ordering::Release);
+      warn!(
+        "Failed to write schema mismatches to '{}': {}",
+        path.display(),
+        e,
+      );
+    }
+  }
+}
+
+/**
+ * Flushes `drift` to its file every `FLUSH_INTERVAL`, on a blocking thread so
+ * that the write stays off the workers answering webhooks.
+ */
+pub fn spawn_flusher(drift: Arc<SchemaDrift>) {
+  if drift.file.is_none() {
+    return;
+  }
+  tokio::spawn(async move {
+    let mut ticker = tokio::time::interval(FLUSH_INTERVAL);
+    loop {
+      ticker.tick().await;
+      let drift = drift.clone();
+      let _ = tokio::task::spawn_blocking(move || drift.flush()).await;
+    }
+  });
+}
+
+fn read_entries(path: &Path) -> Result<Vec<DriftEntry>, String> {
+  match fs::read(path) {
+    Ok(contents) => serde_json::from_slice(&contents).map_err(|e| {
+      format!(
+        "Failed to read schema mismatches from '{}': {}",
+        path.display(),
+        e,
+      )
+    }),
+    Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(vec![]),
+    Err(e) => Err(format!(
+      "Failed to read schema mismatches from '{}': {}",
+      path.display(),
+      e,
+    )),
+  }
+}
+
+fn write_entries(path: &Path, entries: &[DriftEntry]) -> io::Result<()> {
+  let mut tmp = path.as_os_str().to_owned();
+  tmp.push(".tmp");
+  let mut file = File::create(&tmp)?;
+  serde_json::to_writer_pretty(&mut file, entries)?;
+  file.write_all(b"\n")?;
+  file.sync_all()?;
+  fs::rename(&tmp, path)
+}
+
+/**
+ * Prints the mismatches in `path` as a table, for the `schema-drift`
+ * command.
+ */
+pub fn print_report(path: &Path) -> Result<(), String> {
+  let mut entries = read_entries(path)?;
+  if entries.is_empty() {
+    println!("No schema mismatches recorded in {}", path.display());
+    return Ok(());
+  }
+  entries.sort_by(|a, b| {
+    (&a.mismatch.event, &a.mismatch.path)
+      .cmp(&(&b.mismatch.event, &b.mismatch.path))
+  });
+  let header = [
+    "EVENT", "PATH", "EXPECTED", "ACTUAL", "COUNT", "FIRST", "LAST",
+  ];
+  let rows: Vec<[String; 7]> = entries
+    .iter()
+    .map(|entry| {
+      [
+        entry.mismatch.event.clone(),
+        entry.mismatch.path.clone(),
+        entry.mismatch.expected.clone(),
+        entry.mismatch.actual.clone(),
+        entry.count.to_string(),
+        entry.first_seen.format("%Y-%m-%d %H:%M").to_string(),
+        entry.last_seen.format("%Y-%m-%d %H:%M").to_string(),
+      ]
+    })
+    .collect();
+  let widths: Vec<usize> = (0..header.len())
+    .map(|i| {
+      rows
+        .iter()
+        .map(|row| row[i].len())
+        .chain(std::iter::once(header[i].len()))
+        .max()
+        .unwrap_or(0)
+    })
+    .collect();
+  let line = |cells: Vec<&str>| {
+    cells
+      .iter()
+      .zip(&widths)
+      .map(|(cell, width)| format!("{:<width$}", cell, width = width))
+      .collect::<Vec<_>>()
+      .join("  ")
+      .trim_end()
+      .to_string()
+  };
+  println!("{}", line(header.to_vec()));
+  for row in &rows {
+    println!("{}", line(row.iter().map(String::as_str).collect()));
+  }
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::github_types::PushEvent;
+  use crate::test_support::{edited_fixture, TempDir};
+
+  /** The mismatch in the push fixture once `edit` is applied to it. */
+  fn mismatch(edit: impl FnOnce(&mut serde_json::Value)) -> Mismatch {
+    let bytes =
+      edited_fixture(include_bytes!("../tests/fixtures/push.json"), edit);
+    let mut de = serde_json::Deserializer::from_slice(&bytes);
+    let error =
+      serde_path_to_error::deserialize::<_, PushEvent>(&mut de).unwrap_err();
+    Mismatch::new("push", &error.path().to_string(), error.inner())
+  }
+
+  fn has_projects_as_string(payload: &mut serde_json::Value) {
+    payload["repository"]["has_projects"] = serde_json::json!("yes");
+  }
+
+  #[test]
+  fn wrong_type_is_described_by_kind() {
+    assert_eq!(
+      mismatch(has_projects_as_string),
+      Mismatch {
+        event: "push".to_string(),
+        path: "repository.has_projects".to_string(),
+        expected: "a boolean".to_string(),
+        actual: "string".to_string(),
+      }
+    );
+  }
+
+  #[test]
+  fn missing_field_names_the_field() {
+    let mismatch = mismatch(|p| {
+      p["sender"].as_object_mut().unwrap().remove("gists_url");
+    });
+    assert_eq!(mismatch.path, "sender.gists_url");
+    assert_eq!(mismatch.actual, "missing");
+  }
+
+  #[test]
+  fn array_indices_are_left_out() {
+    let mismatch =
+      mismatch(|p| p["commits"][0]["distinct"] = serde_json::Value::Null);
+    assert_eq!(mismatch.path, "commits[].distinct");
+    assert_eq!(mismatch.actual, "null");
+  }
+
+  #[test]
+  fn counts_reach_the_file_when_flushed() {
+    let dir = TempDir::new();
+    let file = dir.path().join("drift.json");
+    let drift = SchemaDrift::new(Some(&file)).unwrap();
+    drift.record(mismatch(has_projects_as_string));
+    drift.record(mismatch(has_projects_as_string));
+    assert!(!file.exists());
+
+    drift.flush();
+    let entries = SchemaDrift::new(Some(&file)).unwrap().entries();
+    assert_eq!(entries.len(), 1);
+    assert_eq!(entries[0].count, 2);
+
+    assert_eq!(drift.clear(), 1);
+    drift.flush();
+    assert!(SchemaDrift::new(Some(&file)).unwrap().entries().is_empty());
+  }
+}
@@ -18,6 +18,7 @@ pub enum ProxyError {
   #[error("Failed to forward request: {0}")]
   ForwardRequest(#[from] reqwest::Error),
 
+  #[allow(dead_code)]
   #[error("Failed to read request body")]
   ReadBody,
 
@@ -48,6 +49,42 @@ pub enum ProxyError {
 
   #[error("Configuration error: {0}")]
   Configuration(String),
+
+  #[error("Unauthorized")]
+  Unauthorized,
+
+  #[error("Not found: {0}")]
+  NotFound(String),
+
+  #[error("Conflict: {0}")]
+  Conflict(String),
+}
+
+impl ProxyError {
+  /** The variant name, for use as a metric label. */
+  pub fn kind(&self) -> &'static str {
+    match self {
+      ProxyError::ServerBind(_) => "ServerBind",
+      ProxyError::InvalidSignature => "InvalidSignature",
+      ProxyError::MissingSignature => "MissingSignature",
+      ProxyError::HmacComputation => "HmacComputation",
+      ProxyError::ForwardRequest(_) => "ForwardRequest",
+      ProxyError::ReadBody => "ReadBody",
+      ProxyError::InvalidHeader(_) => "InvalidHeader",
+      ProxyError::ServerError(_) => "ServerError",
+      ProxyError::InvalidPayload(_) => "InvalidPayload",
+      ProxyError::PayloadDeserializationError(_) => {
+        "PayloadDeserializationError"
+      }
+      ProxyError::SerdePath { .. } => "SerdePath",
+      ProxyError::PayloadTooLarge => "PayloadTooLarge",
+      ProxyError::InvalidJenkinsUrl => "InvalidJenkinsUrl",
+      ProxyError::Configuration(_) => "Configuration",
+      ProxyError::Unauthorized => "Unauthorized",
+      ProxyError::NotFound(_) => "NotFound",
+      ProxyError::Conflict(_) => "Conflict",
+    }
+  }
 }
 
 impl ResponseError for ProxyError {
@@ -56,6 +93,15 @@ impl ResponseError for ProxyError {
       ProxyError::InvalidSignature | ProxyError::MissingSignature => {
         HttpResponse::Unauthorized().body(self.to_string())
       }
+      ProxyError::Unauthorized => {
+        HttpResponse::Unauthorized().body(self.to_string())
+      }
+      ProxyError::NotFound(_) => {
+        HttpResponse::NotFound().body(self.to_string())
+      }
+      ProxyError::Conflict(_) => {
+        HttpResponse::Conflict().body(self.to_string())
+      }
       ProxyError::ForwardRequest(_) => {
         HttpResponse::BadGateway().body("Failed to forward request to Jenkins")
       }
@@ -77,6 +123,9 @@ impl ResponseError for ProxyError {
       ProxyError::InvalidSignature | ProxyError::MissingSignature => {
         StatusCode::UNAUTHORIZED
       }
+      ProxyError::Unauthorized => StatusCode::UNAUTHORIZED,
+      ProxyError::NotFound(_) => StatusCode::NOT_FOUND,
+      ProxyError::Conflict(_) => StatusCode::CONFLICT,
       ProxyError::ForwardRequest(_) => StatusCode::BAD_GATEWAY,
       ProxyError::ReadBody
       | ProxyError::InvalidHeader(_)
@@ -0,0 +1,281 @@
+use glob::{MatchOptions, Pattern};
+
+use crate::github_types::GitHubWebhookPayload;
+
+/**
+ * Commit message markers that ask for a push not to be built.  These are the
+ * ones GitHub Actions honours, minus those naming Actions itself.
+ */
+const SKIP_CI_MARKERS: [&str; 3] = ["[skip ci]", "[ci skip]", "[no ci]"];
+
+/**
+ * Paths are matched like in a `.gitignore`: case sensitively, and with `*`
+ * stopping at a `/`, so a pattern for the files in a directory does not reach
+ * into its subdirectories.  `**` matches any number of directories.
+ */
+const PATH_MATCH: MatchOptions = MatchOptions {
+  case_sensitive: true,
+  require_literal_separator: true,
+  require_literal_leading_dot: false,
+};
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum FilterDecision {
+  Forward,
+  /** Why the event was left out. */
+  Skip(String),
+}
+
+/**
+ * Decides, before routing, whether an event is worth a build at all.  Every
+ * check is off unless configured, and checks that an event has no data for
+ * (such as paths on a pull request) let it through.
+ */
+#[derive(Clone, Debug, Default)]
+pub struct EventFilter {
+  pub skip_draft_pull_requests: bool,
+  pub skip_bot_senders: bool,
+  pub honor_skip_ci: bool,
+  include_paths: Vec<Pattern>,
+  exclude_paths: Vec<Pattern>,
+}
+
+impl EventFilter {
+  pub fn new(
+    skip_draft_pull_requests: bool,
+    skip_bot_senders: bool,
+    honor_skip_ci: bool,
+    include_paths: &[String],
+    exclude_paths: &[String],
+  ) -> Result<EventFilter, String> {
+    Ok(EventFilter {
+      skip_draft_pull_requests,
+      skip_bot_senders,
+      honor_skip_ci,
+      include_paths: path_patterns("--include-path", include_paths)?,
+      exclude_paths: path_patterns("--exclude-path", exclude_paths)?,
+    })
+  }
+
+  pub fn is_empty(&self) -> bool {
+    !self.skip_draft_pull_requests
+      && !self.skip_bot_senders
+      && !self.honor_skip_ci
+      && self.include_paths.is_empty()
+      && self.exclude_paths.is_empty()
+  }
+
+  /**
+   * The checks that are on, for the startup log.
+   */
+  pub fn describe(&self) -> String {
+    let globs = |patterns: &[Pattern]| {
+      patterns
+        .iter()
+        .map(Pattern::as_str)
+        .collect::<Vec<_>>()
+        .join(", ")
+    };
+    let mut checks = vec![];
+    if self.skip_draft_pull_requests {
+      checks.push("draft pull requests".to_string());
+    }
+    if self.skip_bot_senders {
+      checks.push("bot senders".to_string());
+    }
+    if self.honor_skip_ci {
+      checks.push("[skip ci]".to_string());
+    }
+    if !self.include_paths.is_empty() {
+      checks.push(format!("paths outside [{}]", globs(&self.include_paths)));
+    }
+    if !self.exclude_paths.is_empty() {
+      checks.push(format!("paths in [{}]", globs(&self.exclude_paths)));
+    }
+    checks.join(", ")
+  }
+
+  /**
+   * The first check that rejects the event wins.
+   */
+  pub fn check(&self, payload: &GitHubWebhookPayload) -> FilterDecision {
+    if self.skip_draft_pull_requests && payload.is_draft_pull_request() {
+      return FilterDecision::Skip("draft pull request".to_string());
+    }
+    if self.skip_bot_senders && payload.sender_is_bot() {
+      return FilterDecision::Skip(format!(
+        "sent by bot {}",
+        payload.sender_login().unwrap_or("unknown"),
+      ));
+    }
+    if self.honor_skip_ci {
+      if let Some(marker) = payload
+        .commit_messages()
+        .into_iter()
+        .find_map(skip_ci_marker)
+      {
+        return FilterDecision::Skip(format!(
+          "commit message contains {}",
+          marker
+        ));
+      }
+    }
+    if self.include_paths.is_empty() && self.exclude_paths.is_empty() {
+      return FilterDecision::Forward;
+    }
+    match payload.changed_paths() {
+      Some(paths) if !paths.iter().any(|path| self.path_passes(path)) => {
+        FilterDecision::Skip(format!(
+          "none of the {} changed path(s) pass the path filters",
+          paths.len(),
+        ))
+      }
+      _ => FilterDecision::Forward,
+    }
+  }
+
+  fn path_passes(&self, path: &str) -> bool {
+    let matches = |patterns: &[Pattern]| {
+      patterns.iter().any(|p| p.matches_with(path, PATH_MATCH))
+    };
+    (self.include_paths.is_empty() || matches(&self.include_paths))
+      && !matches(&self.exclude_paths)
+  }
+}
+
+fn skip_ci_marker(message: &str) -> Option<&'static str> {
+  let message = message.to_lowercase();
+  SKIP_CI_MARKERS
+    .into_iter()
+    .find(|marker| message.contains(marker))
+}
+
+fn path_patterns(
+  flag: &str,
+  patterns: &[String],
+) -> Result<Vec<Pattern>, String> {
+  patterns
+    .iter()
+    .map(|p| {
+      Pattern::new(p)
+        .map_err(|e| format!("Invalid {} pattern '{}': {}", flag, p, e))
+    })
+    .collect()
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::github_types::{PullRequestEvent, MAX_PUSH_COMMITS};
+  use crate::test_support::{edited_fixture, fixture};
+
+  fn push(edit: impl FnOnce(&mut serde_json::Value)) -> GitHubWebhookPayload {
+    GitHubWebhookPayload::Push(fixture(&edited_fixture(
+      include_bytes!("../tests/fixtures/push.json"),
+      edit,
+    )))
+  }
+
+  fn filter(include: &[&str], exclude: &[&str]) -> EventFilter {
+    let strings =
+      |p: &[&str]| p.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+    EventFilter::new(true, true, true, &strings(include), &strings(exclude))
+      .unwrap()
+  }
+
+  fn push_changing(paths: &[&str]) -> GitHubWebhookPayload {
+    push(|payload| {
+      let commit = &mut payload["commits"][0];
+      commit["added"] = serde_json::json!([]);
+      commit["removed"] = serde_json::json!([]);
+      commit["modified"] = serde_json::json!(paths);
+    })
+  }
+
+  #[test]
+  fn fixtures_are_forwarded_unless_draft() {
+    let mut pull_request: PullRequestEvent = serde_json::from_slice(
+      include_bytes!("../tests/fixtures/pull_request.synchronize.json"),
+    )
+    .unwrap();
+    let filter = filter(&[], &[]);
+    assert_eq!(
+      filter.check(&GitHubWebhookPayload::PullRequest(pull_request.clone())),
+      FilterDecision::Forward
+    );
+    assert_eq!(filter.check(&push(|_| {})), FilterDecision::Forward);
+
+    pull_request.pull_request.draft = true;
+    assert_eq!(
+      filter.check(&GitHubWebhookPayload::PullRequest(pull_request)),
+      FilterDecision::Skip("draft pull request".to_string())
+    );
+  }
+
+  #[test]
+  fn bots_and_skip_ci_are_skipped() {
+    let bot = push(|p| p["sender"]["type"] = serde_json::json!("Bot"));
+    assert!(matches!(
+      filter(&[], &[]).check(&bot),
+      FilterDecision::Skip(_)
+    ));
+
+    let skip_ci = push(|p| {
+      p["head_commit"]["message"] = serde_json::json!("Fix typo [CI SKIP]")
+    });
+    assert_eq!(
+      filter(&[], &[]).check(&skip_ci),
+      FilterDecision::Skip("commit message contains [ci skip]".into())
+    );
+
+    let earlier_skip_ci = push(|p| {
+      let mut earlier = p["commits"][0].clone();
+      earlier["message"] = serde_json::json!("WIP\n\n[skip ci]");
+      p["commits"].as_array_mut().unwrap().insert(0, earlier);
+    });
+    assert_eq!(
+      filter(&[], &[]).check(&earlier_skip_ci),
+      FilterDecision::Skip("commit message contains [skip ci]".into())
+    );
+  }
+
+  #[test]
+  fn paths_must_be_included_and_not_excluded() {
+    let docs = push_changing(&["docs/index.md", "docs/api/push.md"]);
+    assert_eq!(
+      filter(&[], &["docs/**"]).check(&docs),
+      FilterDecision::Skip(
+        "none of the 2 changed path(s) pass the path filters".into()
+      )
+    );
+    assert_eq!(
+      filter(&["docs/*"], &[]).check(&docs),
+      FilterDecision::Forward
+    );
+    assert!(matches!(
+      filter(&["src/**"], &[]).check(&docs),
+      FilterDecision::Skip(_)
+    ));
+
+    let mixed = push_changing(&["docs/index.md", "src/main.rs"]);
+    assert_eq!(
+      filter(&[], &["docs/**"]).check(&mixed),
+      FilterDecision::Forward
+    );
+  }
+
+  #[test]
+  fn pushes_listing_the_most_commits_are_not_filtered_on_paths() {
+    let largest = push(|p| {
+      let mut commit = p["commits"][0].clone();
+      commit["added"] = serde_json::json!([]);
+      commit["removed"] = serde_json::json!([]);
+      commit["modified"] = serde_json::json!(["docs/index.md"]);
+      p["commits"] = serde_json::json!(vec![commit; MAX_PUSH_COMMITS]);
+    });
+    assert_eq!(
+      filter(&[], &["docs/**"]).check(&largest),
+      FilterDecision::Forward
+    );
+  }
+}
@@ -1,8 +1,14 @@
 #![allow(dead_code)]
-use crate::datetime_agnostic::{datetime_from_int_or_str, FlexibleDateTime};
+use crate::datetime_agnostic::FlexibleDateTime;
 use serde::Deserialize;
 use std::collections::HashMap;
 
+/**
+ * The most commits a push webhook lists.  A push listing this many may have
+ * more.
+ */
+pub const MAX_PUSH_COMMITS: usize = 2048;
+
 #[derive(Debug, Clone, Deserialize)]
 pub struct User {
   pub login: String,
@@ -223,6 +229,7 @@ pub struct PullRequest {
 #[derive(Debug, Clone, Deserialize)]
 pub struct PullRequestRef {
   pub label: String,
+  #[serde(rename = "ref")]
   pub ref_field: String,
   pub sha: String,
   pub user: User,
@@ -266,6 +273,7 @@ pub struct Comment {
 }
 
 #[derive(Debug, Clone, Deserialize)]
+#[allow(clippy::large_enum_variant)]
 #[serde(tag = "event", content = "payload")]
 #[serde(rename_all = "snake_case")]
 pub enum WebhookEvent {
@@ -277,6 +285,18 @@ pub enum WebhookEvent {
   Delete(DeleteEvent),
   Fork(ForkEvent),
   Release(ReleaseEvent),
+  Ping(PingEvent),
+  PullRequestReview(PullRequestReviewEvent),
+  PullRequestReviewComment(PullRequestReviewCommentEvent),
+  Status(StatusEvent),
+  CheckRun(CheckRunEvent),
+  CheckSuite(CheckSuiteEvent),
+  WorkflowRun(WorkflowRunEvent),
+  WorkflowDispatch(WorkflowDispatchEvent),
+  RepositoryDispatch(RepositoryDispatchEvent),
+  MergeGroup(MergeGroupEvent),
+  Deployment(DeploymentEvent),
+  DeploymentStatus(DeploymentStatusEvent),
   #[serde(other)]
   Unknown,
 }
@@ -310,7 +330,7 @@ pub struct Pusher {
 
 #[derive(Debug, Clone, Deserialize)]
 pub struct PullRequestEvent {
-  pub action: String,
+  pub action: PullRequestAction,
   pub number: u64,
   pub pull_request: PullRequest,
   pub repository: Repository,
@@ -320,6 +340,67 @@ pub struct PullRequestEvent {
   pub enterprise: Option<Enterprise>,
 }
 
+/**
+ * What happened to a pull request.  GitHub adds actions from time to time, so
+ * ones not listed here are kept as `Other` rather than failing the payload.
+ */
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PullRequestAction {
+  Assigned,
+  AutoMergeDisabled,
+  AutoMergeEnabled,
+  Closed,
+  ConvertedToDraft,
+  Demilestoned,
+  Dequeued,
+  Edited,
+  Enqueued,
+  Labeled,
+  Locked,
+  Milestoned,
+  Opened,
+  ReadyForReview,
+  Reopened,
+  ReviewRequestRemoved,
+  ReviewRequested,
+  Synchronize,
+  Unassigned,
+  Unlabeled,
+  Unlocked,
+  #[serde(untagged)]
+  Other(String),
+}
+
+impl PullRequestAction {
+  pub fn as_str(&self) -> &str {
+    match self {
+      PullRequestAction::Assigned => "assigned",
+      PullRequestAction::AutoMergeDisabled => "auto_merge_disabled",
+      PullRequestAction::AutoMergeEnabled => "auto_merge_enabled",
+      PullRequestAction::Closed => "closed",
+      PullRequestAction::ConvertedToDraft => "converted_to_draft",
+      PullRequestAction::Demilestoned => "demilestoned",
+      PullRequestAction::Dequeued => "dequeued",
+      PullRequestAction::Edited => "edited",
+      PullRequestAction::Enqueued => "enqueued",
+      PullRequestAction::Labeled => "labeled",
+      PullRequestAction::Locked => "locked",
+      PullRequestAction::Milestoned => "milestoned",
+      PullRequestAction::Opened => "opened",
+      PullRequestAction::ReadyForReview => "ready_for_review",
+      PullRequestAction::Reopened => "reopened",
+      PullRequestAction::ReviewRequestRemoved => "review_request_removed",
+      PullRequestAction::ReviewRequested => "review_requested",
+      PullRequestAction::Synchronize => "synchronize",
+      PullRequestAction::Unassigned => "unassigned",
+      PullRequestAction::Unlabeled => "unlabeled",
+      PullRequestAction::Unlocked => "unlocked",
+      PullRequestAction::Other(action) => action,
+    }
+  }
+}
+
 #[derive(Debug, Clone, Deserialize)]
 pub struct IssuesEvent {
   pub action: String,
@@ -392,7 +473,404 @@ pub struct ReleaseEvent {
   pub enterprise: Option<Enterprise>,
 }
 
+/**
+ * Sent once when a webhook is created.  Hooks on an organization or a GitHub
+ * App have no repository, and App hooks have no sender either.
+ */
+#[derive(Debug, Clone, Deserialize)]
+pub struct PingEvent {
+  pub zen: String,
+  pub hook_id: Option<u64>,
+  pub hook: Option<serde_json::Value>,
+  pub repository: Option<Repository>,
+  pub sender: Option<User>,
+  pub organization: Option<Organization>,
+  pub installation: Option<Installation>,
+  pub enterprise: Option<Enterprise>,
+}
+
+/**
+ * The shorter form of a pull request that review events carry, without the
+ * merge state and counters of `PullRequest`.
+ */
+#[derive(Debug, Clone, Deserialize)]
+pub struct SimplePullRequest {
+  pub id: u64,
+  pub node_id: String,
+  pub url: String,
+  pub html_url: String,
+  pub number: u64,
+  pub state: String,
+  pub locked: bool,
+  pub title: String,
+  pub user: User,
+  pub body: Option<String>,
+  pub labels: Vec<Label>,
+  pub created_at: FlexibleDateTime,
+  pub updated_at: FlexibleDateTime,
+  pub closed_at: Option<FlexibleDateTime>,
+  pub merged_at: Option<FlexibleDateTime>,
+  pub merge_commit_sha: Option<String>,
+  pub head: PullRequestRef,
+  pub base: PullRequestRef,
+  pub author_association: String,
+  pub draft: Option<bool>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct PullRequestReview {
+  pub id: u64,
+  pub node_id: String,
+  pub user: Option<User>,
+  pub body: Option<String>,
+  pub commit_id: Option<String>,
+  pub submitted_at: Option<FlexibleDateTime>,
+  pub state: String,
+  pub html_url: String,
+  pub author_association: String,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct PullRequestReviewEvent {
+  pub action: String,
+  pub review: PullRequestReview,
+  pub pull_request: SimplePullRequest,
+  pub repository: Repository,
+  pub sender: User,
+  pub organization: Option<Organization>,
+  pub installation: Option<Installation>,
+  pub enterprise: Option<Enterprise>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct PullRequestReviewComment {
+  pub id: u64,
+  pub node_id: String,
+  pub url: String,
+  pub html_url: String,
+  pub pull_request_review_id: Option<u64>,
+  pub diff_hunk: String,
+  pub path: String,
+  pub commit_id: String,
+  pub original_commit_id: String,
+  pub line: Option<u64>,
+  pub body: String,
+  pub user: User,
+  pub created_at: FlexibleDateTime,
+  pub updated_at: FlexibleDateTime,
+  pub author_association: String,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct PullRequestReviewCommentEvent {
+  pub action: String,
+  pub comment: PullRequestReviewComment,
+  pub pull_request: SimplePullRequest,
+  pub repository: Repository,
+  pub sender: User,
+  pub organization: Option<Organization>,
+  pub installation: Option<Installation>,
+  pub enterprise: Option<Enterprise>,
+}
+
+/**
+ * A commit status set through the statuses API.  There is no single ref; the
+ * commit may be on any of `branches`.
+ */
+#[derive(Debug, Clone, Deserialize)]
+pub struct StatusEvent {
+  pub id: u64,
+  pub sha: String,
+  pub name: String,
+  pub context: String,
+  pub state: String,
+  pub description: Option<String>,
+  pub target_url: Option<String>,
+  pub branches: Vec<StatusBranch>,
+  pub created_at: FlexibleDateTime,
+  pub updated_at: FlexibleDateTime,
+  pub repository: Repository,
+  pub sender: User,
+  pub organization: Option<Organization>,
+  pub installation: Option<Installation>,
+  pub enterprise: Option<Enterprise>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct StatusBranch {
+  pub name: String,
+  pub commit: StatusBranchCommit,
+  pub protected: bool,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct StatusBranchCommit {
+  pub sha: String,
+  pub url: String,
+}
+
+/**
+ * The commit checks, workflow runs and merge groups refer to, which is not
+ * shaped like a push's `Commit`.
+ */
+#[derive(Debug, Clone, Deserialize)]
+pub struct SimpleCommit {
+  pub id: String,
+  pub tree_id: String,
+  pub message: String,
+  pub timestamp: String,
+  pub author: Option<CommitAuthor>,
+  pub committer: Option<CommitAuthor>,
+}
+
+/**
+ * A pull request as checks and workflow runs list them: just its number and
+ * the two ends.
+ */
+#[derive(Debug, Clone, Deserialize)]
+pub struct CheckPullRequest {
+  pub id: u64,
+  pub number: u64,
+  pub url: String,
+  pub head: CheckPullRequestRef,
+  pub base: CheckPullRequestRef,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct CheckPullRequestRef {
+  #[serde(rename = "ref")]
+  pub ref_field: String,
+  pub sha: String,
+  pub repo: serde_json::Value,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct CheckSuite {
+  pub id: u64,
+  pub node_id: Option<String>,
+  pub head_branch: Option<String>,
+  pub head_sha: String,
+  pub status: Option<String>,
+  pub conclusion: Option<String>,
+  pub url: Option<String>,
+  pub before: Option<String>,
+  pub after: Option<String>,
+  #[serde(default)]
+  pub pull_requests: Vec<CheckPullRequest>,
+  pub app: Option<serde_json::Value>,
+  pub head_commit: Option<SimpleCommit>,
+  pub created_at: Option<FlexibleDateTime>,
+  pub updated_at: Option<FlexibleDateTime>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct CheckRun {
+  pub id: u64,
+  pub node_id: String,
+  pub name: String,
+  pub head_sha: String,
+  pub external_id: Option<String>,
+  pub url: String,
+  pub html_url: Option<String>,
+  pub details_url: Option<String>,
+  pub status: String,
+  pub conclusion: Option<String>,
+  pub started_at: Option<FlexibleDateTime>,
+  pub completed_at: Option<FlexibleDateTime>,
+  pub check_suite: CheckSuite,
+  #[serde(default)]
+  pub pull_requests: Vec<CheckPullRequest>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct CheckRunEvent {
+  pub action: String,
+  pub check_run: CheckRun,
+  pub requested_action: Option<serde_json::Value>,
+  pub repository: Repository,
+  pub sender: User,
+  pub organization: Option<Organization>,
+  pub installation: Option<Installation>,
+  pub enterprise: Option<Enterprise>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct CheckSuiteEvent {
+  pub action: String,
+  pub check_suite: CheckSuite,
+  pub repository: Repository,
+  pub sender: User,
+  pub organization: Option<Organization>,
+  pub installation: Option<Installation>,
+  pub enterprise: Option<Enterprise>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct WorkflowRun {
+  pub id: u64,
+  pub node_id: String,
+  pub name: Option<String>,
+  pub path: String,
+  pub workflow_id: u64,
+  pub run_number: u64,
+  pub run_attempt: Option<u64>,
+  pub event: String,
+  pub status: Option<String>,
+  pub conclusion: Option<String>,
+  pub head_branch: Option<String>,
+  pub head_sha: String,
+  pub check_suite_id: Option<u64>,
+  pub url: String,
+  pub html_url: String,
+  #[serde(default)]
+  pub pull_requests: Vec<CheckPullRequest>,
+  pub actor: Option<User>,
+  pub triggering_actor: Option<User>,
+  pub head_commit: Option<SimpleCommit>,
+  pub created_at: FlexibleDateTime,
+  pub updated_at: FlexibleDateTime,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct WorkflowRunEvent {
+  pub action: String,
+  pub workflow_run: WorkflowRun,
+  pub workflow: Option<serde_json::Value>,
+  pub repository: Repository,
+  pub sender: User,
+  pub organization: Option<Organization>,
+  pub installation: Option<Installation>,
+  pub enterprise: Option<Enterprise>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct WorkflowDispatchEvent {
+  #[serde(rename = "ref")]
+  pub ref_field: String,
+  /** The path of the workflow file, such as `.github/workflows/ci.yml`. */
+  pub workflow: String,
+  pub inputs: Option<HashMap<String, serde_json::Value>>,
+  pub repository: Repository,
+  pub sender: User,
+  pub organization: Option<Organization>,
+  pub installation: Option<Installation>,
+  pub enterprise: Option<Enterprise>,
+}
+
+/**
+ * Sent through the API on request.  `action` is the `event_type` the caller
+ * chose, and `branch` the repository's default branch.
+ */
+#[derive(Debug, Clone, Deserialize)]
+pub struct RepositoryDispatchEvent {
+  pub action: String,
+  pub branch: String,
+  pub client_payload: Option<serde_json::Value>,
+  pub repository: Repository,
+  pub sender: User,
+  pub organization: Option<Organization>,
+  pub installation: Option<Installation>,
+  pub enterprise: Option<Enterprise>,
+}
+
+/**
+ * A merge queue entry.  `head_ref` is the queue's temporary branch, and the
+ * `base_ref` the branch it will merge into.  Both are full refs.
+ */
+#[derive(Debug, Clone, Deserialize)]
+pub struct MergeGroup {
+  pub head_sha: String,
+  pub head_ref: String,
+  pub base_sha: String,
+  pub base_ref: String,
+  pub head_commit: SimpleCommit,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct MergeGroupEvent {
+  pub action: String,
+  pub reason: Option<String>,
+  pub merge_group: MergeGroup,
+  pub repository: Repository,
+  pub sender: User,
+  pub organization: Option<Organization>,
+  pub installation: Option<Installation>,
+  pub enterprise: Option<Enterprise>,
+}
+
+/**
+ * `ref` is whatever the deployment was created with: a branch, a tag or a
+ * commit SHA.
+ */
+#[derive(Debug, Clone, Deserialize)]
+pub struct Deployment {
+  pub id: u64,
+  pub node_id: String,
+  pub url: String,
+  pub sha: String,
+  #[serde(rename = "ref")]
+  pub ref_field: String,
+  pub task: String,
+  pub payload: serde_json::Value,
+  pub original_environment: Option<String>,
+  pub environment: String,
+  pub description: Option<String>,
+  pub creator: Option<User>,
+  pub created_at: FlexibleDateTime,
+  pub updated_at: FlexibleDateTime,
+  pub statuses_url: String,
+  pub repository_url: String,
+  pub transient_environment: Option<bool>,
+  pub production_environment: Option<bool>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct DeploymentEvent {
+  pub action: String,
+  pub deployment: Deployment,
+  pub workflow: Option<serde_json::Value>,
+  pub workflow_run: Option<serde_json::Value>,
+  pub repository: Repository,
+  pub sender: User,
+  pub organization: Option<Organization>,
+  pub installation: Option<Installation>,
+  pub enterprise: Option<Enterprise>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct DeploymentStatus {
+  pub id: u64,
+  pub node_id: String,
+  pub url: String,
+  pub state: String,
+  pub description: Option<String>,
+  pub environment: String,
+  pub target_url: Option<String>,
+  pub log_url: Option<String>,
+  pub environment_url: Option<String>,
+  pub creator: Option<User>,
+  pub created_at: FlexibleDateTime,
+  pub updated_at: FlexibleDateTime,
+  pub deployment_url: String,
+  pub repository_url: String,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct DeploymentStatusEvent {
+  pub action: String,
+  pub deployment_status: DeploymentStatus,
+  pub deployment: Deployment,
+  pub check_run: Option<serde_json::Value>,
+  pub workflow_run: Option<serde_json::Value>,
+  pub repository: Repository,
+  pub sender: User,
+  pub organization: Option<Organization>,
+  pub installation: Option<Installation>,
+  pub enterprise: Option<Enterprise>,
+}
+
 #[derive(Debug, Clone, Deserialize)]
+#[allow(clippy::large_enum_variant)]
 #[serde(untagged)]
 pub enum GitHubWebhookPayload {
   Push(PushEvent),
@@ -403,7 +881,20 @@ pub enum GitHubWebhookPayload {
   Delete(DeleteEvent),
   Fork(ForkEvent),
   Release(ReleaseEvent),
+  Ping(PingEvent),
+  PullRequestReview(PullRequestReviewEvent),
+  PullRequestReviewComment(PullRequestReviewCommentEvent),
+  Status(StatusEvent),
+  CheckRun(CheckRunEvent),
+  CheckSuite(CheckSuiteEvent),
+  WorkflowRun(WorkflowRunEvent),
+  WorkflowDispatch(WorkflowDispatchEvent),
+  RepositoryDispatch(RepositoryDispatchEvent),
+  MergeGroup(MergeGroupEvent),
+  Deployment(DeploymentEvent),
+  DeploymentStatus(DeploymentStatusEvent),
   Generic(GenericPayload),
+  Minimal(MinimalPayload),
 }
 
 #[derive(Debug, Clone, Deserialize)]
@@ -417,7 +908,701 @@ pub struct GenericPayload {
   pub other: HashMap<String, serde_json::Value>,
 }
 
+/**
+ * Only the fields the proxy itself uses, every one of them optional, for
+ * `--validation lenient`.  A payload that is a JSON object at all parses, so
+ * changes GitHub makes to the rest of it cannot hold up a build.  The
+ * accessors read the same refs and commits from it as from the typed events.
+ */
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct MinimalPayload {
+  #[serde(rename = "ref")]
+  pub ref_field: Option<String>,
+  pub ref_type: Option<String>,
+  /** The branch of a `repository_dispatch`. */
+  pub branch: Option<String>,
+  pub after: Option<String>,
+  pub sha: Option<String>,
+  pub deleted: Option<bool>,
+  pub action: Option<String>,
+  pub zen: Option<String>,
+  pub hook_id: Option<u64>,
+  pub check_run: Option<MinimalCheckRun>,
+  pub check_suite: Option<MinimalRun>,
+  pub workflow_run: Option<MinimalRun>,
+  pub merge_group: Option<MinimalMergeGroup>,
+  pub deployment: Option<MinimalDeployment>,
+  pub repository: Option<MinimalRepository>,
+  pub organization: Option<OrganizationIdentity>,
+  pub sender: Option<MinimalUser>,
+  pub pull_request: Option<MinimalPullRequest>,
+  pub release: Option<MinimalRelease>,
+  pub head_commit: Option<MinimalCommit>,
+  #[serde(default)]
+  pub commits: Vec<MinimalCommit>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct MinimalRepository {
+  pub full_name: String,
+  pub name: Option<String>,
+  pub owner: Option<MinimalUser>,
+  pub clone_url: Option<String>,
+  pub ssh_url: Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct MinimalUser {
+  pub login: String,
+  #[serde(rename = "type")]
+  pub user_type: Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct MinimalPullRequest {
+  pub number: u64,
+  pub head: MinimalRef,
+  pub base: MinimalRef,
+  pub draft: Option<bool>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct MinimalRef {
+  #[serde(rename = "ref")]
+  pub ref_field: String,
+  pub sha: String,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct MinimalRelease {
+  pub tag_name: String,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct MinimalCheckRun {
+  pub head_sha: Option<String>,
+  pub check_suite: Option<MinimalRun>,
+}
+
+/** A check suite or a workflow run, which both name only a branch. */
+#[derive(Debug, Clone, Deserialize)]
+pub struct MinimalRun {
+  pub head_branch: Option<String>,
+  pub head_sha: Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct MinimalMergeGroup {
+  pub head_sha: Option<String>,
+  pub head_ref: Option<String>,
+  pub base_ref: Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct MinimalDeployment {
+  pub sha: Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct MinimalCommit {
+  pub id: String,
+  pub message: Option<String>,
+  #[serde(default)]
+  pub added: Vec<String>,
+  #[serde(default)]
+  pub removed: Vec<String>,
+  #[serde(default)]
+  pub modified: Vec<String>,
+}
+
+impl MinimalPayload {
+  /**
+   * For `--validation off`: nothing but what was read to pick the secret.
+   */
+  pub fn from_identity(identity: &PayloadIdentity) -> MinimalPayload {
+    MinimalPayload {
+      repository: identity.repository.as_ref().map(|r| MinimalRepository {
+        full_name: r.full_name.clone(),
+        name: None,
+        owner: None,
+        clone_url: None,
+        ssh_url: None,
+      }),
+      organization: identity.organization.clone(),
+      ..MinimalPayload::default()
+    }
+  }
+
+  /**
+   * `create` and `delete` name a short ref plus its type.  Otherwise only a
+   * full ref is taken, since a short one (as on a deployment) may as well be
+   * a commit SHA.  Without a `ref`, the merge queue's branch or the branch of
+   * a check, workflow run or repository dispatch is used, as for the typed
+   * events.
+   */
+  fn git_ref(&self) -> Option<String> {
+    if let Some(name) = &self.ref_field {
+      return match &self.ref_type {
+        Some(ref_type) => Some(full_ref(ref_type, name)),
+        None if name.starts_with("refs/") => Some(name.clone()),
+        None => None,
+      };
+    }
+    if let Some(merge_group) = &self.merge_group {
+      return merge_group.head_ref.clone();
+    }
+    self
+      .run()
+      .and_then(|run| run.head_branch.as_deref())
+      .or(self.branch.as_deref())
+      .map(|branch| full_ref("branch", branch))
+  }
+
+  /** The check suite or workflow run the event is about. */
+  fn run(&self) -> Option<&MinimalRun> {
+    self
+      .check_run
+      .as_ref()
+      .and_then(|check_run| check_run.check_suite.as_ref())
+      .or(self.check_suite.as_ref())
+      .or(self.workflow_run.as_ref())
+  }
+
+  /** The commit a check, workflow run, merge group or deployment is for. */
+  fn head_sha(&self) -> Option<&str> {
+    self
+      .check_run
+      .as_ref()
+      .and_then(|check_run| check_run.head_sha.as_deref())
+      .or_else(|| self.run().and_then(|run| run.head_sha.as_deref()))
+      .or_else(|| {
+        self
+          .merge_group
+          .as_ref()
+          .and_then(|merge_group| merge_group.head_sha.as_deref())
+      })
+      .or_else(|| {
+        self
+          .deployment
+          .as_ref()
+          .and_then(|deployment| deployment.sha.as_deref())
+      })
+  }
+}
+
+/**
+ * Just enough of a payload to choose which secret to verify it with.  This is
+ * read before the HMAC check, so nothing here may be trusted for anything
+ * other than secret selection.
+ */
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct PayloadIdentity {
+  pub repository: Option<RepositoryIdentity>,
+  pub organization: Option<OrganizationIdentity>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct RepositoryIdentity {
+  pub full_name: String,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct OrganizationIdentity {
+  pub login: String,
+}
+
+impl PayloadIdentity {
+  pub fn repository_full_name(&self) -> Option<&str> {
+    self.repository.as_ref().map(|r| r.full_name.as_str())
+  }
+
+  /**
+   * Repositories owned by a user rather than an organization have no
+   * `organization` field, so the owner half of `full_name` is used instead.
+   */
+  pub fn organization_login(&self) -> Option<&str> {
+    self
+      .organization
+      .as_ref()
+      .map(|o| o.login.as_str())
+      .or_else(|| {
+        self
+          .repository_full_name()
+          .and_then(|name| name.split_once('/'))
+          .map(|(owner, _)| owner)
+      })
+  }
+}
+
 impl GitHubWebhookPayload {
+  pub fn repository(&self) -> Option<&Repository> {
+    match self {
+      GitHubWebhookPayload::Push(e) => Some(&e.repository),
+      GitHubWebhookPayload::PullRequest(e) => Some(&e.repository),
+      GitHubWebhookPayload::Issues(e) => Some(&e.repository),
+      GitHubWebhookPayload::IssueComment(e) => Some(&e.repository),
+      GitHubWebhookPayload::Create(e) => Some(&e.repository),
+      GitHubWebhookPayload::Delete(e) => Some(&e.repository),
+      GitHubWebhookPayload::Fork(e) => Some(&e.repository),
+      GitHubWebhookPayload::Release(e) => Some(&e.repository),
+      GitHubWebhookPayload::Ping(e) => e.repository.as_ref(),
+      GitHubWebhookPayload::PullRequestReview(e) => Some(&e.repository),
+      GitHubWebhookPayload::PullRequestReviewComment(e) => Some(&e.repository),
+      GitHubWebhookPayload::Status(e) => Some(&e.repository),
+      GitHubWebhookPayload::CheckRun(e) => Some(&e.repository),
+      GitHubWebhookPayload::CheckSuite(e) => Some(&e.repository),
+      GitHubWebhookPayload::WorkflowRun(e) => Some(&e.repository),
+      GitHubWebhookPayload::WorkflowDispatch(e) => Some(&e.repository),
+      GitHubWebhookPayload::RepositoryDispatch(e) => Some(&e.repository),
+      GitHubWebhookPayload::MergeGroup(e) => Some(&e.repository),
+      GitHubWebhookPayload::Deployment(e) => Some(&e.repository),
+      GitHubWebhookPayload::DeploymentStatus(e) => Some(&e.repository),
+      GitHubWebhookPayload::Generic(e) => e.repository.as_ref(),
+      GitHubWebhookPayload::Minimal(_) => None,
+    }
+  }
+
+  pub fn organization(&self) -> Option<&Organization> {
+    match self {
+      GitHubWebhookPayload::Push(e) => e.organization.as_ref(),
+      GitHubWebhookPayload::PullRequest(e) => e.organization.as_ref(),
+      GitHubWebhookPayload::Issues(e) => e.organization.as_ref(),
+      GitHubWebhookPayload::IssueComment(e) => e.organization.as_ref(),
+      GitHubWebhookPayload::Create(e) => e.organization.as_ref(),
+      GitHubWebhookPayload::Delete(e) => e.organization.as_ref(),
+      GitHubWebhookPayload::Fork(e) => e.organization.as_ref(),
+      GitHubWebhookPayload::Release(e) => e.organization.as_ref(),
+      GitHubWebhookPayload::Ping(e) => e.organization.as_ref(),
+      GitHubWebhookPayload::PullRequestReview(e) => e.organization.as_ref(),
+      GitHubWebhookPayload::PullRequestReviewComment(e) => {
+        e.organization.as_ref()
+      }
+      GitHubWebhookPayload::Status(e) => e.organization.as_ref(),
+      GitHubWebhookPayload::CheckRun(e) => e.organization.as_ref(),
+      GitHubWebhookPayload::CheckSuite(e) => e.organization.as_ref(),
+      GitHubWebhookPayload::WorkflowRun(e) => e.organization.as_ref(),
+      GitHubWebhookPayload::WorkflowDispatch(e) => e.organization.as_ref(),
+      GitHubWebhookPayload::RepositoryDispatch(e) => e.organization.as_ref(),
+      GitHubWebhookPayload::MergeGroup(e) => e.organization.as_ref(),
+      GitHubWebhookPayload::Deployment(e) => e.organization.as_ref(),
+      GitHubWebhookPayload::DeploymentStatus(e) => e.organization.as_ref(),
+      GitHubWebhookPayload::Generic(e) => e.organization.as_ref(),
+      GitHubWebhookPayload::Minimal(_) => None,
+    }
+  }
+
+  pub fn sender(&self) -> Option<&User> {
+    match self {
+      GitHubWebhookPayload::Push(e) => Some(&e.sender),
+      GitHubWebhookPayload::PullRequest(e) => Some(&e.sender),
+      GitHubWebhookPayload::Issues(e) => Some(&e.sender),
+      GitHubWebhookPayload::IssueComment(e) => Some(&e.sender),
+      GitHubWebhookPayload::Create(e) => Some(&e.sender),
+      GitHubWebhookPayload::Delete(e) => Some(&e.sender),
+      GitHubWebhookPayload::Fork(e) => Some(&e.sender),
+      GitHubWebhookPayload::Release(e) => Some(&e.sender),
+      GitHubWebhookPayload::Ping(e) => e.sender.as_ref(),
+      GitHubWebhookPayload::PullRequestReview(e) => Some(&e.sender),
+      GitHubWebhookPayload::PullRequestReviewComment(e) => Some(&e.sender),
+      GitHubWebhookPayload::Status(e) => Some(&e.sender),
+      GitHubWebhookPayload::CheckRun(e) => Some(&e.sender),
+      GitHubWebhookPayload::CheckSuite(e) => Some(&e.sender),
+      GitHubWebhookPayload::WorkflowRun(e) => Some(&e.sender),
+      GitHubWebhookPayload::WorkflowDispatch(e) => Some(&e.sender),
+      GitHubWebhookPayload::RepositoryDispatch(e) => Some(&e.sender),
+      GitHubWebhookPayload::MergeGroup(e) => Some(&e.sender),
+      GitHubWebhookPayload::Deployment(e) => Some(&e.sender),
+      GitHubWebhookPayload::DeploymentStatus(e) => Some(&e.sender),
+      GitHubWebhookPayload::Generic(e) => e.sender.as_ref(),
+      GitHubWebhookPayload::Minimal(_) => None,
+    }
+  }
+
+  /**
+   * The full git ref (`refs/heads/...` or `refs/tags/...`) for events that
+   * are about a ref.  Checks and workflow runs only name their branch, so
+   * theirs is always taken to be a branch.  Deployments are left out since
+   * their ref may just as well be a commit SHA.
+   */
+  pub fn git_ref(&self) -> Option<String> {
+    match self {
+      GitHubWebhookPayload::Push(e) => Some(e.ref_field.clone()),
+      GitHubWebhookPayload::Create(e) => {
+        Some(full_ref(&e.ref_type, &e.ref_field))
+      }
+      GitHubWebhookPayload::Delete(e) => {
+        Some(full_ref(&e.ref_type, &e.ref_field))
+      }
+      GitHubWebhookPayload::CheckRun(e) => e
+        .check_run
+        .check_suite
+        .head_branch
+        .as_ref()
+        .map(|branch| full_ref("branch", branch)),
+      GitHubWebhookPayload::CheckSuite(e) => e
+        .check_suite
+        .head_branch
+        .as_ref()
+        .map(|branch| full_ref("branch", branch)),
+      GitHubWebhookPayload::WorkflowRun(e) => e
+        .workflow_run
+        .head_branch
+        .as_ref()
+        .map(|branch| full_ref("branch", branch)),
+      GitHubWebhookPayload::WorkflowDispatch(e) => Some(e.ref_field.clone()),
+      GitHubWebhookPayload::RepositoryDispatch(e) => {
+        Some(full_ref("branch", &e.branch))
+      }
+      GitHubWebhookPayload::MergeGroup(e) => {
+        Some(e.merge_group.head_ref.clone())
+      }
+      GitHubWebhookPayload::Minimal(e) => e.git_ref(),
+      _ => None,
+    }
+  }
+
+  /**
+   * The branch a pull request, or a merge queue entry, is going into.
+   */
+  pub fn pull_request_base_branch(&self) -> Option<&str> {
+    match self {
+      GitHubWebhookPayload::PullRequest(e) => {
+        Some(&e.pull_request.base.ref_field)
+      }
+      GitHubWebhookPayload::PullRequestReview(e) => {
+        Some(&e.pull_request.base.ref_field)
+      }
+      GitHubWebhookPayload::PullRequestReviewComment(e) => {
+        Some(&e.pull_request.base.ref_field)
+      }
+      GitHubWebhookPayload::MergeGroup(e) => {
+        e.merge_group.base_ref.strip_prefix("refs/heads/")
+      }
+      GitHubWebhookPayload::Minimal(e) => {
+        match (&e.pull_request, &e.merge_group) {
+          (Some(pr), _) => Some(&pr.base.ref_field),
+          (None, Some(merge_group)) => merge_group
+            .base_ref
+            .as_deref()
+            .and_then(|r| r.strip_prefix("refs/heads/")),
+          (None, None) => None,
+        }
+      }
+      _ => None,
+    }
+  }
+
+  pub fn action(&self) -> Option<&str> {
+    match self {
+      GitHubWebhookPayload::PullRequest(e) => Some(e.action.as_str()),
+      GitHubWebhookPayload::Issues(e) => Some(&e.action),
+      GitHubWebhookPayload::IssueComment(e) => Some(&e.action),
+      GitHubWebhookPayload::Release(e) => Some(&e.action),
+      GitHubWebhookPayload::PullRequestReview(e) => Some(&e.action),
+      GitHubWebhookPayload::PullRequestReviewComment(e) => Some(&e.action),
+      GitHubWebhookPayload::CheckRun(e) => Some(&e.action),
+      GitHubWebhookPayload::CheckSuite(e) => Some(&e.action),
+      GitHubWebhookPayload::WorkflowRun(e) => Some(&e.action),
+      GitHubWebhookPayload::RepositoryDispatch(e) => Some(&e.action),
+      GitHubWebhookPayload::MergeGroup(e) => Some(&e.action),
+      GitHubWebhookPayload::Deployment(e) => Some(&e.action),
+      GitHubWebhookPayload::DeploymentStatus(e) => Some(&e.action),
+      GitHubWebhookPayload::Minimal(e) => e.action.as_deref(),
+      _ => None,
+    }
+  }
+
+  /**
+   * The branch a push or pull request builds, without `refs/heads/`.  For a
+   * pull request that is the head branch.
+   */
+  pub fn branch(&self) -> Option<String> {
+    match self {
+      GitHubWebhookPayload::PullRequest(e) => {
+        Some(e.pull_request.head.ref_field.clone())
+      }
+      GitHubWebhookPayload::PullRequestReview(e) => {
+        Some(e.pull_request.head.ref_field.clone())
+      }
+      GitHubWebhookPayload::PullRequestReviewComment(e) => {
+        Some(e.pull_request.head.ref_field.clone())
+      }
+      GitHubWebhookPayload::Minimal(MinimalPayload {
+        pull_request: Some(pr),
+        ..
+      }) => Some(pr.head.ref_field.clone()),
+      _ => self
+        .git_ref()
+        .and_then(|r| r.strip_prefix("refs/heads/").map(str::to_string)),
+    }
+  }
+
+  pub fn tag(&self) -> Option<String> {
+    match self {
+      GitHubWebhookPayload::Release(e) => Some(e.release.tag_name.clone()),
+      GitHubWebhookPayload::Minimal(MinimalPayload {
+        release: Some(release),
+        ..
+      }) => Some(release.tag_name.clone()),
+      _ => self
+        .git_ref()
+        .and_then(|r| r.strip_prefix("refs/tags/").map(str::to_string)),
+    }
+  }
+
+  /**
+   * Whether this is a push of a tag rather than of a branch.
+   */
+  pub fn is_tag_push(&self) -> bool {
+    matches!(self, GitHubWebhookPayload::Push(e)
+      if e.ref_field.starts_with("refs/tags/"))
+  }
+
+  /**
+   * The commit to build: what a push moved the ref to, or the head of a pull
+   * request.  Deleting a ref has no commit to build.  Pushing an annotated tag
+   * moves the ref to the tag object, so the commit it points at is taken from
+   * `head_commit` instead.
+   */
+  pub fn sha(&self) -> Option<&str> {
+    match self {
+      GitHubWebhookPayload::Push(e) if e.deleted => None,
+      GitHubWebhookPayload::Push(e) if self.is_tag_push() => Some(
+        e.head_commit
+          .as_ref()
+          .map(|c| c.id.as_str())
+          .unwrap_or(&e.after),
+      ),
+      GitHubWebhookPayload::Push(e) => Some(&e.after),
+      GitHubWebhookPayload::PullRequest(e) => Some(&e.pull_request.head.sha),
+      GitHubWebhookPayload::PullRequestReview(e) => {
+        Some(&e.pull_request.head.sha)
+      }
+      GitHubWebhookPayload::PullRequestReviewComment(e) => {
+        Some(&e.pull_request.head.sha)
+      }
+      GitHubWebhookPayload::Status(e) => Some(&e.sha),
+      GitHubWebhookPayload::CheckRun(e) => Some(&e.check_run.head_sha),
+      GitHubWebhookPayload::CheckSuite(e) => Some(&e.check_suite.head_sha),
+      GitHubWebhookPayload::WorkflowRun(e) => Some(&e.workflow_run.head_sha),
+      GitHubWebhookPayload::MergeGroup(e) => Some(&e.merge_group.head_sha),
+      GitHubWebhookPayload::Deployment(e) => Some(&e.deployment.sha),
+      GitHubWebhookPayload::DeploymentStatus(e) => Some(&e.deployment.sha),
+      GitHubWebhookPayload::Minimal(e) => match &e.pull_request {
+        Some(pr) => Some(&pr.head.sha),
+        None if e.deleted == Some(true) => None,
+        None
+          if e
+            .ref_field
+            .as_ref()
+            .is_some_and(|r| r.starts_with("refs/tags/")) =>
+        {
+          e.head_commit
+            .as_ref()
+            .map(|c| c.id.as_str())
+            .or(e.after.as_deref())
+        }
+        None => e
+          .after
+          .as_deref()
+          .or(e.sha.as_deref())
+          .or_else(|| e.head_sha()),
+      },
+      _ => None,
+    }
+  }
+
+  pub fn pull_request_number(&self) -> Option<u64> {
+    match self {
+      GitHubWebhookPayload::PullRequest(e) => Some(e.number),
+      GitHubWebhookPayload::PullRequestReview(e) => Some(e.pull_request.number),
+      GitHubWebhookPayload::PullRequestReviewComment(e) => {
+        Some(e.pull_request.number)
+      }
+      GitHubWebhookPayload::Minimal(e) => {
+        e.pull_request.as_ref().map(|pr| pr.number)
+      }
+      _ => None,
+    }
+  }
+
+  /**
+   * The organization login, or the repository owner for repositories that do
+   * not belong to an organization.
+   */
+  pub fn organization_login(&self) -> Option<&str> {
+    match self {
+      GitHubWebhookPayload::Minimal(e) => e
+        .organization
+        .as_ref()
+        .map(|o| o.login.as_str())
+        .or_else(|| {
+          self
+            .repository_full_name()
+            .and_then(|name| name.split_once('/'))
+            .map(|(owner, _)| owner)
+        }),
+      _ => self
+        .organization()
+        .map(|o| o.login.as_str())
+        .or_else(|| self.repository().map(|r| r.owner.login.as_str())),
+    }
+  }
+
+  // The accessors below also work on a `Minimal` payload, so anything that
+  // has to cope with `--validation lenient` uses them rather than reaching
+  // into `repository()` or `sender()`.
+
+  pub fn repository_full_name(&self) -> Option<&str> {
+    match self {
+      GitHubWebhookPayload::Minimal(e) => {
+        e.repository.as_ref().map(|r| r.full_name.as_str())
+      }
+      _ => self.repository().map(|r| r.full_name.as_str()),
+    }
+  }
+
+  pub fn repository_name(&self) -> Option<&str> {
+    match self {
+      GitHubWebhookPayload::Minimal(e) => e
+        .repository
+        .as_ref()
+        .and_then(|r| r.name.as_deref())
+        .or_else(|| {
+          self
+            .repository_full_name()
+            .and_then(|name| name.split_once('/'))
+            .map(|(_, name)| name)
+        }),
+      _ => self.repository().map(|r| r.name.as_str()),
+    }
+  }
+
+  pub fn clone_url(&self) -> Option<&str> {
+    match self {
+      GitHubWebhookPayload::Minimal(e) => {
+        e.repository.as_ref().and_then(|r| r.clone_url.as_deref())
+      }
+      _ => self.repository().map(|r| r.clone_url.as_str()),
+    }
+  }
+
+  pub fn ssh_url(&self) -> Option<&str> {
+    match self {
+      GitHubWebhookPayload::Minimal(e) => {
+        e.repository.as_ref().and_then(|r| r.ssh_url.as_deref())
+      }
+      _ => self.repository().map(|r| r.ssh_url.as_str()),
+    }
+  }
+
+  /**
+   * The login of the `organization` field alone, without falling back to the
+   * repository owner like `organization_login` does.
+   */
+  pub fn explicit_organization_login(&self) -> Option<&str> {
+    match self {
+      GitHubWebhookPayload::Minimal(e) => {
+        e.organization.as_ref().map(|o| o.login.as_str())
+      }
+      _ => self.organization().map(|o| o.login.as_str()),
+    }
+  }
+
+  pub fn sender_login(&self) -> Option<&str> {
+    match self {
+      GitHubWebhookPayload::Minimal(e) => {
+        e.sender.as_ref().map(|u| u.login.as_str())
+      }
+      _ => self.sender().map(|u| u.login.as_str()),
+    }
+  }
+
+  pub fn sender_is_bot(&self) -> bool {
+    match self {
+      GitHubWebhookPayload::Minimal(e) => e
+        .sender
+        .as_ref()
+        .is_some_and(|u| u.user_type.as_deref() == Some("Bot")),
+      _ => self.sender().is_some_and(|u| u.user_type == "Bot"),
+    }
+  }
+
+  pub fn is_draft_pull_request(&self) -> bool {
+    match self {
+      GitHubWebhookPayload::PullRequest(e) => e.pull_request.draft,
+      GitHubWebhookPayload::PullRequestReview(e) => {
+        e.pull_request.draft == Some(true)
+      }
+      GitHubWebhookPayload::PullRequestReviewComment(e) => {
+        e.pull_request.draft == Some(true)
+      }
+      GitHubWebhookPayload::Minimal(e) => e
+        .pull_request
+        .as_ref()
+        .is_some_and(|pr| pr.draft == Some(true)),
+      _ => false,
+    }
+  }
+
+  /**
+   * The message of every commit a push lists, and of its head commit.
+   */
+  pub fn commit_messages(&self) -> Vec<&str> {
+    match self {
+      GitHubWebhookPayload::Push(e) => e
+        .head_commit
+        .iter()
+        .chain(&e.commits)
+        .map(|c| c.message.as_str())
+        .collect(),
+      GitHubWebhookPayload::Minimal(e) => e
+        .head_commit
+        .iter()
+        .chain(&e.commits)
+        .filter_map(|c| c.message.as_deref())
+        .collect(),
+      _ => vec![],
+    }
+  }
+
+  /**
+   * Every path a push added, modified or removed, or `None` for anything
+   * that does not list all its changes: a push without commits (such as a
+   * deletion), or one with more than the `MAX_PUSH_COMMITS` GitHub lists.
+   * Path filters forward those.
+   */
+  pub fn changed_paths(&self) -> Option<Vec<&str>> {
+    let commits = match self {
+      GitHubWebhookPayload::Push(e) => e.commits.len(),
+      GitHubWebhookPayload::Minimal(e) => e.commits.len(),
+      _ => 0,
+    };
+    if commits >= MAX_PUSH_COMMITS {
+      return None;
+    }
+    let paths: Vec<&str> = match self {
+      GitHubWebhookPayload::Push(e) => e
+        .commits
+        .iter()
+        .flat_map(|c| c.added.iter().chain(&c.modified).chain(&c.removed))
+        .map(String::as_str)
+        .collect(),
+      GitHubWebhookPayload::Minimal(e) => e
+        .commits
+        .iter()
+        .flat_map(|c| c.added.iter().chain(&c.modified).chain(&c.removed))
+        .map(String::as_str)
+        .collect(),
+      _ => return None,
+    };
+    if paths.is_empty() {
+      None
+    } else {
+      Some(paths)
+    }
+  }
+
   pub fn validate_required_fields(&self) -> bool {
     match self {
       GitHubWebhookPayload::Push(e) => !e.ref_field.is_empty(),
@@ -432,9 +1617,273 @@ impl GitHubWebhookPayload {
       }
       GitHubWebhookPayload::Fork(_) => true,
       GitHubWebhookPayload::Release(e) => !e.action.is_empty(),
+      GitHubWebhookPayload::Ping(e) => !e.zen.is_empty(),
+      GitHubWebhookPayload::PullRequestReview(e) => e.pull_request.number > 0,
+      GitHubWebhookPayload::PullRequestReviewComment(e) => {
+        e.pull_request.number > 0
+      }
+      GitHubWebhookPayload::Status(e) => !e.sha.is_empty(),
+      GitHubWebhookPayload::CheckRun(e) => !e.check_run.head_sha.is_empty(),
+      GitHubWebhookPayload::CheckSuite(e) => !e.check_suite.head_sha.is_empty(),
+      GitHubWebhookPayload::WorkflowRun(e) => {
+        !e.workflow_run.head_sha.is_empty()
+      }
+      GitHubWebhookPayload::WorkflowDispatch(e) => !e.ref_field.is_empty(),
+      GitHubWebhookPayload::RepositoryDispatch(e) => !e.action.is_empty(),
+      GitHubWebhookPayload::MergeGroup(e) => !e.merge_group.head_sha.is_empty(),
+      GitHubWebhookPayload::Deployment(e) => !e.deployment.sha.is_empty(),
+      GitHubWebhookPayload::DeploymentStatus(e) => !e.deployment.sha.is_empty(),
       GitHubWebhookPayload::Generic(e) => {
         e.repository.is_some() || e.sender.is_some()
       }
+      GitHubWebhookPayload::Minimal(e) => {
+        e.repository.is_some() || e.sender.is_some()
+      }
+    }
+  }
+}
+
+/**
+ * `create` and `delete` events carry a short ref name plus its type, rather
+ * than the full ref that `push` uses.
+ */
+fn full_ref(ref_type: &str, name: &str) -> String {
+  match ref_type {
+    "tag" => format!("refs/tags/{}", name),
+    _ => format!("refs/heads/{}", name),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::test_support::{edited_fixture, fixture};
+
+  fn pull_request_event(bytes: &[u8]) -> PullRequestEvent {
+    fixture(bytes)
+  }
+
+  #[test]
+  fn pull_request_fixtures_parse() {
+    let fixtures: [(&[u8], PullRequestAction); 5] = [
+      (
+        include_bytes!("../tests/fixtures/pull_request.opened.json"),
+        PullRequestAction::Opened,
+      ),
+      (
+        include_bytes!("../tests/fixtures/pull_request.synchronize.json"),
+        PullRequestAction::Synchronize,
+      ),
+      (
+        include_bytes!("../tests/fixtures/pull_request.closed.json"),
+        PullRequestAction::Closed,
+      ),
+      (
+        include_bytes!("../tests/fixtures/pull_request.labeled.json"),
+        PullRequestAction::Labeled,
+      ),
+      (
+        include_bytes!("../tests/fixtures/pull_request.ready_for_review.json"),
+        PullRequestAction::ReadyForReview,
+      ),
+    ];
+    for (fixture, action) in fixtures {
+      let event = pull_request_event(fixture);
+      assert_eq!(event.action, action);
+      assert_eq!(event.pull_request.head.ref_field, "changes");
+      assert_eq!(event.pull_request.base.ref_field, "master");
     }
   }
+
+  #[test]
+  fn pull_request_payload_accessors() {
+    let payload = GitHubWebhookPayload::PullRequest(pull_request_event(
+      include_bytes!("../tests/fixtures/pull_request.synchronize.json"),
+    ));
+    assert_eq!(payload.action(), Some("synchronize"));
+    assert_eq!(payload.pull_request_base_branch(), Some("master"));
+    assert_eq!(payload.branch().as_deref(), Some("changes"));
+    assert_eq!(
+      payload.sha(),
+      Some("34c5c7793cb3b279e22454cb6750c80560547b3a")
+    );
+    assert_eq!(payload.pull_request_number(), Some(2));
+  }
+
+  #[test]
+  fn unknown_pull_request_action_is_kept() {
+    let action: PullRequestAction =
+      serde_json::from_str("\"some_future_action\"").unwrap();
+    assert_eq!(
+      action,
+      PullRequestAction::Other("some_future_action".to_string())
+    );
+    assert_eq!(action.as_str(), "some_future_action");
+  }
+
+  #[test]
+  fn push_fixture_parses() {
+    let event: PushEvent =
+      serde_json::from_slice(include_bytes!("../tests/fixtures/push.json"))
+        .unwrap();
+    assert_eq!(event.ref_field, "refs/heads/master");
+    assert_eq!(event.commits[0].removed, vec!["CONTRIBUTING.md"]);
+  }
+
+  #[test]
+  fn minimal_payload_agrees_with_typed_payload() {
+    let push: &[u8] = include_bytes!("../tests/fixtures/push.json");
+    let tag: &[u8] = include_bytes!("../tests/fixtures/push.tag.json");
+    let pull_request: &[u8] =
+      include_bytes!("../tests/fixtures/pull_request.synchronize.json");
+    let review: &[u8] =
+      include_bytes!("../tests/fixtures/pull_request_review.submitted.json");
+    let check_run: &[u8] =
+      include_bytes!("../tests/fixtures/check_run.completed.json");
+    let workflow_run: &[u8] =
+      include_bytes!("../tests/fixtures/workflow_run.completed.json");
+    let merge_group: &[u8] =
+      include_bytes!("../tests/fixtures/merge_group.checks_requested.json");
+    let deployment_status: &[u8] =
+      include_bytes!("../tests/fixtures/deployment_status.created.json");
+    let fixtures = [
+      (push, GitHubWebhookPayload::Push(fixture(push))),
+      (tag, GitHubWebhookPayload::Push(fixture(tag))),
+      (
+        pull_request,
+        GitHubWebhookPayload::PullRequest(fixture(pull_request)),
+      ),
+      (
+        review,
+        GitHubWebhookPayload::PullRequestReview(fixture(review)),
+      ),
+      (
+        check_run,
+        GitHubWebhookPayload::CheckRun(fixture(check_run)),
+      ),
+      (
+        workflow_run,
+        GitHubWebhookPayload::WorkflowRun(fixture(workflow_run)),
+      ),
+      (
+        merge_group,
+        GitHubWebhookPayload::MergeGroup(fixture(merge_group)),
+      ),
+      (
+        deployment_status,
+        GitHubWebhookPayload::DeploymentStatus(fixture(deployment_status)),
+      ),
+    ];
+    for (bytes, typed) in fixtures {
+      let minimal = GitHubWebhookPayload::Minimal(fixture(bytes));
+      assert!(typed.git_ref().is_some() || typed.sha().is_some());
+      assert_eq!(minimal.git_ref(), typed.git_ref());
+      assert_eq!(minimal.branch(), typed.branch());
+      assert_eq!(minimal.tag(), typed.tag());
+      assert_eq!(minimal.sha(), typed.sha());
+      assert_eq!(minimal.action(), typed.action());
+      assert_eq!(minimal.pull_request_number(), typed.pull_request_number());
+      assert_eq!(
+        minimal.pull_request_base_branch(),
+        typed.pull_request_base_branch()
+      );
+      assert_eq!(minimal.repository_full_name(), typed.repository_full_name());
+      assert_eq!(minimal.repository_name(), typed.repository_name());
+      assert_eq!(minimal.organization_login(), typed.organization_login());
+      assert_eq!(minimal.sender_login(), typed.sender_login());
+      assert_eq!(minimal.sender_is_bot(), typed.sender_is_bot());
+      assert_eq!(
+        minimal.is_draft_pull_request(),
+        typed.is_draft_pull_request()
+      );
+      assert_eq!(minimal.commit_messages(), typed.commit_messages());
+      assert_eq!(minimal.changed_paths(), typed.changed_paths());
+    }
+  }
+
+  #[test]
+  fn minimal_payload_ignores_schema_drift() {
+    let bytes =
+      edited_fixture(include_bytes!("../tests/fixtures/push.json"), |p| {
+        p["sender"].as_object_mut().unwrap().remove("gists_url");
+        p["repository"]["has_projects"] = serde_json::json!("yes");
+      });
+
+    let mut de = serde_json::Deserializer::from_slice(&bytes);
+    let error =
+      serde_path_to_error::deserialize::<_, PushEvent>(&mut de).unwrap_err();
+    assert_eq!(error.path().to_string(), "repository.has_projects");
+
+    let minimal = GitHubWebhookPayload::Minimal(fixture(&bytes));
+    assert_eq!(minimal.branch().as_deref(), Some("master"));
+    assert_eq!(minimal.sender_login(), Some("Codertocat"));
+  }
+
+  #[test]
+  fn tag_push_builds_the_tagged_commit() {
+    let payload = GitHubWebhookPayload::Push(fixture(include_bytes!(
+      "../tests/fixtures/push.tag.json"
+    )));
+    assert!(payload.is_tag_push());
+    assert_eq!(payload.tag().as_deref(), Some("v1.0.0"));
+    assert_eq!(payload.branch(), None);
+    assert_eq!(
+      payload.sha(),
+      Some("6113728f27ae82c7b1a177c8d03f9e96e0adf246")
+    );
+  }
+
+  #[test]
+  fn ci_event_fixtures_parse() {
+    let ping = GitHubWebhookPayload::Ping(fixture(include_bytes!(
+      "../tests/fixtures/ping.json"
+    )));
+    assert!(ping.validate_required_fields());
+    assert_eq!(
+      ping.repository().map(|r| r.full_name.as_str()),
+      Some("Codertocat/Hello-World")
+    );
+
+    let review = GitHubWebhookPayload::PullRequestReview(fixture(
+      include_bytes!("../tests/fixtures/pull_request_review.submitted.json"),
+    ));
+    assert_eq!(review.action(), Some("submitted"));
+    assert_eq!(review.pull_request_number(), Some(2));
+    assert_eq!(review.branch().as_deref(), Some("changes"));
+    assert_eq!(review.pull_request_base_branch(), Some("master"));
+
+    let check_run = GitHubWebhookPayload::CheckRun(fixture(include_bytes!(
+      "../tests/fixtures/check_run.completed.json"
+    )));
+    assert_eq!(check_run.git_ref().as_deref(), Some("refs/heads/changes"));
+    assert_eq!(
+      check_run.sha(),
+      Some("ec26c3e57ca3a959ca5aad62de7213c562f8c821")
+    );
+
+    let workflow_run = GitHubWebhookPayload::WorkflowRun(fixture(
+      include_bytes!("../tests/fixtures/workflow_run.completed.json"),
+    ));
+    assert_eq!(workflow_run.action(), Some("completed"));
+    assert_eq!(workflow_run.branch().as_deref(), Some("main"));
+
+    let merge_group = GitHubWebhookPayload::MergeGroup(fixture(
+      include_bytes!("../tests/fixtures/merge_group.checks_requested.json"),
+    ));
+    assert_eq!(merge_group.pull_request_base_branch(), Some("master"));
+    assert_eq!(
+      merge_group.sha(),
+      Some("5b4a1c2d3e4f5a6b7c8d9e0f1a2b3c4d5e6f7a8b")
+    );
+
+    let deployment_status = GitHubWebhookPayload::DeploymentStatus(fixture(
+      include_bytes!("../tests/fixtures/deployment_status.created.json"),
+    ));
+    assert_eq!(deployment_status.action(), Some("created"));
+    assert_eq!(deployment_status.git_ref(), None);
+    assert_eq!(
+      deployment_status.sha(),
+      Some("f95f852bd8fca8fcc58a9a2d6c842781e32a215e")
+    );
+  }
 }
@@ -0,0 +1,254 @@
+use actix_web::{web, HttpResponse};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::time::{Duration, Instant};
+use tokio::sync::Mutex;
+use tracing::warn;
+
+use crate::jenkins::{error_chain, JenkinsTarget};
+use crate::queue::DeliveryQueue;
+use crate::state::{AppState, StateHandle};
+
+/**
+ * Probes the Jenkins targets for `/readyz`.  Results are cached for
+ * `cache_ttl`, and for no longer than the configuration generation they were
+ * taken under, so that a load balancer polling every second does not turn into
+ * a stream of requests to Jenkins.
+ */
+pub struct Readiness {
+  timeout: Duration,
+  cache_ttl: Duration,
+  cache: Mutex<Option<CachedProbe>>,
+}
+
+struct CachedProbe {
+  generation: u64,
+  taken: Instant,
+  targets: Vec<TargetHealth>,
+}
+
+#[derive(Clone, Serialize)]
+struct TargetHealth {
+  target: String,
+  reachable: bool,
+  status: Option<u16>,
+  error: Option<String>,
+  duration_ms: u64,
+  checked_at: DateTime<Utc>,
+}
+
+#[derive(Serialize)]
+struct ReadinessReport {
+  ready: bool,
+  generation: u64,
+  last_reload_error: Option<String>,
+  queue_writable: Option<bool>,
+  queue_depth: Option<usize>,
+  dead_letters: Option<usize>,
+  targets: Vec<TargetHealth>,
+}
+
+impl Readiness {
+  pub fn new(timeout: Duration, cache_ttl: Duration) -> Readiness {
+    Readiness {
+      timeout,
+      cache_ttl,
+      cache: Mutex::new(None),
+    }
+  }
+
+  async fn targets(&self, state: &AppState) -> Vec<TargetHealth> {
+    let generation = state.generation;
+    // Holding the lock while probing makes concurrent callers wait for this
+    // probe instead of starting their own.
+    let mut cache = self.cache.lock().await;
+    if let Some(cached) = cache.as_ref() {
+      if cached.generation == generation
+        && cached.taken.elapsed() < self.cache_ttl
+      {
+        return cached.targets.clone();
+      }
+    }
+    let targets = state.routes.targets();
+    let probes = targets
+      .iter()
+      .map(|target| self.probe(&state.client, target));
+    let results = futures_util::future::join_all(probes).await;
+    *cache = Some(CachedProbe {
+      generation,
+      taken: Instant::now(),
+      targets: results.clone(),
+    });
+    results
+  }
+
+  /**
+   * A GET of the webhook endpoint.  Jenkins answers that with an error, since
+   * it only takes POSTs, but any answer short of a 5xx shows that it is up.
+   */
+  async fn probe(
+    &self,
+    client: &reqwest::Client,
+    target: &JenkinsTarget,
+  ) -> TargetHealth {
+    let started = Instant::now();
+    let result = target
+      .authorize(client.get(target.probe_url()))
+      .timeout(self.timeout)
+      .send()
+      .await;
+    let duration_ms = started.elapsed().as_millis() as u64;
+    let (reachable, status, error) = match result {
+      Ok(response) if response.status().is_server_error() => (
+        false,
+        Some(response.status().as_u16()),
+        Some(format!("Jenkins answered {}", response.status())),
+      ),
+      Ok(response) => (true, Some(response.status().as_u16()), None),
+      Err(e) => (false, None, Some(error_chain(&e))),
+    };
+    if !reachable {
+      warn!(
+        target = %target.name,
+        error = error.as_deref().unwrap_or(""),
+        "Readiness probe failed",
+      );
+    }
+    TargetHealth {
+      target: target.name.clone(),
+      reachable,
+      status,
+      error,
+      duration_ms,
+      checked_at: Utc::now(),
+    }
+  }
+}
+
+/**
+ * Liveness: the process is up and serving requests.
+ */
+pub async fn healthz() -> HttpResponse {
+  HttpResponse::Ok().body("ok")
+}
+
+/**
+ * Readiness: webhooks can be accepted.  With the queue enabled that is
+ * whenever it can be written to, since it holds on to deliveries until
+ * Jenkins is back, and target reachability is reported for information only.
+ * Without it, every Jenkins target has to answer.  The queue's depth and dead
+ * letter count are reported alongside, from its counters.
+ */
+pub async fn readyz(
+  state: web::Data<StateHandle>,
+  readiness: web::Data<Readiness>,
+  queue: Option<web::Data<DeliveryQueue>>,
+) -> HttpResponse {
+  let current = state.current();
+  let targets = readiness.targets(&current).await;
+  let queue_writable = match &queue {
+    Some(queue) => {
+      let queue = queue.clone();
+      Some(
+        web::block(move || queue.is_writable())
+          .await
+          .unwrap_or(false),
+      )
+    }
+    None => None,
+  };
+  let report = ReadinessReport {
+    ready: queue_writable
+      .unwrap_or_else(|| targets.iter().all(|t| t.reachable)),
+    generation: current.generation,
+    last_reload_error: state.last_reload_error(),
+    queue_writable,
+    queue_depth: queue.as_ref().map(|q| q.depth()),
+    dead_letters: queue.as_ref().map(|q| q.dead_letter_count()),
+    targets,
+  };
+  if report.ready {
+    HttpResponse::Ok().json(report)
+  } else {
+    HttpResponse::ServiceUnavailable().json(report)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::args::Args;
+  use crate::test_support::{FakeJenkins, TempDir};
+  use actix_web::http::StatusCode;
+  use actix_web::{test, App};
+  use clap::Parser;
+
+  /** `/readyz` with a single target that always answers 500. */
+  async fn readyz_while_jenkins_is_down(
+    queue: Option<DeliveryQueue>,
+  ) -> (StatusCode, serde_json::Value) {
+    let jenkins = FakeJenkins::start();
+    let url = format!("{}/down/", jenkins.url);
+    let args = Args::try_parse_from([
+      "github-to-jenkins-webhook",
+      "--github-secret",
+      "secret",
+      "--jenkins-url",
+      &url,
+    ])
+    .unwrap();
+    let state = StateHandle::new(AppState::build(&args, 0).unwrap(), args);
+    let mut app =
+      App::new()
+        .app_data(web::Data::new(state))
+        .app_data(web::Data::new(Readiness::new(
+          Duration::from_secs(5),
+          Duration::ZERO,
+        )));
+    if let Some(queue) = queue {
+      app = app.app_data(web::Data::new(queue));
+    }
+    let app =
+      test::init_service(app.route("/readyz", web::get().to(readyz))).await;
+    let response = test::call_service(
+      &app,
+      test::TestRequest::get().uri("/readyz").to_request(),
+    )
+    .await;
+    let status = response.status();
+    (status, test::read_body_json(response).await)
+  }
+
+  #[actix_web::test]
+  async fn without_a_queue_unreachable_targets_are_unready() {
+    let (status, report) = readyz_while_jenkins_is_down(None).await;
+    assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+    assert_eq!(report["ready"], false);
+    assert_eq!(report["targets"][0]["reachable"], false);
+    assert!(report["queue_writable"].is_null());
+  }
+
+  #[actix_web::test]
+  async fn with_a_queue_unreachable_targets_are_only_reported() {
+    let dir = TempDir::new();
+    let queue = DeliveryQueue::open(dir.path(), Duration::from_secs(60));
+    let (status, report) =
+      readyz_while_jenkins_is_down(Some(queue.unwrap())).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(report["ready"], true);
+    assert_eq!(report["queue_writable"], true);
+    assert_eq!(report["queue_depth"], 0);
+    assert_eq!(report["targets"][0]["reachable"], false);
+  }
+
+  #[actix_web::test]
+  async fn an_unwritable_queue_is_unready() {
+    let dir = TempDir::new();
+    let queue = DeliveryQueue::open(dir.path(), Duration::from_secs(60));
+    std::fs::remove_dir_all(dir.path()).unwrap();
+    let (status, report) =
+      readyz_while_jenkins_is_down(Some(queue.unwrap())).await;
+    assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+    assert_eq!(report["queue_writable"], false);
+  }
+}
@@ -0,0 +1,1199 @@
+use actix_web::{web, HttpRequest};
+use reqwest::header::{
+  HeaderMap as RHeaderMap, HeaderName as RHeaderName,
+  HeaderValue as RHeaderValue, CONTENT_TYPE, LOCATION,
+};
+use reqwest::{RequestBuilder, StatusCode};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::sync::{Arc, LazyLock};
+use std::time::{Duration, Instant};
+use tokio::sync::{mpsc, Semaphore};
+use tracing::{
+  debug, field, info, info_span, instrument, warn, Instrument, Span,
+};
+use url::Url;
+
+use crate::error::ProxyError;
+use crate::jenkins_auth::{CrumbCache, JenkinsAuth};
+use crate::metrics::METRICS;
+use crate::variables::{flattened_payload, forwarded_variables};
+use crate::webhook::GITHUB_DELIVERY_HEADER;
+
+/**
+ * A Jenkins controller that webhooks are relayed to.  `url` is the fully
+ * constructed endpoint, while `name` is what shows up in logs and summaries.
+ * Clones share the crumb cache.
+ */
+#[derive(Clone, Debug)]
+pub struct JenkinsTarget {
+  pub name: String,
+  pub url: String,
+  /** The Jenkins root URL, with a trailing slash. */
+  pub root_url: String,
+  pub endpoint: Endpoint,
+  pub auth: Option<JenkinsAuth>,
+  /** Set when POSTs need a CSRF crumb. */
+  pub crumbs: Option<Arc<CrumbCache>>,
+}
+
+impl JenkinsTarget {
+  pub fn from_base_url(base_url: &str) -> Result<JenkinsTarget, ProxyError> {
+    JenkinsTarget::named(base_url, base_url)
+  }
+
+  pub fn named(
+    name: &str,
+    base_url: &str,
+  ) -> Result<JenkinsTarget, ProxyError> {
+    if !validate_jenkins_url(base_url)? {
+      return Err(ProxyError::InvalidJenkinsUrl);
+    }
+    Ok(JenkinsTarget::from_url(
+      name,
+      &construct_jenkins_url(base_url)?,
+    ))
+  }
+
+  /**
+   * A target posting to `endpoint` of the Jenkins at `base_url`.  Whatever
+   * the endpoint needs beyond that comes from the route, see `for_route`.
+   */
+  pub fn for_endpoint(
+    name: &str,
+    base_url: &str,
+    endpoint: Endpoint,
+  ) -> Result<JenkinsTarget, ProxyError> {
+    if let Endpoint::GitHubWebhook = endpoint {
+      return JenkinsTarget::named(name, base_url);
+    }
+    if !validate_jenkins_url(base_url)? {
+      return Err(ProxyError::InvalidJenkinsUrl);
+    }
+    let root_url = jenkins_root_url(base_url)?;
+    Ok(JenkinsTarget {
+      name: name.to_string(),
+      url: match endpoint {
+        Endpoint::GenericWebhookTrigger { .. } => {
+          format!("{}{}", root_url, GENERIC_WEBHOOK_TRIGGER_PATH)
+        }
+        // Until a route names the job.
+        _ => root_url.clone(),
+      },
+      root_url,
+      endpoint,
+      auth: None,
+      crumbs: None,
+    })
+  }
+
+  /**
+   * A target for an already constructed webhook endpoint, as kept in the
+   * delivery queue.
+   */
+  pub fn from_url(name: &str, url: &str) -> JenkinsTarget {
+    let trimmed = url.trim_end_matches('/');
+    let root_url = trimmed
+      .strip_suffix("github-webhook")
+      .or_else(|| trimmed.strip_suffix("ghprbhook"))
+      .or_else(|| trimmed.strip_suffix(GENERIC_WEBHOOK_TRIGGER_PATH))
+      .unwrap_or(url)
+      .to_string();
+    JenkinsTarget {
+      name: name.to_string(),
+      url: url.to_string(),
+      root_url,
+      endpoint: Endpoint::GitHubWebhook,
+      auth: None,
+      crumbs: None,
+    }
+  }
+
+  /**
+   * This target as used by one route, which supplies what the endpoint needs
+   * beyond the target itself.
+   */
+  pub fn for_route(
+    mut self,
+    route: &str,
+    options: &RouteEndpointOptions,
+  ) -> Result<JenkinsTarget, String> {
+    match &mut self.endpoint {
+      Endpoint::GitHubWebhook if options.flatten => Err(format!(
+        "Route '{}' flattens payloads, but target '{}' takes GitHub webhooks \
+         as they are",
+        route, self.name,
+      )),
+      Endpoint::GitHubWebhook => Ok(self),
+      Endpoint::GenericWebhookTrigger { token, flatten } => {
+        if options.token.is_none() {
+          return Err(format!(
+            "Route '{}' forwards to Generic Webhook Trigger target '{}' but \
+             has no token",
+            route, self.name,
+          ));
+        }
+        token.clone_from(&options.token);
+        *flatten = options.flatten;
+        Ok(self)
+      }
+      Endpoint::BuildWithParameters { parameters } => {
+        let Some(job) = &options.job else {
+          return Err(format!(
+            "Route '{}' forwards to buildWithParameters target '{}' but has \
+             no job",
+            route, self.name,
+          ));
+        };
+        *parameters = if options.parameters.is_empty() {
+          DEFAULT_BUILD_PARAMETERS
+            .iter()
+            .map(|(name, variable)| (name.to_string(), variable.to_string()))
+            .collect()
+        } else {
+          options.parameters.clone()
+        };
+        self.url = format!(
+          "{}{}buildWithParameters",
+          self.root_url,
+          job_path(job).map_err(|e| format!("Route '{}': {}", route, e))?,
+        );
+        Ok(self)
+      }
+    }
+  }
+
+  /**
+   * What the readiness probe GETs.  Anything but the GitHub plugin's endpoint
+   * may start a build on a GET, so those targets are probed at the root.
+   */
+  pub fn probe_url(&self) -> &str {
+    match self.endpoint {
+      Endpoint::GitHubWebhook => &self.url,
+      _ => &self.root_url,
+    }
+  }
+
+  /**
+   * Authenticates as `auth`, and fetches a crumb for POSTs if `crumb` is set
+   * or, when it is not, if there are credentials.  Anonymous POSTs to
+   * `/github-webhook/` are exempt from CSRF protection.
+   */
+  pub fn with_auth(
+    mut self,
+    auth: Option<JenkinsAuth>,
+    crumb: Option<bool>,
+  ) -> JenkinsTarget {
+    if crumb.unwrap_or(auth.is_some()) {
+      self.crumbs = Some(Arc::new(CrumbCache::default()));
+    }
+    self.auth = auth;
+    self
+  }
+
+  /**
+   * Adds the target's credentials, if any, to a request.
+   */
+  pub fn authorize(&self, request: RequestBuilder) -> RequestBuilder {
+    match &self.auth {
+      Some(auth) => auth.apply(request),
+      None => request,
+    }
+  }
+}
+
+const GENERIC_WEBHOOK_TRIGGER_PATH: &str = "generic-webhook-trigger/invoke";
+
+/** Build parameters for routes that do not list their own. */
+const DEFAULT_BUILD_PARAMETERS: [(&str, &str); 5] = [
+  ("BRANCH", "branch"),
+  ("SHA", "sha"),
+  ("PR_NUMBER", "pr_number"),
+  ("REPO", "repository"),
+  ("SENDER", "sender"),
+];
+
+/** How often, and for how long, a queued build is checked on. */
+const QUEUE_ITEM_POLL_INTERVAL: Duration = Duration::from_secs(2);
+const QUEUE_ITEM_TIMEOUT: Duration = Duration::from_secs(10 * 60);
+
+/**
+ * How many queued builds are checked on at once.  Past that, builds are
+ * still triggered but their numbers are not logged.
+ */
+const MAX_QUEUE_ITEM_FOLLOWERS: usize = 64;
+static QUEUE_ITEM_FOLLOWERS: LazyLock<Arc<Semaphore>> =
+  LazyLock::new(|| Arc::new(Semaphore::new(MAX_QUEUE_ITEM_FOLLOWERS)));
+
+/**
+ * What a route adds to the targets it forwards to, for endpoints that need
+ * more than the target itself.
+ */
+#[derive(Clone, Debug, Default)]
+pub struct RouteEndpointOptions {
+  /** The Generic Webhook Trigger token. */
+  pub token: Option<String>,
+  /** Whether Generic Webhook Trigger targets get the flattened payload. */
+  pub flatten: bool,
+  /** The job buildWithParameters targets build, as `folder/name`. */
+  pub job: Option<String>,
+  /** Build parameter names mapped to payload variable names. */
+  pub parameters: BTreeMap<String, String>,
+}
+
+/**
+ * The Jenkins endpoint a target posts to, which decides what it is sent.
+ */
+#[derive(Clone, Debug)]
+pub enum Endpoint {
+  /**
+   * `/github-webhook/` of the GitHub plugin (or `/ghprbhook/` of the GitHub
+   * Pull Request Builder), which gets the webhook exactly as GitHub sent it.
+   */
+  GitHubWebhook,
+  /**
+   * `/generic-webhook-trigger/invoke` of the Generic Webhook Trigger plugin.
+   * The token picks the jobs to trigger and is sent as a query parameter.
+   * With `flatten`, the body is the payload's variables (see
+   * `payload_variables`) instead of the webhook itself.
+   */
+  GenericWebhookTrigger {
+    token: Option<String>,
+    flatten: bool,
+  },
+  /**
+   * `/job/<path>/buildWithParameters`, which starts a build of one job
+   * directly.  The body is form encoded build parameters, each taken from a
+   * payload variable, and parameters whose variable the event lacks are left
+   * for Jenkins to default.
+   */
+  BuildWithParameters {
+    parameters: BTreeMap<String, String>,
+  },
+}
+
+/**
+ * The `mode` of a named target in the config file, which picks its
+ * `Endpoint`.
+ */
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TargetMode {
+  GitHubWebhook,
+  GenericWebhookTrigger,
+  BuildWithParameters,
+}
+
+/**
+ * Decides when a fan-out counts as delivered, and therefore what GitHub is
+ * told about it.
+ */
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FanOutMode {
+  FirstSuccess,
+  All,
+  Quorum(usize),
+}
+
+impl FanOutMode {
+  pub fn name(&self) -> String {
+    match self {
+      FanOutMode::FirstSuccess => "first-success".to_string(),
+      FanOutMode::All => "all".to_string(),
+      FanOutMode::Quorum(n) => format!("quorum({})", n),
+    }
+  }
+
+  pub fn required_successes(&self, total: usize) -> usize {
+    match self {
+      FanOutMode::FirstSuccess => 1.min(total),
+      FanOutMode::All => total,
+      FanOutMode::Quorum(n) => (*n).min(total),
+    }
+  }
+}
+
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Outcome {
+  Success,
+  Failure,
+  Pending,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct TargetOutcome {
+  pub target: String,
+  pub outcome: Outcome,
+  pub status: Option<u16>,
+  pub error: Option<String>,
+  pub duration_ms: Option<u64>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct FanOutSummary {
+  pub mode: String,
+  pub delivered: bool,
+  pub targets: Vec<TargetOutcome>,
+}
+
+/**
+ * These both use the headers from `http` under the hood, but there are
+ * disagreements on the version of `http` that reqwest and actix use.
+ */
+fn actix_to_reqwest_headers(req: &HttpRequest) -> RHeaderMap {
+  let mut out = RHeaderMap::new();
+  for (name, value) in req.headers().iter() {
+    if let (Ok(n), Ok(v)) = (
+      RHeaderName::from_bytes(name.as_str().as_bytes()),
+      RHeaderValue::from_bytes(value.as_bytes()),
+    ) {
+      out.insert(n, v);
+    }
+  }
+  out
+}
+
+/**
+ * The subset of the incoming GitHub request headers that Jenkins gets to see.
+ */
+pub fn forwarded_headers(original_req: &HttpRequest) -> RHeaderMap {
+  let mut out = RHeaderMap::new();
+  let headers = actix_to_reqwest_headers(original_req);
+  for (header_name, header_value) in headers.iter() {
+    let header_name_str = header_name.as_str();
+    if header_name_str.to_lowercase().starts_with("x-github-")
+      || header_name_str.to_lowercase().starts_with("x-hub-")
+      || header_name_str.to_lowercase().starts_with("x-forwarded-")
+      || header_name_str.to_lowercase() == "host"
+      || header_name_str.to_lowercase() == "accept"
+      || header_name_str.to_lowercase() == "content-type"
+    {
+      debug!(
+        "Passing header - {}: {:?}",
+        header_name,
+        header_value.to_str()
+      );
+      out.insert(header_name.clone(), header_value.clone());
+    } else {
+      debug!(
+        "Dropping header - {}: {:?}",
+        header_name,
+        header_value.to_str()
+      );
+    }
+  }
+  out
+}
+
+fn validate_jenkins_url(jenkins_url: &str) -> Result<bool, ProxyError> {
+  let url =
+    Url::parse(jenkins_url).map_err(|_| ProxyError::InvalidJenkinsUrl)?;
+
+  if url.scheme() != "http" && url.scheme() != "https" {
+    warn!("Jenkins URL has invalid scheme: {}", url.scheme());
+    return Ok(false);
+  }
+
+  if url.host_str().is_none() {
+    warn!("Jenkins URL has no host");
+    return Ok(false);
+  }
+
+  let path = url.path();
+  if path.contains("..") || path.contains("//") {
+    warn!("Jenkins URL contains suspicious path traversal patterns");
+    return Ok(false);
+  }
+
+  if url.query().is_some() || url.fragment().is_some() {
+    warn!("Jenkins URL should not contain query parameters or fragments");
+    return Ok(false);
+  }
+
+  Ok(true)
+}
+
+/**
+ * The Jenkins root for a target URL, which may already name an endpoint.
+ */
+fn jenkins_root_url(base_url: &str) -> Result<String, ProxyError> {
+  let url = Url::parse(base_url).map_err(|_| ProxyError::InvalidJenkinsUrl)?;
+  let mut root = url.to_string();
+  if !root.ends_with('/') {
+    root.push('/');
+  }
+  for endpoint in ["github-webhook/", "ghprbhook/"] {
+    if let Some(stripped) = root.strip_suffix(endpoint) {
+      return Ok(stripped.to_string());
+    }
+  }
+  Ok(root)
+}
+
+/**
+ * `folder/name` as the URL path Jenkins gives that job,
+ * `job/folder/job/name/`.
+ */
+fn job_path(job: &str) -> Result<String, String> {
+  let segments: Vec<&str> = job.trim_matches('/').split('/').collect();
+  if segments
+    .iter()
+    .any(|s| s.is_empty() || *s == "." || *s == "..")
+  {
+    return Err(format!("'{}' is not a valid job path", job));
+  }
+  Ok(
+    segments
+      .iter()
+      .map(|segment| {
+        let encoded: String =
+          url::form_urlencoded::byte_serialize(segment.as_bytes()).collect();
+        // byte_serialize is for forms and writes spaces as '+'.
+        format!("job/{}/", encoded.replace('+', "%20"))
+      })
+      .collect(),
+  )
+}
+
+fn construct_jenkins_url(base_url: &str) -> Result<String, ProxyError> {
+  let mut url =
+    Url::parse(base_url).map_err(|_| ProxyError::InvalidJenkinsUrl)?;
+
+  let path = url.path().to_string();
+
+  let new_path = if !path.ends_with('/') {
+    format!("{}/", path)
+  } else {
+    path.clone()
+  };
+
+  if !new_path.contains("/github-webhook/") && !new_path.contains("/ghprbhook/")
+  {
+    url.set_path(&format!("{}github-webhook/", new_path));
+  } else {
+    url.set_path(&new_path);
+  }
+
+  Ok(url.to_string())
+}
+
+pub async fn forward_to_jenkins(
+  client: &reqwest::Client,
+  headers: &RHeaderMap,
+  body: &web::Bytes,
+  target: &JenkinsTarget,
+) -> Result<(reqwest::StatusCode, web::Bytes), ProxyError> {
+  let mut response = post_to_jenkins(client, headers, body, target).await?;
+  // A crumb goes stale when its session expires or Jenkins restarts, which
+  // Jenkins reports as a 403.  One retry with a fresh crumb tells that apart
+  // from a user who really lacks permission.
+  if let (StatusCode::FORBIDDEN, Some(crumbs)) =
+    (response.status, &target.crumbs)
+  {
+    debug!(url = %target.url, "Jenkins answered 403, refreshing the crumb");
+    crumbs.invalidate().await;
+    response = post_to_jenkins(client, headers, body, target).await?;
+  }
+  let status = response.status;
+
+  info!(url = %target.url, status = status.as_u16(), "Forwarded webhook to Jenkins");
+
+  if let (Endpoint::BuildWithParameters { .. }, true, Some(location)) =
+    (&target.endpoint, status.is_success(), response.location)
+  {
+    let delivery_id = headers
+      .get(GITHUB_DELIVERY_HEADER)
+      .and_then(|v| v.to_str().ok())
+      .map(str::to_string);
+    follow_queue_item(client.clone(), target.clone(), location, delivery_id);
+  }
+
+  Ok((status, response.body))
+}
+
+struct JenkinsResponse {
+  status: StatusCode,
+  /** Where buildWithParameters put the build in the queue. */
+  location: Option<String>,
+  body: web::Bytes,
+}
+
+async fn post_to_jenkins(
+  client: &reqwest::Client,
+  headers: &RHeaderMap,
+  body: &web::Bytes,
+  target: &JenkinsTarget,
+) -> Result<JenkinsResponse, ProxyError> {
+  let mut url =
+    Url::parse(&target.url).map_err(|_| ProxyError::InvalidJenkinsUrl)?;
+  let mut token = None;
+  let (headers, body) = match &target.endpoint {
+    Endpoint::GitHubWebhook => (headers.clone(), body.clone()),
+    Endpoint::GenericWebhookTrigger {
+      token: gwt_token,
+      flatten,
+    } => {
+      token = gwt_token.as_deref();
+      if let Some(token) = token {
+        url.query_pairs_mut().append_pair("token", token);
+      }
+      if *flatten {
+        (headers.clone(), flattened_payload(headers, body)?)
+      } else {
+        (headers.clone(), body.clone())
+      }
+    }
+    Endpoint::BuildWithParameters { parameters } => {
+      let variables = forwarded_variables(headers, body)?;
+      let mut form = url::form_urlencoded::Serializer::new(String::new());
+      for (parameter, variable) in parameters {
+        if let Some(value) = variables.get(variable.as_str()) {
+          form.append_pair(parameter, value);
+        }
+      }
+      // Jenkins has no use for GitHub's headers here.
+      let mut form_headers = RHeaderMap::new();
+      form_headers.insert(
+        CONTENT_TYPE,
+        RHeaderValue::from_static("application/x-www-form-urlencoded"),
+      );
+      (form_headers, web::Bytes::from(form.finish()))
+    }
+  };
+  let mut request = target
+    .authorize(client.post(url))
+    .headers(headers)
+    .body(body);
+  if let Some(crumbs) = &target.crumbs {
+    request = crumbs
+      .apply(client, &target.root_url, target.auth.as_ref(), request)
+      .await?;
+  }
+  // reqwest puts the URL in its errors, and the token must not end up in the
+  // logs along with it.
+  let hide_token = |e: reqwest::Error| match token {
+    Some(_) => e.without_url(),
+    None => e,
+  };
+  let response = request.send().await.map_err(hide_token)?;
+  Ok(JenkinsResponse {
+    status: response.status(),
+    location: response
+      .headers()
+      .get(LOCATION)
+      .and_then(|v| v.to_str().ok())
+      .map(str::to_string),
+    body: response.bytes().await.map_err(hide_token)?,
+  })
+}
+
+/**
+ * The parts of `/queue/item/<id>/api/json` that say whether the build has
+ * started.
+ */
+#[derive(Deserialize)]
+struct QueueItem {
+  #[serde(default)]
+  cancelled: bool,
+  executable: Option<QueueExecutable>,
+}
+
+#[derive(Deserialize)]
+struct QueueExecutable {
+  number: u64,
+  url: Option<String>,
+}
+
+/**
+ * The queue item URL from a `Location` header, if it is under the target's
+ * root URL.  The item is fetched with the target's credentials, so a
+ * `Location` pointing anywhere else is not followed.
+ */
+fn queue_item_url(root_url: &str, location: &str) -> Option<Url> {
+  let root = Url::parse(root_url).ok()?;
+  let item = root.join(location).ok()?;
+  (item.origin() == root.origin() && item.path().starts_with(root.path()))
+    .then_some(item)
+}
+
+/**
+ * buildWithParameters only answers with the build's place in the queue, so
+ * the queue item is polled in the background until the build has a number to
+ * log.  It gets its own trace, linked to the webhook's, so that the webhook is
+ * not reported as lasting until the build starts.  At most
+ * `MAX_QUEUE_ITEM_FOLLOWERS` items are polled at once.
+ */
+fn follow_queue_item(
+  client: reqwest::Client,
+  target: JenkinsTarget,
+  location: String,
+  delivery_id: Option<String>,
+) {
+  let Some(item) = queue_item_url(&target.root_url, &location) else {
+    warn!(
+      target = %target.name,
+      location,
+      "Not following a queued build outside the target's root URL",
+    );
+    return;
+  };
+  let Ok(permit) = QUEUE_ITEM_FOLLOWERS.clone().try_acquire_owned() else {
+    debug!(
+      target = %target.name,
+      "Already following {} queued builds, not following this one",
+      MAX_QUEUE_ITEM_FOLLOWERS,
+    );
+    return;
+  };
+  let span = info_span!(
+    parent: None,
+    "jenkins_queue_item",
+    delivery_id = delivery_id.as_deref().unwrap_or(""),
+    target = %target.name,
+    queue_item = %location,
+    build = field::Empty,
+  );
+  span.follows_from(Span::current());
+  tokio::spawn(
+    async move {
+      let _permit = permit;
+      let url = format!("{}/api/json", item.as_str().trim_end_matches('/'));
+      let started = Instant::now();
+      while started.elapsed() < QUEUE_ITEM_TIMEOUT {
+        let response = target
+          .authorize(client.get(&url))
+          .send()
+          .await
+          .and_then(|r| r.error_for_status());
+        let item = match response {
+          Ok(response) => response.json::<QueueItem>().await,
+          Err(e) => Err(e),
+        };
+        match item {
+          Ok(QueueItem {
+            executable: Some(build),
+            ..
+          }) => {
+            Span::current().record("build", build.number);
+            info!(
+              build = build.number,
+              url = build.url.as_deref().unwrap_or(""),
+              "Jenkins started build",
+            );
+            return;
+          }
+          Ok(QueueItem {
+            cancelled: true, ..
+          }) => {
+            warn!("Jenkins cancelled the queued build");
+            return;
+          }
+          Ok(_) => {}
+          Err(e) => {
+            warn!(error = %error_chain(&e), "Failed to check on queued build");
+            return;
+          }
+        }
+        tokio::time::sleep(QUEUE_ITEM_POLL_INTERVAL).await;
+      }
+      warn!(
+        waited_secs = QUEUE_ITEM_TIMEOUT.as_secs(),
+        "Queued build has not started, no longer waiting for it",
+      );
+    }
+    .instrument(span),
+  );
+}
+
+/**
+ * An error followed by its sources, since reqwest's own message leaves out
+ * the cause (a refused connection, an untrusted certificate, ...).
+ */
+pub fn error_chain(e: &dyn std::error::Error) -> String {
+  let mut message = e.to_string();
+  let mut source = e.source();
+  while let Some(cause) = source {
+    // Wrapping errors often repeat their source in their own message.
+    let cause_message = cause.to_string();
+    if !message.contains(&cause_message) {
+      message.push_str(": ");
+      message.push_str(&cause_message);
+    }
+    source = cause.source();
+  }
+  message
+}
+
+/**
+ * Forwards to a single target, turning whatever happens into an outcome.
+ * Only a 2xx from Jenkins counts as a success.
+ */
+#[instrument(
+  name = "jenkins",
+  skip_all,
+  fields(
+    target = %target.name,
+    status = field::Empty,
+    outcome = field::Empty,
+  ),
+)]
+pub async fn deliver_to_target(
+  client: &reqwest::Client,
+  target: &JenkinsTarget,
+  headers: &RHeaderMap,
+  body: &web::Bytes,
+) -> TargetOutcome {
+  let started = Instant::now();
+  let result = forward_to_jenkins(client, headers, body, target).await;
+  let elapsed = started.elapsed();
+  let duration_ms = Some(elapsed.as_millis() as u64);
+  let span = Span::current();
+  if let Ok((status, _)) = &result {
+    span.record("status", status.as_u16());
+  }
+  span.record(
+    "outcome",
+    match &result {
+      Ok((status, _)) if status.is_success() => "success",
+      _ => "failure",
+    },
+  );
+  METRICS.jenkins_response(
+    &target.name,
+    result.as_ref().ok().map(|(status, _)| status.as_u16()),
+    elapsed,
+  );
+  match result {
+    Ok((status, _)) if status.is_success() => TargetOutcome {
+      target: target.name.clone(),
+      outcome: Outcome::Success,
+      status: Some(status.as_u16()),
+      error: None,
+      duration_ms,
+    },
+    Ok((status, body)) => {
+      warn!(
+        response = %String::from_utf8_lossy(&body[..body.len().min(200)]),
+        "Jenkins target answered with an error",
+      );
+      TargetOutcome {
+        target: target.name.clone(),
+        outcome: Outcome::Failure,
+        status: Some(status.as_u16()),
+        error: Some(format!("Jenkins answered {}", status)),
+        duration_ms,
+      }
+    }
+    Err(e) => {
+      let error = error_chain(&e);
+      warn!(error = %error, "Failed to forward to Jenkins target");
+      TargetOutcome {
+        target: target.name.clone(),
+        outcome: Outcome::Failure,
+        status: None,
+        error: Some(error),
+        duration_ms,
+      }
+    }
+  }
+}
+
+/**
+ * Relays a webhook to every target concurrently.  Each target runs in its own
+ * task, so a slow or failing controller never holds up the others, and the
+ * summary is returned as soon as `mode` is decided either way.  Targets that
+ * have not answered by then keep going in the background and show up as
+ * pending.
+ */
+pub async fn fan_out(
+  client: &reqwest::Client,
+  targets: &[JenkinsTarget],
+  headers: RHeaderMap,
+  body: web::Bytes,
+  mode: FanOutMode,
+) -> FanOutSummary {
+  let (tx, mut rx) = mpsc::unbounded_channel();
+  for (index, target) in targets.iter().enumerate() {
+    let tx = tx.clone();
+    let client = client.clone();
+    let target = target.clone();
+    let headers = headers.clone();
+    let body = body.clone();
+    tokio::spawn(
+      async move {
+        let outcome =
+          deliver_to_target(&client, &target, &headers, &body).await;
+        // The receiver is gone once the fan-out has been decided.
+        let _ = tx.send((index, outcome));
+      }
+      .in_current_span(),
+    );
+  }
+  drop(tx);
+
+  let mut outcomes: Vec<TargetOutcome> = targets
+    .iter()
+    .map(|t| TargetOutcome {
+      target: t.name.clone(),
+      outcome: Outcome::Pending,
+      status: None,
+      error: None,
+      duration_ms: None,
+    })
+    .collect();
+  let required = mode.required_successes(targets.len());
+  let mut successes = 0;
+  let mut failures = 0;
+  while successes < required && failures + required <= targets.len() {
+    let Some((index, outcome)) = rx.recv().await else {
+      break;
+    };
+    match outcome.outcome {
+      Outcome::Success => successes += 1,
+      _ => failures += 1,
+    }
+    outcomes[index] = outcome;
+  }
+
+  FanOutSummary {
+    mode: mode.name(),
+    delivered: required > 0 && successes >= required,
+    targets: outcomes,
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::test_support::FakeJenkins;
+
+  fn targets(jenkins: &FakeJenkins, answers: &[&str]) -> Vec<JenkinsTarget> {
+    answers
+      .iter()
+      .enumerate()
+      .map(|(i, answer)| {
+        let name = format!("{}-{}", answer, i);
+        JenkinsTarget::named(&name, &format!("{}/{}/", jenkins.url, answer))
+          .unwrap()
+      })
+      .collect()
+  }
+
+  fn push_headers() -> RHeaderMap {
+    let mut headers = RHeaderMap::new();
+    headers.insert("X-GitHub-Event", RHeaderValue::from_static("push"));
+    headers.insert("X-GitHub-Delivery", RHeaderValue::from_static("72d3162e"));
+    headers
+  }
+
+  fn push_body() -> web::Bytes {
+    web::Bytes::from_static(include_bytes!("../tests/fixtures/push.json"))
+  }
+
+  async fn delivered(answers: &[&str], mode: FanOutMode) -> bool {
+    let jenkins = FakeJenkins::start();
+    fan_out(
+      &reqwest::Client::new(),
+      &targets(&jenkins, answers),
+      RHeaderMap::new(),
+      web::Bytes::from_static(b"{}"),
+      mode,
+    )
+    .await
+    .delivered
+  }
+
+  #[test]
+  fn required_successes_never_exceed_the_targets() {
+    assert_eq!(FanOutMode::FirstSuccess.required_successes(3), 1);
+    assert_eq!(FanOutMode::FirstSuccess.required_successes(0), 0);
+    assert_eq!(FanOutMode::All.required_successes(3), 3);
+    assert_eq!(FanOutMode::Quorum(2).required_successes(3), 2);
+    assert_eq!(FanOutMode::Quorum(5).required_successes(3), 3);
+  }
+
+  #[tokio::test]
+  async fn all_needs_every_target() {
+    assert!(delivered(&["ok", "ok"], FanOutMode::All).await);
+    assert!(!delivered(&["ok", "fail"], FanOutMode::All).await);
+  }
+
+  #[tokio::test]
+  async fn every_target_gets_the_webhook_as_sent() {
+    let jenkins = FakeJenkins::start();
+    fan_out(
+      &reqwest::Client::new(),
+      &targets(&jenkins, &["ok", "fail"]),
+      RHeaderMap::new(),
+      web::Bytes::from_static(b"{\"zen\":\"Non-blocking is better.\"}"),
+      FanOutMode::All,
+    )
+    .await;
+    let mut received = jenkins.received();
+    received.sort_by(|a, b| a.path.cmp(&b.path));
+    let received: Vec<(&str, &str)> = received
+      .iter()
+      .map(|r| (r.path.as_str(), r.body.as_str()))
+      .collect();
+    assert_eq!(
+      received,
+      [
+        (
+          "/fail/github-webhook/",
+          r#"{"zen":"Non-blocking is better."}"#
+        ),
+        (
+          "/ok/github-webhook/",
+          r#"{"zen":"Non-blocking is better."}"#
+        ),
+      ]
+    );
+  }
+
+  #[tokio::test]
+  async fn first_success_needs_one_target() {
+    assert!(delivered(&["fail", "ok"], FanOutMode::FirstSuccess).await);
+    assert!(!delivered(&["fail", "fail"], FanOutMode::FirstSuccess).await);
+  }
+
+  #[tokio::test]
+  async fn quorum_needs_that_many_targets() {
+    assert!(delivered(&["ok", "fail", "ok"], FanOutMode::Quorum(2)).await);
+    assert!(!delivered(&["ok", "fail", "fail"], FanOutMode::Quorum(2)).await);
+  }
+
+  #[tokio::test]
+  async fn no_targets_is_not_delivered() {
+    assert!(!delivered(&[], FanOutMode::All).await);
+  }
+
+  #[tokio::test]
+  async fn decided_fan_out_does_not_wait_for_slow_targets() {
+    let jenkins = FakeJenkins::start();
+    let started = Instant::now();
+    let summary = fan_out(
+      &reqwest::Client::new(),
+      &targets(&jenkins, &["ok", "slow"]),
+      RHeaderMap::new(),
+      web::Bytes::from_static(b"{}"),
+      FanOutMode::FirstSuccess,
+    )
+    .await;
+    assert!(started.elapsed() < Duration::from_secs(2));
+    assert!(summary.delivered);
+    assert!(matches!(summary.targets[0].outcome, Outcome::Success));
+    assert!(matches!(summary.targets[1].outcome, Outcome::Pending));
+  }
+
+  #[test]
+  fn generic_webhook_trigger_targets_take_the_token_from_the_route() {
+    let endpoint = Endpoint::GenericWebhookTrigger {
+      token: None,
+      flatten: false,
+    };
+    let target = JenkinsTarget::for_endpoint(
+      "generic",
+      "https://ci.example.com/",
+      endpoint,
+    )
+    .unwrap();
+    assert_eq!(
+      target.url,
+      "https://ci.example.com/generic-webhook-trigger/invoke"
+    );
+    assert!(target
+      .clone()
+      .for_route("untokened", &RouteEndpointOptions::default())
+      .is_err());
+
+    let options = RouteEndpointOptions {
+      token: Some("s3cret".to_string()),
+      flatten: true,
+      ..Default::default()
+    };
+    let routed = target.for_route("tokened", &options).unwrap();
+    assert!(matches!(
+      routed.endpoint,
+      Endpoint::GenericWebhookTrigger {
+        token: Some(ref token),
+        flatten: true,
+      } if token == "s3cret"
+    ));
+  }
+
+  #[test]
+  fn github_webhook_targets_cannot_be_flattened() {
+    let target =
+      JenkinsTarget::named("plain", "https://ci.example.com/").unwrap();
+    let options = RouteEndpointOptions {
+      flatten: true,
+      ..Default::default()
+    };
+    assert!(target.for_route("flattening", &options).is_err());
+  }
+
+  #[tokio::test]
+  async fn generic_webhook_trigger_gets_the_token_and_the_variables() {
+    let jenkins = FakeJenkins::start();
+    let endpoint = Endpoint::GenericWebhookTrigger {
+      token: None,
+      flatten: false,
+    };
+    let options = RouteEndpointOptions {
+      token: Some("s3cret".to_string()),
+      flatten: true,
+      ..Default::default()
+    };
+    let target = JenkinsTarget::for_endpoint(
+      "generic",
+      &format!("{}/ok/", jenkins.url),
+      endpoint,
+    )
+    .unwrap()
+    .for_route("tokened", &options)
+    .unwrap();
+
+    let outcome = deliver_to_target(
+      &reqwest::Client::new(),
+      &target,
+      &push_headers(),
+      &push_body(),
+    )
+    .await;
+
+    assert!(matches!(outcome.outcome, Outcome::Success));
+    let received = jenkins.received();
+    assert_eq!(
+      received[0].path,
+      "/ok/generic-webhook-trigger/invoke?token=s3cret"
+    );
+    let variables: BTreeMap<String, String> =
+      serde_json::from_str(&received[0].body).unwrap();
+    assert_eq!(variables["event"], "push");
+    assert_eq!(variables["delivery_id"], "72d3162e");
+    assert_eq!(variables["repository"], "Codertocat/Hello-World");
+    assert_eq!(variables["branch"], "master");
+  }
+
+  fn build_target(
+    base_url: &str,
+    job: &str,
+    parameters: &[(&str, &str)],
+  ) -> Result<JenkinsTarget, String> {
+    let endpoint = Endpoint::BuildWithParameters {
+      parameters: BTreeMap::new(),
+    };
+    let options = RouteEndpointOptions {
+      job: Some(job.to_string()),
+      parameters: parameters
+        .iter()
+        .map(|(name, variable)| (name.to_string(), variable.to_string()))
+        .collect(),
+      ..Default::default()
+    };
+    JenkinsTarget::for_endpoint("build", base_url, endpoint)
+      .unwrap()
+      .for_route("building", &options)
+  }
+
+  #[test]
+  fn job_paths_name_every_folder() {
+    assert_eq!(job_path("app").unwrap(), "job/app/");
+    assert_eq!(job_path("/team/app/").unwrap(), "job/team/job/app/");
+    assert_eq!(job_path("team/my app").unwrap(), "job/team/job/my%20app/");
+    assert_eq!(job_path("team/a&b").unwrap(), "job/team/job/a%26b/");
+    for job in ["", "team//app", "team/../admin", "./app"] {
+      assert!(job_path(job).is_err(), "{:?} was accepted", job);
+    }
+  }
+
+  #[test]
+  fn build_with_parameters_targets_need_a_job() {
+    let target = build_target("https://ci.example.com/", "team/app", &[]);
+    assert_eq!(
+      target.unwrap().url,
+      "https://ci.example.com/job/team/job/app/buildWithParameters"
+    );
+    let endpoint = Endpoint::BuildWithParameters {
+      parameters: BTreeMap::new(),
+    };
+    let target =
+      JenkinsTarget::for_endpoint("build", "https://ci.example.com/", endpoint)
+        .unwrap();
+    assert!(target
+      .for_route("jobless", &RouteEndpointOptions::default())
+      .is_err());
+  }
+
+  #[tokio::test]
+  async fn build_parameters_default_to_the_usual_variables() {
+    let jenkins = FakeJenkins::start();
+    let target =
+      build_target(&format!("{}/ok/", jenkins.url), "app", &[]).unwrap();
+
+    let outcome = deliver_to_target(
+      &reqwest::Client::new(),
+      &target,
+      &push_headers(),
+      &push_body(),
+    )
+    .await;
+
+    assert!(matches!(outcome.outcome, Outcome::Success));
+    let received = jenkins.received();
+    assert_eq!(received[0].path, "/ok/job/app/buildWithParameters");
+    // A push has no pull request number, so PR_NUMBER is left out.
+    assert_eq!(
+      received[0].body,
+      "BRANCH=master&REPO=Codertocat%2FHello-World&SENDER=Codertocat\
+       &SHA=6113728f27ae82c7b1a177c8d03f9e96e0adf246"
+    );
+  }
+
+  #[tokio::test]
+  async fn build_parameters_follow_the_route() {
+    let jenkins = FakeJenkins::start();
+    let target = build_target(
+      &format!("{}/ok/", jenkins.url),
+      "app",
+      &[("GIT_REF", "ref"), ("PR", "pr_number")],
+    )
+    .unwrap();
+
+    deliver_to_target(
+      &reqwest::Client::new(),
+      &target,
+      &push_headers(),
+      &push_body(),
+    )
+    .await;
+
+    let received = jenkins.received();
+    assert_eq!(received[0].body, "GIT_REF=refs%2Fheads%2Fmaster");
+  }
+
+  #[test]
+  fn queue_items_are_only_followed_under_the_root_url() {
+    let root = "https://ci.example.com/jenkins/";
+    let followed = |location: &str| {
+      queue_item_url(root, location).map(|url| url.to_string())
+    };
+    assert_eq!(
+      followed("https://ci.example.com/jenkins/queue/item/5/").as_deref(),
+      Some("https://ci.example.com/jenkins/queue/item/5/")
+    );
+    assert_eq!(
+      followed("/jenkins/queue/item/5/").as_deref(),
+      Some("https://ci.example.com/jenkins/queue/item/5/")
+    );
+    for location in [
+      "https://elsewhere.example.com/jenkins/queue/item/5/",
+      "https://ci.example.com:8443/jenkins/queue/item/5/",
+      "http://ci.example.com/jenkins/queue/item/5/",
+      "https://ci.example.com/other/queue/item/5/",
+      "//elsewhere.example.com/jenkins/queue/item/5/",
+    ] {
+      assert_eq!(followed(location), None, "{}", location);
+    }
+  }
+}
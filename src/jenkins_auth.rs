@@ -0,0 +1,170 @@
+use reqwest::header::{COOKIE, SET_COOKIE};
+use reqwest::{RequestBuilder, StatusCode};
+use serde::Deserialize;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use tokio::sync::Mutex;
+use tracing::{debug, info};
+
+use crate::error::ProxyError;
+
+/**
+ * A Jenkins user and API token (or password), sent as basic auth.
+ */
+#[derive(Clone)]
+pub struct JenkinsAuth {
+  pub user: String,
+  token: String,
+}
+
+impl fmt::Debug for JenkinsAuth {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.debug_struct("JenkinsAuth")
+      .field("user", &self.user)
+      .field("token", &"<redacted>")
+      .finish()
+  }
+}
+
+impl JenkinsAuth {
+  pub fn load(user: &str, token_file: &Path) -> Result<JenkinsAuth, String> {
+    let token = fs::read_to_string(token_file)
+      .map(|s| s.trim().to_string())
+      .map_err(|e| {
+        format!(
+          "Failed to read Jenkins API token from file '{}': {}",
+          token_file.display(),
+          e,
+        )
+      })?;
+    if token.is_empty() {
+      return Err(format!(
+        "Jenkins API token file '{}' is empty",
+        token_file.display()
+      ));
+    }
+    Ok(JenkinsAuth {
+      user: user.to_string(),
+      token,
+    })
+  }
+
+  pub fn apply(&self, request: RequestBuilder) -> RequestBuilder {
+    request.basic_auth(&self.user, Some(&self.token))
+  }
+}
+
+/**
+ * What `/crumbIssuer/api/json` answers with.
+ */
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct CrumbResponse {
+  crumb: String,
+  crumb_request_field: String,
+}
+
+#[derive(Clone, Debug)]
+enum CachedCrumb {
+  Crumb {
+    field: String,
+    value: String,
+    /**
+     * Since Jenkins 2.176 a crumb is only valid within the web session it was
+     * issued to, so the session cookie has to go along with it.
+     */
+    cookie: Option<String>,
+  },
+  /** The crumb issuer is disabled, so POSTs need no crumb. */
+  NotRequired,
+}
+
+/**
+ * The CSRF crumb of one Jenkins controller, fetched on first use and kept
+ * until Jenkins rejects it.  The cache lives as long as the configuration
+ * generation, so a reload starts over with a fresh crumb.
+ */
+#[derive(Debug, Default)]
+pub struct CrumbCache {
+  crumb: Mutex<Option<CachedCrumb>>,
+}
+
+impl CrumbCache {
+  /**
+   * Adds the crumb to `request`, fetching one first if there is none cached.
+   */
+  pub async fn apply(
+    &self,
+    client: &reqwest::Client,
+    root_url: &str,
+    auth: Option<&JenkinsAuth>,
+    request: RequestBuilder,
+  ) -> Result<RequestBuilder, ProxyError> {
+    // Holding the lock while fetching makes concurrent deliveries wait for
+    // this crumb instead of each fetching their own.
+    let mut cached = self.crumb.lock().await;
+    let crumb = match cached.as_ref() {
+      Some(crumb) => crumb.clone(),
+      None => {
+        let crumb = fetch_crumb(client, root_url, auth).await?;
+        *cached = Some(crumb.clone());
+        crumb
+      }
+    };
+    Ok(match crumb {
+      CachedCrumb::Crumb {
+        field,
+        value,
+        cookie,
+      } => {
+        let request = request.header(field, value);
+        match cookie {
+          Some(cookie) => request.header(COOKIE, cookie),
+          None => request,
+        }
+      }
+      CachedCrumb::NotRequired => request,
+    })
+  }
+
+  /**
+   * Drops the cached crumb, so the next request fetches a new one.
+   */
+  pub async fn invalidate(&self) {
+    *self.crumb.lock().await = None;
+  }
+}
+
+async fn fetch_crumb(
+  client: &reqwest::Client,
+  root_url: &str,
+  auth: Option<&JenkinsAuth>,
+) -> Result<CachedCrumb, ProxyError> {
+  let url = format!("{}crumbIssuer/api/json", root_url);
+  let mut request = client.get(&url);
+  if let Some(auth) = auth {
+    request = auth.apply(request);
+  }
+  let response = request.send().await?;
+  if response.status() == StatusCode::NOT_FOUND {
+    info!(url = %url, "Jenkins has no crumb issuer, sending POSTs without one");
+    return Ok(CachedCrumb::NotRequired);
+  }
+  let response = response.error_for_status()?;
+  let cookie = response
+    .headers()
+    .get_all(SET_COOKIE)
+    .iter()
+    .filter_map(|value| value.to_str().ok())
+    .filter_map(|value| value.split(';').next())
+    .collect::<Vec<_>>()
+    .join("; ");
+  let crumb: CrumbResponse = response.json().await?;
+  debug!(url = %url, field = %crumb.crumb_request_field, "Fetched Jenkins crumb");
+  Ok(CachedCrumb::Crumb {
+    field: crumb.crumb_request_field,
+    value: crumb.crumb,
+    cookie: Some(cookie).filter(|c| !c.is_empty()),
+  })
+}
@@ -1,67 +1,205 @@
+mod admin;
 mod args;
+mod config;
 mod datetime_agnostic;
+mod dedup;
+mod drift;
 mod error;
+mod filters;
 mod github_types;
+mod health;
+mod jenkins;
+mod jenkins_auth;
+mod metrics;
+mod queue;
+mod routing;
+mod secrets;
+mod state;
+mod telemetry;
+#[cfg(test)]
+mod test_support;
+mod tls;
+mod variables;
 mod webhook;
 
 use actix_web::{middleware, web, App, HttpRequest, HttpResponse, HttpServer};
 use clap::Parser;
+use std::sync::Arc;
 use tracing::{info, warn};
 
-use crate::args::Args;
+use crate::args::{Args, Command};
+use crate::dedup::Deduplicator;
+use crate::drift::{print_report, spawn_flusher, SchemaDrift};
 use crate::error::ProxyError;
+use crate::health::{healthz, readyz, Readiness};
+use crate::metrics::metrics;
+use crate::queue::{spawn_worker, DeliveryQueue};
+use crate::state::{spawn_reloader, AppState, StateHandle};
+use crate::tls::{spawn_certificate_reloader, CertificateResolver};
 use crate::webhook::handle_webhook;
 
 #[tokio::main]
 async fn main() -> Result<(), ProxyError> {
-  let args = Args::parse();
+  let cli_args = Args::parse();
+  let args = cli_args
+    .clone()
+    .merge_config_file()
+    .map_err(ProxyError::Configuration)?;
 
-  let log_level = args
-    .get_log_level()
-    .map_err(|e| ProxyError::Configuration(e))?;
-
-  tracing_subscriber::fmt()
-    .with_env_filter(tracing_subscriber::EnvFilter::from_default_env())
-    .with_max_level(log_level)
-    .init();
+  if let Some(Command::SchemaDrift) = &args.command {
+    let path = args.schema_drift_file.as_deref().ok_or_else(|| {
+      ProxyError::Configuration(
+        "schema-drift needs --schema-drift-file".to_string(),
+      )
+    })?;
+    return print_report(path).map_err(ProxyError::Configuration);
+  }
 
-  let bind_address = format!("{}:{}", args.host, args.port);
+  let log_level = args.get_log_level().map_err(ProxyError::Configuration)?;
 
-  info!(
-    "Starting GitHub to Jenkins webhook proxy on {}",
-    bind_address
-  );
-  info!("Forwarding webhooks to: {}", args.jenkins_url);
+  let log_format = args.get_log_format().map_err(ProxyError::Configuration)?;
+  let tracer_provider = telemetry::init(&args, log_level, log_format)?;
 
-  let github_secret = args
-    .get_github_secret()
-    .map_err(|e| ProxyError::Configuration(e))?;
+  let bind_address = args.bind_address();
 
-  let app_state = web::Data::new(AppState {
-    jenkins_url: args.jenkins_url,
-    github_secret,
-  });
+  let https_bind_address = args.https_bind_address();
+  let tls_config = match (&args.tls_cert, &args.tls_key) {
+    (Some(cert), Some(key)) => {
+      let resolver = Arc::new(
+        CertificateResolver::new(cert, key)
+          .map_err(ProxyError::Configuration)?,
+      );
+      spawn_certificate_reloader(resolver.clone(), args.get_reload_interval())?;
+      Some(resolver.server_config())
+    }
+    _ if https_bind_address.is_some() => {
+      return Err(ProxyError::Configuration(
+        "--https-port needs --tls-cert and --tls-key".to_string(),
+      ));
+    }
+    _ => None,
+  };
+  match (&tls_config, &https_bind_address) {
+    (Some(_), None) => info!(
+      "Starting GitHub to Jenkins webhook proxy on {} (HTTPS)",
+      bind_address
+    ),
+    (Some(_), Some(https_bind_address)) => info!(
+      "Starting GitHub to Jenkins webhook proxy on {} (HTTP) and {} (HTTPS)",
+      bind_address, https_bind_address
+    ),
+    (None, _) => info!(
+      "Starting GitHub to Jenkins webhook proxy on {}",
+      bind_address
+    ),
+  }
+  let app_state =
+    AppState::build(&args, 1).map_err(ProxyError::Configuration)?;
+  let state_handle = Arc::new(StateHandle::new(app_state, cli_args));
+  spawn_reloader(
+    state_handle.clone(),
+    args.clone(),
+    args.get_reload_interval(),
+  )?;
+  let queue = match &args.queue_dir {
+    Some(dir) => {
+      let queue = Arc::new(
+        DeliveryQueue::open(dir, args.get_queue_max_age())
+          .map_err(ProxyError::Configuration)?,
+      );
+      info!("Queueing deliveries in {}", dir.display());
+      spawn_worker(queue.clone(), state_handle.clone());
+      Some(web::Data::from(queue))
+    }
+    None => None,
+  };
+  let dedup = match args.get_dedup_ttl() {
+    Some(ttl) => {
+      let dedup = Deduplicator::new(
+        ttl,
+        args.get_dedup_capacity(),
+        args.dedup_file.as_deref(),
+      )
+      .map_err(ProxyError::Configuration)?;
+      info!("Ignoring repeated delivery IDs for {}s", ttl.as_secs());
+      Some(web::Data::new(dedup))
+    }
+    None => None,
+  };
+  let drift = Arc::new(
+    SchemaDrift::new(args.schema_drift_file.as_deref())
+      .map_err(ProxyError::Configuration)?,
+  );
+  spawn_flusher(drift.clone());
+  let drift = web::Data::from(drift);
+  let drift_on_shutdown = drift.clone();
+  let readiness = web::Data::new(Readiness::new(
+    args.get_readiness_timeout(),
+    args.get_readiness_cache(),
+  ));
+  let state_handle = web::Data::from(state_handle);
+  let metrics_bind_address = args.metrics_bind_address();
+  let metrics_on_main_listener = metrics_bind_address.is_none();
 
-  HttpServer::new(move || {
-    App::new()
-      .app_data(app_state.clone())
+  let server = HttpServer::new(move || {
+    let mut app = App::new();
+    if let Some(queue) = &queue {
+      app = app.app_data(queue.clone());
+    }
+    if let Some(dedup) = &dedup {
+      app = app.app_data(dedup.clone());
+    }
+    if metrics_on_main_listener {
+      app = app.route("/metrics", web::get().to(metrics));
+    }
+    app
+      .app_data(state_handle.clone())
+      .app_data(readiness.clone())
+      .app_data(drift.clone())
       .wrap(middleware::Logger::default())
       .service(
         web::resource("/github-webhook/").route(web::post().to(handle_webhook)),
       )
+      .service(admin::scope())
+      .route("/healthz", web::get().to(healthz))
+      .route("/readyz", web::get().to(readyz))
       .service(web::resource("/").route(web::get().to(health_check)))
       .default_service(web::route().to(not_found))
-  })
-  .bind(bind_address)?
-  .run()
-  .await
-  .map_err(ProxyError::from)
-}
+  });
+  let server = match (tls_config, https_bind_address) {
+    (Some(tls_config), None) => {
+      server.bind_rustls_0_23(bind_address, tls_config)?
+    }
+    (Some(tls_config), Some(https_bind_address)) => server
+      .bind(bind_address)?
+      .bind_rustls_0_23(https_bind_address, tls_config)?,
+    (None, _) => server.bind(bind_address)?,
+  }
+  .run();
+
+  match metrics_bind_address {
+    Some(metrics_bind_address) => {
+      info!("Serving /metrics on {}", metrics_bind_address);
+      let metrics_server = HttpServer::new(|| {
+        App::new()
+          .route("/metrics", web::get().to(metrics))
+          .default_service(web::route().to(not_found))
+      })
+      .workers(1)
+      .bind(metrics_bind_address)?
+      .run();
+      tokio::try_join!(server, metrics_server)?;
+    }
+    None => server.await?,
+  }
+  web::block(move || drift_on_shutdown.flush()).await.ok();
 
-#[derive(Clone)]
-pub struct AppState {
-  pub jenkins_url: String,
-  pub github_secret: String,
+  if let Some(provider) = tracer_provider {
+    if let Err(e) = provider.shutdown() {
+      warn!("Failed to flush traces: {}", e);
+    }
+  }
+  Ok(())
 }
 
 async fn health_check() -> HttpResponse {
@@ -0,0 +1,225 @@
+use actix_web::HttpResponse;
+use prometheus::{
+  exponential_buckets, Encoder, HistogramOpts, HistogramVec, IntCounter,
+  IntCounterVec, Opts, Registry, TextEncoder,
+};
+use std::sync::LazyLock;
+use std::time::Duration;
+
+use crate::error::ProxyError;
+use crate::webhook::EVENT_TYPES;
+
+/**
+ * The Prometheus registry served on `/metrics` and the metrics in it.  It is
+ * a global so that the webhook handler, the fan-out and the queue worker can
+ * record without it being passed down to them.
+ */
+pub struct Metrics {
+  registry: Registry,
+  events_received: IntCounterVec,
+  signature_failures: IntCounterVec,
+  parse_failures: IntCounterVec,
+  legacy_sha1_signatures: IntCounter,
+  jenkins_responses: IntCounterVec,
+  forward_duration: HistogramVec,
+  payload_size: HistogramVec,
+}
+
+pub static METRICS: LazyLock<Metrics> = LazyLock::new(Metrics::new);
+
+impl Metrics {
+  fn new() -> Metrics {
+    let registry = Registry::new();
+    let events_received = IntCounterVec::new(
+      Opts::new(
+        "webhook_events_received_total",
+        "Webhooks with a valid signature, by event type and repository",
+      ),
+      &["event", "repository"],
+    )
+    .unwrap();
+    let signature_failures = IntCounterVec::new(
+      Opts::new(
+        "webhook_signature_failures_total",
+        "Webhooks rejected for their signature, by reason",
+      ),
+      &["reason"],
+    )
+    .unwrap();
+    let parse_failures = IntCounterVec::new(
+      Opts::new(
+        "webhook_parse_failures_total",
+        "Signed webhooks whose payload could not be parsed, by error",
+      ),
+      &["error"],
+    )
+    .unwrap();
+    let legacy_sha1_signatures = IntCounter::new(
+      "webhook_legacy_sha1_signatures_total",
+      "Webhooks accepted on the strength of a SHA-1 X-Hub-Signature",
+    )
+    .unwrap();
+    let jenkins_responses = IntCounterVec::new(
+      Opts::new(
+        "jenkins_responses_total",
+        "Forwarding attempts by Jenkins target and response status class",
+      ),
+      &["target", "status_class"],
+    )
+    .unwrap();
+    let forward_duration = HistogramVec::new(
+      HistogramOpts::new(
+        "jenkins_forward_duration_seconds",
+        "Time taken to forward a webhook to a Jenkins target",
+      )
+      .buckets(exponential_buckets(0.005, 2.0, 14).unwrap()),
+      &["target"],
+    )
+    .unwrap();
+    let payload_size = HistogramVec::new(
+      HistogramOpts::new(
+        "webhook_payload_size_bytes",
+        "Size of signed webhook payloads, by event type",
+      )
+      .buckets(exponential_buckets(256.0, 4.0, 10).unwrap()),
+      &["event"],
+    )
+    .unwrap();
+
+    registry
+      .register(Box::new(events_received.clone()))
+      .unwrap();
+    registry
+      .register(Box::new(signature_failures.clone()))
+      .unwrap();
+    registry.register(Box::new(parse_failures.clone())).unwrap();
+    registry
+      .register(Box::new(legacy_sha1_signatures.clone()))
+      .unwrap();
+    registry
+      .register(Box::new(jenkins_responses.clone()))
+      .unwrap();
+    registry
+      .register(Box::new(forward_duration.clone()))
+      .unwrap();
+    registry.register(Box::new(payload_size.clone())).unwrap();
+
+    Metrics {
+      registry,
+      events_received,
+      signature_failures,
+      parse_failures,
+      legacy_sha1_signatures,
+      jenkins_responses,
+      forward_duration,
+      payload_size,
+    }
+  }
+
+  /**
+   * Only called once the signature has passed, so that the repository comes
+   * from a signed body.  The event type comes from `X-GitHub-Event`, which
+   * the signature does not cover, so anything outside `EVENT_TYPES` is
+   * counted as `other` rather than growing a series per made-up name.
+   */
+  pub fn event_received(
+    &self,
+    event: &str,
+    repository: Option<&str>,
+    size: usize,
+  ) {
+    let event = if EVENT_TYPES.contains(&event) {
+      event
+    } else {
+      "other"
+    };
+    self
+      .events_received
+      .with_label_values(&[event, repository.unwrap_or("")])
+      .inc();
+    self
+      .payload_size
+      .with_label_values(&[event])
+      .observe(size as f64);
+  }
+
+  pub fn signature_failure(&self, error: &ProxyError) {
+    let reason = match error {
+      ProxyError::MissingSignature => "missing",
+      _ => "invalid",
+    };
+    self.signature_failures.with_label_values(&[reason]).inc();
+  }
+
+  pub fn parse_failure(&self, error: &ProxyError) {
+    self.parse_failures.with_label_values(&[error.kind()]).inc();
+  }
+
+  /** Returns the number of SHA-1 verifications so far, this one included. */
+  pub fn legacy_sha1_signature(&self) -> u64 {
+    self.legacy_sha1_signatures.inc();
+    self.legacy_sha1_signatures.get()
+  }
+
+  /**
+   * `status` is `None` when no response was received at all (connection
+   * refused, timeout).
+   */
+  pub fn jenkins_response(
+    &self,
+    target: &str,
+    status: Option<u16>,
+    elapsed: Duration,
+  ) {
+    let class = match status {
+      Some(status) => format!("{}xx", status / 100),
+      None => "error".to_string(),
+    };
+    self
+      .jenkins_responses
+      .with_label_values(&[target, &class])
+      .inc();
+    self
+      .forward_duration
+      .with_label_values(&[target])
+      .observe(elapsed.as_secs_f64());
+  }
+}
+
+pub async fn metrics() -> HttpResponse {
+  let encoder = TextEncoder::new();
+  let mut buffer = vec![];
+  match encoder.encode(&METRICS.registry.gather(), &mut buffer) {
+    Ok(()) => HttpResponse::Ok()
+      .content_type(encoder.format_type())
+      .body(buffer),
+    Err(e) => HttpResponse::InternalServerError().body(e.to_string()),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn unknown_event_types_share_one_label() {
+    let metrics = Metrics::new();
+    metrics.event_received("push", Some("Codertocat/Hello-World"), 10);
+    for event in ["made-up", "another-made-up"] {
+      metrics.event_received(event, Some("Codertocat/Hello-World"), 10);
+    }
+    let count = |event: &str| {
+      metrics
+        .events_received
+        .with_label_values(&[event, "Codertocat/Hello-World"])
+        .get()
+    };
+    assert_eq!((count("push"), count("other")), (1, 2));
+    let families = metrics.registry.gather();
+    let received = families
+      .iter()
+      .find(|f| f.name() == "webhook_events_received_total")
+      .unwrap();
+    assert_eq!(received.get_metric().len(), 2);
+  }
+}
@@ -0,0 +1,1006 @@
+use actix_web::web;
+use chrono::{DateTime, Utc};
+use reqwest::header::{
+  HeaderMap as RHeaderMap, HeaderName as RHeaderName,
+  HeaderValue as RHeaderValue,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::Notify;
+use tracing::{error, info, instrument, warn, Instrument};
+
+use crate::error::ProxyError;
+use crate::jenkins::{
+  deliver_to_target, fan_out, FanOutSummary, JenkinsTarget, Outcome,
+};
+use crate::routing::RouteTable;
+use crate::state::StateHandle;
+
+const PENDING_DIR: &str = "pending";
+const DEAD_DIR: &str = "dead";
+/** The extension of a dead letter's metadata while it is being replayed. */
+const CLAIMED_EXT: &str = "replaying";
+const FIRST_RETRY_DELAY: Duration = Duration::from_secs(5);
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(10 * 60);
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/**
+ * A target as recorded in the queue.  At delivery time the target of the same
+ * name in the current configuration is used, so a reload that changes a URL
+ * applies to deliveries that are already queued.  A target that is no longer
+ * configured fails the delivery rather than being sent to `url` without the
+ * endpoint, credentials and parameters it was configured with.
+ */
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct QueuedTarget {
+  pub name: String,
+  pub url: String,
+  /** The route that picked the target, which may add to it (a token). */
+  #[serde(default)]
+  pub route: Option<String>,
+}
+
+/**
+ * Metadata for a queued delivery, stored as `<id>.json` next to the raw body
+ * in `<id>.body`.  The ID is the queue's own: GitHub reuses the
+ * `X-GitHub-Delivery` ID for redeliveries, so that is only kept alongside.
+ */
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct QueuedDelivery {
+  pub id: String,
+  #[serde(default)]
+  pub delivery_id: Option<String>,
+  pub event: String,
+  pub repository: Option<String>,
+  pub headers: Vec<(String, String)>,
+  /** Targets that have not accepted the delivery yet. */
+  pub targets: Vec<QueuedTarget>,
+  pub total_targets: usize,
+  pub successes: usize,
+  pub received_at: DateTime<Utc>,
+  pub attempts: u32,
+  pub next_attempt_at: DateTime<Utc>,
+  pub last_error: Option<String>,
+}
+
+/**
+ * A spool directory of verified deliveries waiting to reach Jenkins.  Each
+ * delivery is written with a write-to-temp, fsync and rename dance, so a
+ * crash leaves either the whole delivery or nothing, and anything pending is
+ * picked up again after a restart.  Deliveries that are still failing after
+ * `max_age` are moved to the dead letter directory.
+ */
+pub struct DeliveryQueue {
+  dir: PathBuf,
+  max_age: Duration,
+  wake: Notify,
+  in_flight: Mutex<HashSet<String>>,
+  /**
+   * When each pending delivery is next due, read from the pending directory
+   * once at startup so the worker does not have to list it to find out.
+   */
+  schedule: Mutex<HashMap<String, DateTime<Utc>>>,
+  sequence: AtomicU64,
+  /** Deliveries in the pending directory, so probes need not list it. */
+  pending: AtomicUsize,
+  /** Deliveries in the dead letter directory. */
+  dead: AtomicUsize,
+}
+
+impl DeliveryQueue {
+  pub fn open(dir: &Path, max_age: Duration) -> Result<DeliveryQueue, String> {
+    for sub in [PENDING_DIR, DEAD_DIR] {
+      fs::create_dir_all(dir.join(sub)).map_err(|e| {
+        format!(
+          "Failed to create queue directory '{}': {}",
+          dir.join(sub).display(),
+          e,
+        )
+      })?;
+    }
+    // A replay that was cut short by a restart leaves its dead letter
+    // claimed; put it back so that it can be replayed again.
+    for claimed in fs::read_dir(dir.join(DEAD_DIR))
+      .map_err(|e| format!("Failed to read '{}': {}", dir.display(), e))?
+      .flatten()
+      .map(|e| e.path())
+      .filter(|p| p.extension().is_some_and(|ext| ext == CLAIMED_EXT))
+    {
+      fs::rename(&claimed, claimed.with_extension("json")).map_err(|e| {
+        format!("Failed to restore '{}': {}", claimed.display(), e)
+      })?;
+    }
+    Ok(DeliveryQueue {
+      dir: dir.to_path_buf(),
+      max_age,
+      wake: Notify::new(),
+      in_flight: Mutex::new(HashSet::new()),
+      schedule: Mutex::new(
+        list(&dir.join(PENDING_DIR))
+          .into_iter()
+          .map(|delivery| (delivery.id, delivery.next_attempt_at))
+          .collect(),
+      ),
+      sequence: AtomicU64::new(0),
+      pending: AtomicUsize::new(count(&dir.join(PENDING_DIR))),
+      dead: AtomicUsize::new(count(&dir.join(DEAD_DIR))),
+    })
+  }
+
+  /**
+   * Writes a delivery to the spool and wakes the worker.  The files are
+   * written and synced on the blocking thread pool, so a slow disk does not
+   * hold up the other requests.
+   */
+  #[allow(clippy::too_many_arguments)]
+  pub async fn enqueue(
+    &self,
+    delivery_id: Option<&str>,
+    event: &str,
+    repository: Option<&str>,
+    headers: &RHeaderMap,
+    body: &web::Bytes,
+    route: &str,
+    targets: &[JenkinsTarget],
+  ) -> io::Result<String> {
+    let now = Utc::now();
+    let id = format!(
+      "{}-{}",
+      now.format("%Y%m%d%H%M%S%9f"),
+      self.sequence.fetch_add(1, Ordering::Relaxed),
+    );
+    let delivery = QueuedDelivery {
+      id: id.clone(),
+      delivery_id: delivery_id.map(str::to_string),
+      event: event.to_string(),
+      repository: repository.map(str::to_string),
+      headers: headers
+        .iter()
+        .filter_map(|(name, value)| {
+          value
+            .to_str()
+            .ok()
+            .map(|v| (name.as_str().to_string(), v.to_string()))
+        })
+        .collect(),
+      targets: targets
+        .iter()
+        .map(|t| QueuedTarget {
+          name: t.name.clone(),
+          url: t.url.clone(),
+          route: Some(route.to_string()),
+        })
+        .collect(),
+      total_targets: targets.len(),
+      successes: 0,
+      received_at: now,
+      attempts: 0,
+      next_attempt_at: now,
+      last_error: None,
+    };
+    let metadata = serde_json::to_vec_pretty(&delivery)?;
+    let pending = self.dir.join(PENDING_DIR);
+    let body = body.clone();
+    let name = id.clone();
+    web::block(move || {
+      // The body goes first: a metadata file is only ever visible once its
+      // body is safely on disk.
+      write_atomic(&pending.join(format!("{}.body", name)), &body)?;
+      write_atomic(&pending.join(format!("{}.json", name)), &metadata)
+    })
+    .await
+    .map_err(io::Error::other)??;
+    self.pending.fetch_add(1, Ordering::Relaxed);
+    self.schedule.lock().unwrap().insert(id.clone(), now);
+    self.wake.notify_one();
+    Ok(id)
+  }
+
+  /** The pending deliveries that are due by `now`. */
+  fn due(&self, now: DateTime<Utc>) -> Vec<String> {
+    self
+      .schedule
+      .lock()
+      .unwrap()
+      .iter()
+      .filter(|(_, next_attempt_at)| **next_attempt_at <= now)
+      .map(|(id, _)| id.clone())
+      .collect()
+  }
+
+  pub fn dead_letters(&self) -> Vec<QueuedDelivery> {
+    list(&self.dir.join(DEAD_DIR))
+  }
+
+  /**
+   * Whether a delivery could be queued right now, found by creating and
+   * removing a file in the pending directory.
+   */
+  pub fn is_writable(&self) -> bool {
+    let probe = self.dir.join(PENDING_DIR).join(".writable");
+    File::create(&probe)
+      .and_then(|_| fs::remove_file(&probe))
+      .is_ok()
+  }
+
+  /** How many deliveries are pending, without reading them. */
+  pub fn depth(&self) -> usize {
+    self.pending.load(Ordering::Relaxed)
+  }
+
+  /** How many dead letters there are, without reading them. */
+  pub fn dead_letter_count(&self) -> usize {
+    self.dead.load(Ordering::Relaxed)
+  }
+
+  fn counter(&self, sub: &str) -> &AtomicUsize {
+    if sub == DEAD_DIR {
+      &self.dead
+    } else {
+      &self.pending
+    }
+  }
+
+  /**
+   * Takes a dead letter off the list for as long as it is being replayed, by
+   * renaming its metadata, so that two replays cannot both send it.
+   */
+  fn claim_dead_letter(&self, id: &str) -> Result<QueuedDelivery, ProxyError> {
+    // IDs come from the admin API, so anything that would not survive
+    // sanitizing cannot name a file in the queue.
+    if sanitize_id(id) != id {
+      return Err(ProxyError::NotFound(format!("No dead letter '{}'", id)));
+    }
+    let dead = self.dir.join(DEAD_DIR);
+    let claimed = dead.join(format!("{}.{}", id, CLAIMED_EXT));
+    match fs::rename(dead.join(format!("{}.json", id)), &claimed) {
+      Ok(()) => {}
+      Err(e) if e.kind() == io::ErrorKind::NotFound && claimed.exists() => {
+        return Err(ProxyError::Conflict(format!(
+          "Dead letter '{}' is already being replayed",
+          id
+        )));
+      }
+      Err(e) if e.kind() == io::ErrorKind::NotFound => {
+        return Err(ProxyError::NotFound(format!("No dead letter '{}'", id)));
+      }
+      Err(e) => {
+        return Err(ProxyError::ServerError(format!(
+          "Dead letter '{}': {}",
+          id, e
+        )));
+      }
+    }
+    let delivery = fs::read(&claimed)
+      .map_err(|e| e.to_string())
+      .and_then(|b| serde_json::from_slice(&b).map_err(|e| e.to_string()));
+    delivery.map_err(|e| {
+      self.release_dead_letter(id);
+      ProxyError::ServerError(format!("Dead letter '{}': {}", id, e))
+    })
+  }
+
+  fn release_dead_letter(&self, id: &str) {
+    let dead = self.dir.join(DEAD_DIR);
+    if let Err(e) = fs::rename(
+      dead.join(format!("{}.{}", id, CLAIMED_EXT)),
+      dead.join(format!("{}.json", id)),
+    ) {
+      error!("Failed to release dead letter {}: {}", id, e);
+    }
+  }
+
+  /**
+   * Sends a dead letter through the same fan-out as a live webhook, to the
+   * targets that never accepted it.  It is removed once delivered and keeps
+   * its place among the dead letters, with the new error, otherwise.  Only
+   * the targets that failed are kept: those still pending when the fan-out
+   * was decided go on delivering in the background, and sending them the
+   * delivery again would start a second build.
+   */
+  pub async fn replay(
+    &self,
+    state: &StateHandle,
+    id: &str,
+  ) -> Result<FanOutSummary, ProxyError> {
+    let mut delivery = self.claim_dead_letter(id)?;
+    let body = self.body(DEAD_DIR, id).map_err(|e| {
+      self.release_dead_letter(id);
+      ProxyError::ServerError(format!(
+        "Dead letter '{}' has no body: {}",
+        id, e
+      ))
+    })?;
+    let current = state.current();
+    let targets =
+      resolve_targets(&current.routes, &delivery.targets).map_err(|e| {
+        self.release_dead_letter(id);
+        ProxyError::Conflict(format!(
+          "Dead letter '{}' cannot be replayed: {}",
+          id, e
+        ))
+      })?;
+    info!(
+      "Replaying dead {} event {} to {} target(s)",
+      delivery.event,
+      id,
+      targets.len(),
+    );
+    let summary = fan_out(
+      &current.client,
+      &targets,
+      to_header_map(&delivery.headers),
+      body,
+      current.fan_out_mode,
+    )
+    .await;
+
+    let io_error = |e: io::Error| {
+      ProxyError::ServerError(format!("Dead letter '{}': {}", id, e))
+    };
+    let claimed = self
+      .dir
+      .join(DEAD_DIR)
+      .join(format!("{}.{}", id, CLAIMED_EXT));
+    delivery.targets.retain(|queued| {
+      summary.targets.iter().any(|outcome| {
+        outcome.target == queued.name
+          && matches!(outcome.outcome, Outcome::Failure)
+      })
+    });
+    if summary.delivered || delivery.targets.is_empty() {
+      info!("Replayed dead {} event {}", delivery.event, id);
+      fs::remove_file(&claimed)
+        .and_then(|_| {
+          fs::remove_file(self.dir.join(DEAD_DIR).join(format!("{}.body", id)))
+        })
+        .map_err(io_error)?;
+      self.dead.fetch_sub(1, Ordering::Relaxed);
+    } else {
+      delivery.attempts += 1;
+      delivery.last_error = Some(
+        summary
+          .targets
+          .iter()
+          .filter_map(|o| {
+            o.error.as_ref().map(|e| format!("{}: {}", o.target, e))
+          })
+          .collect::<Vec<_>>()
+          .join("; "),
+      );
+      warn!(
+        "Replay of dead {} event {} failed: {}",
+        delivery.event,
+        id,
+        delivery.last_error.as_deref().unwrap_or_default(),
+      );
+      self
+        .save(DEAD_DIR, &delivery)
+        .and_then(|_| fs::remove_file(&claimed))
+        .map_err(io_error)?;
+    }
+    Ok(summary)
+  }
+
+  fn body(&self, sub: &str, id: &str) -> io::Result<web::Bytes> {
+    fs::read(self.dir.join(sub).join(format!("{}.body", id)))
+      .map(web::Bytes::from)
+  }
+
+  fn load(&self, sub: &str, id: &str) -> io::Result<QueuedDelivery> {
+    let metadata = fs::read(self.dir.join(sub).join(format!("{}.json", id)))?;
+    Ok(serde_json::from_slice(&metadata)?)
+  }
+
+  fn save(&self, sub: &str, delivery: &QueuedDelivery) -> io::Result<()> {
+    write_atomic(
+      &self.dir.join(sub).join(format!("{}.json", delivery.id)),
+      &serde_json::to_vec_pretty(delivery)?,
+    )
+  }
+
+  /**
+   * Moves both files of a delivery between the pending and dead letter
+   * directories, metadata last so the move is never seen half done.  A
+   * delivery already at the destination is left alone and the move fails.
+   * A missing body is not an error, so that such a delivery can still be
+   * given up on.
+   */
+  fn relocate(&self, from: &str, to: &str, id: &str) -> io::Result<()> {
+    let metadata = self.dir.join(to).join(format!("{}.json", id));
+    if metadata.exists() {
+      return Err(io::Error::new(
+        io::ErrorKind::AlreadyExists,
+        format!("'{}' already exists", metadata.display()),
+      ));
+    }
+    for ext in ["body", "json"] {
+      let name = format!("{}.{}", id, ext);
+      match fs::rename(
+        self.dir.join(from).join(&name),
+        self.dir.join(to).join(&name),
+      ) {
+        Err(e) if ext == "body" && e.kind() == io::ErrorKind::NotFound => {}
+        result => result?,
+      }
+    }
+    self.counter(from).fetch_sub(1, Ordering::Relaxed);
+    self.counter(to).fetch_add(1, Ordering::Relaxed);
+    Ok(())
+  }
+
+  fn remove(&self, sub: &str, id: &str) -> io::Result<()> {
+    fs::remove_file(self.dir.join(sub).join(format!("{}.json", id)))?;
+    self.counter(sub).fetch_sub(1, Ordering::Relaxed);
+    fs::remove_file(self.dir.join(sub).join(format!("{}.body", id)))
+  }
+
+  /**
+   * Makes one attempt at a pending delivery, sending it only to the targets
+   * that have not accepted it yet.
+   */
+  #[instrument(
+    name = "queue_attempt",
+    skip_all,
+    fields(
+      queue_id = %delivery.id,
+      delivery_id = delivery.delivery_id.as_deref(),
+      event = %delivery.event,
+      attempt = delivery.attempts + 1,
+    ),
+  )]
+  async fn attempt(&self, state: &StateHandle, mut delivery: QueuedDelivery) {
+    let body = match self.body(PENDING_DIR, &delivery.id) {
+      Ok(body) => body,
+      Err(e) => {
+        delivery.last_error = Some(format!("No readable body: {}", e));
+        self.dead_letter(delivery);
+        return;
+      }
+    };
+    let current = state.current();
+    let targets = match resolve_targets(&current.routes, &delivery.targets) {
+      Ok(targets) => targets,
+      Err(e) => {
+        delivery.last_error = Some(e);
+        self.dead_letter(delivery);
+        return;
+      }
+    };
+    let headers = to_header_map(&delivery.headers);
+    let handles: Vec<_> = targets
+      .into_iter()
+      .map(|target| {
+        let client = current.client.clone();
+        let headers = headers.clone();
+        let body = body.clone();
+        tokio::spawn(
+            async move {
+              deliver_to_target(&client, &target, &headers, &body).await
+            }
+            .in_current_span(),
+          )
+      })
+      .collect();
+
+    let mut remaining = vec![];
+    let mut errors = vec![];
+    for (queued, handle) in delivery.targets.iter().zip(handles) {
+      match handle.await {
+        Ok(outcome) if matches!(outcome.outcome, Outcome::Success) => {
+          delivery.successes += 1;
+        }
+        Ok(outcome) => {
+          errors.push(format!(
+            "{}: {}",
+            queued.name,
+            outcome.error.unwrap_or_default(),
+          ));
+          remaining.push(queued.clone());
+        }
+        Err(e) => {
+          errors.push(format!("{}: {}", queued.name, e));
+          remaining.push(queued.clone());
+        }
+      }
+    }
+    delivery.targets = remaining;
+    delivery.attempts += 1;
+
+    let required = current
+      .fan_out_mode
+      .required_successes(delivery.total_targets);
+    if delivery.successes >= required || delivery.targets.is_empty() {
+      info!(
+        "Delivered queued {} event {} after {} attempt(s)",
+        delivery.event, delivery.id, delivery.attempts,
+      );
+      self.schedule.lock().unwrap().remove(&delivery.id);
+      if let Err(e) = self.remove(PENDING_DIR, &delivery.id) {
+        error!("Failed to remove delivered {}: {}", delivery.id, e);
+      }
+      return;
+    }
+
+    delivery.last_error = Some(errors.join("; "));
+    let age = (Utc::now() - delivery.received_at)
+      .to_std()
+      .unwrap_or_default();
+    if age >= self.max_age {
+      self.dead_letter(delivery);
+      return;
+    }
+
+    let delay = retry_delay(delivery.attempts);
+    delivery.next_attempt_at = Utc::now()
+      + chrono::Duration::from_std(delay).unwrap_or(chrono::TimeDelta::MAX);
+    warn!(
+      "Queued {} event {} failed attempt {}, retrying in {}s: {}",
+      delivery.event,
+      delivery.id,
+      delivery.attempts,
+      delay.as_secs(),
+      delivery.last_error.as_deref().unwrap_or_default(),
+    );
+    self
+      .schedule
+      .lock()
+      .unwrap()
+      .insert(delivery.id.clone(), delivery.next_attempt_at);
+    if let Err(e) = self.save(PENDING_DIR, &delivery) {
+      error!("Failed to update queued delivery {}: {}", delivery.id, e);
+    }
+  }
+
+  /**
+   * Gives up on a pending delivery, moving it to the dead letters with its
+   * `last_error`.
+   */
+  fn dead_letter(&self, delivery: QueuedDelivery) {
+    error!(
+      "Giving up on {} event {} after {} attempt(s), moving it to the dead \
+       letters: {}",
+      delivery.event,
+      delivery.id,
+      delivery.attempts,
+      delivery.last_error.as_deref().unwrap_or_default(),
+    );
+    self.schedule.lock().unwrap().remove(&delivery.id);
+    if let Err(e) = self
+      .save(PENDING_DIR, &delivery)
+      .and_then(|_| self.relocate(PENDING_DIR, DEAD_DIR, &delivery.id))
+    {
+      error!("Failed to dead letter {}: {}", delivery.id, e);
+    }
+  }
+}
+
+/**
+ * Delivers queued webhooks in the background.  Each due delivery gets its own
+ * task, so one unreachable controller does not hold up the rest of the queue.
+ */
+pub fn spawn_worker(queue: Arc<DeliveryQueue>, state: Arc<StateHandle>) {
+  let pending = queue.depth();
+  if pending > 0 {
+    info!("Resuming {} queued deliveries", pending);
+  }
+  tokio::spawn(async move {
+    loop {
+      for id in queue.due(Utc::now()) {
+        if !queue.in_flight.lock().unwrap().insert(id.clone()) {
+          continue;
+        }
+        let queue = queue.clone();
+        let state = state.clone();
+        tokio::spawn(async move {
+          match queue.load(PENDING_DIR, &id) {
+            Ok(delivery) => queue.attempt(&state, delivery).await,
+            Err(e) => {
+              error!("Failed to read queued delivery {}: {}", id, e);
+              queue.schedule.lock().unwrap().remove(&id);
+            }
+          }
+          queue.in_flight.lock().unwrap().remove(&id);
+        });
+      }
+      let _ = tokio::time::timeout(POLL_INTERVAL, queue.wake.notified()).await;
+    }
+  });
+}
+
+/**
+ * The current configuration of each queued target, or an error naming those
+ * that no longer have one.
+ */
+fn resolve_targets(
+  routes: &RouteTable,
+  queued: &[QueuedTarget],
+) -> Result<Vec<JenkinsTarget>, String> {
+  let mut targets = vec![];
+  let mut missing = vec![];
+  for queued in queued {
+    match routes.find_target(queued.route.as_deref(), &queued.name) {
+      Some(target) => targets.push(target),
+      None => missing.push(queued.name.as_str()),
+    }
+  }
+  if missing.is_empty() {
+    Ok(targets)
+  } else {
+    Err(format!("No longer configured: {}", missing.join(", ")))
+  }
+}
+
+fn retry_delay(attempts: u32) -> Duration {
+  FIRST_RETRY_DELAY
+    .saturating_mul(2u32.saturating_pow(attempts.saturating_sub(1)))
+    .min(MAX_RETRY_DELAY)
+}
+
+fn list(dir: &Path) -> Vec<QueuedDelivery> {
+  let Ok(entries) = fs::read_dir(dir) else {
+    return vec![];
+  };
+  let mut deliveries: Vec<QueuedDelivery> = entries
+    .flatten()
+    .map(|e| e.path())
+    .filter(|p| p.extension().is_some_and(|ext| ext == "json"))
+    .filter_map(|p| match fs::read(&p).map(|b| serde_json::from_slice(&b)) {
+      Ok(Ok(delivery)) => Some(delivery),
+      Ok(Err(e)) => {
+        warn!("Skipping unreadable queue entry '{}': {}", p.display(), e);
+        None
+      }
+      // Removed between listing and reading.
+      Err(_) => None,
+    })
+    .collect();
+  deliveries.sort_by_key(|d| d.received_at);
+  deliveries
+}
+
+fn count(dir: &Path) -> usize {
+  fs::read_dir(dir).map_or(0, |entries| {
+    entries
+      .flatten()
+      .filter(|e| e.path().extension().is_some_and(|ext| ext == "json"))
+      .count()
+  })
+}
+
+fn to_header_map(headers: &[(String, String)]) -> RHeaderMap {
+  let mut out = RHeaderMap::new();
+  for (name, value) in headers {
+    if let (Ok(n), Ok(v)) = (
+      RHeaderName::from_bytes(name.as_bytes()),
+      RHeaderValue::from_str(value),
+    ) {
+      out.insert(n, v);
+    }
+  }
+  out
+}
+
+/**
+ * Queue IDs from the admin API end up in file names, so anything other than
+ * the characters they are made of is dropped.
+ */
+fn sanitize_id(id: &str) -> String {
+  id.chars()
+    .filter(|c| c.is_ascii_alphanumeric() || *c == '-')
+    .collect()
+}
+
+fn write_atomic(path: &Path, contents: &[u8]) -> io::Result<()> {
+  let mut tmp = path.as_os_str().to_owned();
+  tmp.push(".tmp");
+  let mut file = File::create(&tmp)?;
+  file.write_all(contents)?;
+  file.sync_all()?;
+  fs::rename(&tmp, path)?;
+  if let Some(parent) = path.parent() {
+    File::open(parent)?.sync_all()?;
+  }
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::args::Args;
+  use crate::state::AppState;
+  use crate::test_support::{FakeJenkins, TempDir};
+  use clap::Parser;
+
+  /** A configuration whose only target answers as `answer` does. */
+  fn state(jenkins: &FakeJenkins, answer: &str) -> StateHandle {
+    let url = format!("{}/{}/github-webhook/", jenkins.url, answer);
+    let args = Args::try_parse_from([
+      "github-to-jenkins-webhook",
+      "--github-secret",
+      "secret",
+      "--jenkins-url",
+      &url,
+    ])
+    .unwrap();
+    StateHandle::new(AppState::build(&args, 0).unwrap(), args)
+  }
+
+  fn pending(queue: &DeliveryQueue) -> Vec<QueuedDelivery> {
+    list(&queue.dir.join(PENDING_DIR))
+  }
+
+  async fn enqueue(
+    queue: &DeliveryQueue,
+    state: &StateHandle,
+    delivery_id: &str,
+  ) -> String {
+    let targets = state.current().routes.targets();
+    queue
+      .enqueue(
+        Some(delivery_id),
+        "push",
+        Some("Codertocat/Hello-World"),
+        &RHeaderMap::new(),
+        &web::Bytes::from_static(b"{}"),
+        "default",
+        &targets,
+      )
+      .await
+      .unwrap()
+  }
+
+  #[test]
+  fn retries_back_off_up_to_ten_minutes() {
+    let delays: Vec<u64> = (1..=9)
+      .map(|attempts| retry_delay(attempts).as_secs())
+      .collect();
+    assert_eq!(delays, [5, 10, 20, 40, 80, 160, 320, 600, 600]);
+    assert_eq!(retry_delay(u32::MAX), MAX_RETRY_DELAY);
+  }
+
+  #[tokio::test]
+  async fn redeliveries_are_queued_side_by_side() {
+    let dir = TempDir::new();
+    let jenkins = FakeJenkins::start();
+    let state = state(&jenkins, "ok");
+    let queue = DeliveryQueue::open(dir.path(), MAX_RETRY_DELAY).unwrap();
+
+    let first = enqueue(&queue, &state, "72d3162e").await;
+    let second = enqueue(&queue, &state, "72d3162e").await;
+
+    assert_ne!(first, second);
+    assert_eq!(queue.depth(), 2);
+    let pending = pending(&queue);
+    assert_eq!(pending.len(), 2);
+    assert!(pending
+      .iter()
+      .all(|d| d.delivery_id.as_deref() == Some("72d3162e")));
+  }
+
+  #[tokio::test]
+  async fn moving_a_delivery_never_replaces_another() {
+    let dir = TempDir::new();
+    let jenkins = FakeJenkins::start();
+    let state = state(&jenkins, "ok");
+    let queue = DeliveryQueue::open(dir.path(), MAX_RETRY_DELAY).unwrap();
+    let id = enqueue(&queue, &state, "72d3162e").await;
+
+    queue.relocate(PENDING_DIR, DEAD_DIR, &id).unwrap();
+    assert!(pending(&queue).is_empty());
+    assert_eq!(queue.dead_letters().len(), 1);
+    assert_eq!((queue.depth(), queue.dead_letter_count()), (0, 1));
+
+    for ext in ["body", "json"] {
+      let name = format!("{}.{}", id, ext);
+      fs::write(dir.path().join(PENDING_DIR).join(&name), b"{}").unwrap();
+    }
+    let error = queue.relocate(PENDING_DIR, DEAD_DIR, &id).unwrap_err();
+    assert_eq!(error.kind(), io::ErrorKind::AlreadyExists);
+    assert_eq!(queue.dead_letters()[0].event, "push");
+  }
+
+  #[tokio::test]
+  async fn delivered_replays_are_removed() {
+    let dir = TempDir::new();
+    let jenkins = FakeJenkins::start();
+    let state = state(&jenkins, "ok");
+    let queue = DeliveryQueue::open(dir.path(), MAX_RETRY_DELAY).unwrap();
+    let id = enqueue(&queue, &state, "72d3162e").await;
+    queue.relocate(PENDING_DIR, DEAD_DIR, &id).unwrap();
+
+    let summary = queue.replay(&state, &id).await.unwrap();
+
+    assert!(summary.delivered);
+    assert!(queue.dead_letters().is_empty());
+    assert_eq!(queue.dead_letter_count(), 0);
+    assert_eq!(jenkins.received().len(), 1);
+  }
+
+  #[tokio::test]
+  async fn failed_replays_stay_dead_letters() {
+    let dir = TempDir::new();
+    let jenkins = FakeJenkins::start();
+    let state = state(&jenkins, "fail");
+    let queue = DeliveryQueue::open(dir.path(), MAX_RETRY_DELAY).unwrap();
+    let id = enqueue(&queue, &state, "72d3162e").await;
+    queue.relocate(PENDING_DIR, DEAD_DIR, &id).unwrap();
+
+    let summary = queue.replay(&state, &id).await.unwrap();
+
+    assert!(!summary.delivered);
+    let dead_letters = queue.dead_letters();
+    assert_eq!(dead_letters.len(), 1);
+    assert_eq!(dead_letters[0].attempts, 1);
+    assert_eq!(dead_letters[0].targets.len(), 1);
+    assert!(dead_letters[0].last_error.is_some());
+  }
+
+  #[tokio::test]
+  async fn a_dead_letter_is_replayed_once_at_a_time() {
+    let dir = TempDir::new();
+    let jenkins = FakeJenkins::start();
+    let state = state(&jenkins, "ok");
+    let queue = DeliveryQueue::open(dir.path(), MAX_RETRY_DELAY).unwrap();
+    let id = enqueue(&queue, &state, "72d3162e").await;
+    queue.relocate(PENDING_DIR, DEAD_DIR, &id).unwrap();
+
+    queue.claim_dead_letter(&id).unwrap();
+
+    assert!(queue.dead_letters().is_empty());
+    assert!(matches!(
+      queue.replay(&state, &id).await,
+      Err(ProxyError::Conflict(_)),
+    ));
+    assert!(jenkins.received().is_empty());
+  }
+
+  #[tokio::test]
+  async fn interrupted_replays_are_released_on_restart() {
+    let dir = TempDir::new();
+    let jenkins = FakeJenkins::start();
+    let state = state(&jenkins, "ok");
+    let queue = DeliveryQueue::open(dir.path(), MAX_RETRY_DELAY).unwrap();
+    let id = enqueue(&queue, &state, "72d3162e").await;
+    queue.relocate(PENDING_DIR, DEAD_DIR, &id).unwrap();
+    queue.claim_dead_letter(&id).unwrap();
+
+    let queue = DeliveryQueue::open(dir.path(), MAX_RETRY_DELAY).unwrap();
+
+    assert_eq!(queue.dead_letters().len(), 1);
+    assert_eq!((queue.depth(), queue.dead_letter_count()), (0, 1));
+    assert!(queue.replay(&state, &id).await.unwrap().delivered);
+  }
+
+  #[tokio::test]
+  async fn unknown_dead_letters_are_not_found() {
+    let dir = TempDir::new();
+    let jenkins = FakeJenkins::start();
+    let state = state(&jenkins, "ok");
+    let queue = DeliveryQueue::open(dir.path(), MAX_RETRY_DELAY).unwrap();
+
+    for id in ["missing", "../pending/missing"] {
+      assert!(matches!(
+        queue.replay(&state, id).await,
+        Err(ProxyError::NotFound(_)),
+      ));
+    }
+  }
+
+  #[tokio::test]
+  async fn targets_no_longer_configured_are_dead_lettered() {
+    let dir = TempDir::new();
+    let jenkins = FakeJenkins::start();
+    let state = state(&jenkins, "ok");
+    let queue = DeliveryQueue::open(dir.path(), MAX_RETRY_DELAY).unwrap();
+    let gone = JenkinsTarget::from_url(
+      "gone",
+      &format!("{}/ok/generic-webhook-trigger/invoke", jenkins.url),
+    );
+    queue
+      .enqueue(
+        Some("72d3162e"),
+        "push",
+        None,
+        &RHeaderMap::new(),
+        &web::Bytes::from_static(b"{}"),
+        "default",
+        &[gone],
+      )
+      .await
+      .unwrap();
+
+    queue.attempt(&state, pending(&queue).remove(0)).await;
+
+    assert!(jenkins.received().is_empty());
+    let dead_letters = queue.dead_letters();
+    assert_eq!(dead_letters.len(), 1);
+    assert_eq!(
+      dead_letters[0].last_error.as_deref(),
+      Some("No longer configured: gone")
+    );
+    assert!(matches!(
+      queue.replay(&state, &dead_letters[0].id).await,
+      Err(ProxyError::Conflict(_)),
+    ));
+    assert_eq!(queue.dead_letters().len(), 1);
+  }
+
+  #[tokio::test]
+  async fn next_attempts_are_scheduled_in_memory() {
+    let dir = TempDir::new();
+    let jenkins = FakeJenkins::start();
+    let state = state(&jenkins, "fail");
+    let queue = DeliveryQueue::open(dir.path(), MAX_RETRY_DELAY).unwrap();
+    let id = enqueue(&queue, &state, "72d3162e").await;
+    assert_eq!(queue.due(Utc::now()), [id.as_str()]);
+
+    queue.attempt(&state, pending(&queue).remove(0)).await;
+
+    let later = Utc::now() + chrono::Duration::minutes(1);
+    assert!(queue.due(Utc::now()).is_empty());
+    assert_eq!(queue.due(later), [id.as_str()]);
+    let queue = DeliveryQueue::open(dir.path(), MAX_RETRY_DELAY).unwrap();
+    assert!(queue.due(Utc::now()).is_empty());
+    assert_eq!(queue.due(later), [id]);
+  }
+
+  #[tokio::test]
+  async fn deliveries_without_a_body_are_dead_lettered() {
+    let dir = TempDir::new();
+    let jenkins = FakeJenkins::start();
+    let state = state(&jenkins, "ok");
+    let queue = DeliveryQueue::open(dir.path(), MAX_RETRY_DELAY).unwrap();
+    let id = enqueue(&queue, &state, "72d3162e").await;
+    fs::remove_file(dir.path().join(PENDING_DIR).join(format!("{}.body", id)))
+      .unwrap();
+
+    queue.attempt(&state, pending(&queue).remove(0)).await;
+
+    assert!(jenkins.received().is_empty());
+    assert!(queue.due(Utc::now()).is_empty());
+    assert_eq!((queue.depth(), queue.dead_letter_count()), (0, 1));
+    let dead_letters = queue.dead_letters();
+    assert!(dead_letters[0]
+      .last_error
+      .as_deref()
+      .unwrap()
+      .starts_with("No readable body"));
+  }
+
+  #[tokio::test]
+  async fn replays_are_not_repeated_to_pending_targets() {
+    let dir = TempDir::new();
+    let jenkins = FakeJenkins::start();
+    let failing = format!("{}/fail/github-webhook/", jenkins.url);
+    let slow = format!("{}/slow/github-webhook/", jenkins.url);
+    let args = Args::try_parse_from([
+      "github-to-jenkins-webhook",
+      "--github-secret",
+      "secret",
+      "--jenkins-url",
+      &failing,
+      "--jenkins-url",
+      &slow,
+    ])
+    .unwrap();
+    let state = StateHandle::new(AppState::build(&args, 0).unwrap(), args);
+    let queue = DeliveryQueue::open(dir.path(), MAX_RETRY_DELAY).unwrap();
+    let id = enqueue(&queue, &state, "72d3162e").await;
+    queue.relocate(PENDING_DIR, DEAD_DIR, &id).unwrap();
+
+    let summary = queue.replay(&state, &id).await.unwrap();
+
+    assert!(!summary.delivered);
+    assert!(matches!(summary.targets[1].outcome, Outcome::Pending));
+    let targets = &queue.dead_letters()[0].targets;
+    assert_eq!(targets.len(), 1);
+    assert!(targets[0].url.contains("/fail/"));
+  }
+}
@@ -0,0 +1,574 @@
+use glob::{MatchOptions, Pattern};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use crate::config::from_toml_file_with_path;
+use crate::github_types::GitHubWebhookPayload;
+use crate::jenkins::{Endpoint, JenkinsTarget, RouteEndpointOptions};
+use crate::variables::VARIABLE_NAMES;
+
+/**
+ * A routing table as written in a routes file.  Routes are evaluated in order
+ * and the first match wins; `default` decides what happens to everything
+ * else.
+ */
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RoutesConfig {
+  #[serde(default)]
+  pub default: DefaultRouteConfig,
+  #[serde(default, rename = "route")]
+  pub routes: Vec<RouteConfig>,
+}
+
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct DefaultRouteConfig {
+  #[serde(default)]
+  pub action: RouteAction,
+  #[serde(default)]
+  pub targets: Vec<String>,
+  pub token: Option<String>,
+  #[serde(default)]
+  pub payload: PayloadFormat,
+  pub job: Option<String>,
+  #[serde(default)]
+  pub parameters: BTreeMap<String, String>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RouteConfig {
+  pub name: Option<String>,
+  #[serde(default)]
+  pub events: Vec<String>,
+  pub repository: Option<String>,
+  #[serde(rename = "ref")]
+  pub ref_pattern: Option<String>,
+  pub base_branch: Option<String>,
+  pub organization: Option<String>,
+  #[serde(default)]
+  pub action: RouteAction,
+  #[serde(default)]
+  pub targets: Vec<String>,
+  /** The token Generic Webhook Trigger targets are invoked with. */
+  pub token: Option<String>,
+  /** What Generic Webhook Trigger targets are sent. */
+  #[serde(default)]
+  pub payload: PayloadFormat,
+  /** The job buildWithParameters targets build, as `folder/name`. */
+  pub job: Option<String>,
+  /** Build parameter names mapped to the payload variables they take. */
+  #[serde(default)]
+  pub parameters: BTreeMap<String, String>,
+}
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum PayloadFormat {
+  /** The webhook as GitHub sent it. */
+  #[default]
+  Original,
+  /** A flat object of the payload's variables. */
+  Flattened,
+}
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum RouteAction {
+  #[default]
+  Forward,
+  Drop,
+}
+
+#[derive(Clone, Debug)]
+pub enum RouteDecision {
+  Forward(Vec<JenkinsTarget>),
+  Drop,
+}
+
+#[derive(Clone, Debug)]
+pub struct Route {
+  pub name: String,
+  events: Vec<String>,
+  repository: Option<Pattern>,
+  ref_pattern: Option<Pattern>,
+  base_branch: Option<Pattern>,
+  organization: Option<Pattern>,
+  decision: RouteDecision,
+}
+
+#[derive(Clone, Debug)]
+pub struct RouteTable {
+  routes: Vec<Route>,
+  default: RouteDecision,
+}
+
+const CASE_INSENSITIVE: MatchOptions = MatchOptions {
+  case_sensitive: false,
+  require_literal_separator: false,
+  require_literal_leading_dot: false,
+};
+
+impl RouteTable {
+  /**
+   * With no routes, everything goes to the `--jenkins-url` targets.
+   */
+  pub fn forward_all(targets: Vec<JenkinsTarget>) -> RouteTable {
+    RouteTable {
+      routes: vec![],
+      default: RouteDecision::Forward(targets),
+    }
+  }
+
+  pub fn load(
+    path: &Path,
+    default_targets: &[JenkinsTarget],
+    named_targets: &BTreeMap<String, JenkinsTarget>,
+  ) -> Result<RouteTable, String> {
+    let config: RoutesConfig = from_toml_file_with_path(path, "routes file")?;
+    RouteTable::from_config(&config, default_targets, named_targets)
+  }
+
+  pub fn from_config(
+    config: &RoutesConfig,
+    default_targets: &[JenkinsTarget],
+    named_targets: &BTreeMap<String, JenkinsTarget>,
+  ) -> Result<RouteTable, String> {
+    let default = decision(
+      "default",
+      config.default.action,
+      &config.default.targets,
+      &RouteEndpointOptions {
+        token: config.default.token.clone(),
+        flatten: config.default.payload == PayloadFormat::Flattened,
+        job: config.default.job.clone(),
+        parameters: config.default.parameters.clone(),
+      },
+      default_targets,
+      named_targets,
+    )?;
+    let routes = config
+      .routes
+      .iter()
+      .enumerate()
+      .map(|(index, route)| {
+        let name = route
+          .name
+          .clone()
+          .unwrap_or_else(|| format!("route[{}]", index));
+        Ok(Route {
+          events: route.events.clone(),
+          repository: pattern(&name, route.repository.as_deref())?,
+          ref_pattern: pattern(&name, route.ref_pattern.as_deref())?,
+          base_branch: pattern(&name, route.base_branch.as_deref())?,
+          organization: pattern(&name, route.organization.as_deref())?,
+          decision: decision(
+            &name,
+            route.action,
+            &route.targets,
+            &RouteEndpointOptions {
+              token: route.token.clone(),
+              flatten: route.payload == PayloadFormat::Flattened,
+              job: route.job.clone(),
+              parameters: route.parameters.clone(),
+            },
+            default_targets,
+            named_targets,
+          )?,
+          name,
+        })
+      })
+      .collect::<Result<Vec<_>, String>>()?;
+    Ok(RouteTable { routes, default })
+  }
+
+  /**
+   * Every target the table can route to, once each.
+   */
+  pub fn targets(&self) -> Vec<JenkinsTarget> {
+    let mut targets: Vec<JenkinsTarget> = vec![];
+    let all = self
+      .routes
+      .iter()
+      .map(|route| &route.decision)
+      .chain(std::iter::once(&self.default))
+      .filter_map(|decision| match decision {
+        RouteDecision::Forward(targets) => Some(targets),
+        RouteDecision::Drop => None,
+      })
+      .flatten();
+    for target in all {
+      if !targets.iter().any(|t| t.name == target.name) {
+        targets.push(target.clone());
+      }
+    }
+    targets
+  }
+
+  /**
+   * Looks up a target by name, preferring the one `route` forwards to since
+   * routes can give the same target a different token.  `None` if `route`
+   * no longer exists, as whatever it added to the target is gone with it.
+   */
+  pub fn find_target(
+    &self,
+    route: Option<&str>,
+    name: &str,
+  ) -> Option<JenkinsTarget> {
+    let decision = match route {
+      Some("default") => Some(&self.default),
+      Some(route) => {
+        Some(&self.routes.iter().find(|r| r.name == route)?.decision)
+      }
+      None => None,
+    };
+    let in_route = match decision {
+      Some(RouteDecision::Forward(targets)) => {
+        targets.iter().find(|target| target.name == name).cloned()
+      }
+      _ => None,
+    };
+    in_route.or_else(|| {
+      self
+        .targets()
+        .into_iter()
+        .find(|target| target.name == name)
+    })
+  }
+
+  /**
+   * The first route with a condition on more of the payload than the
+   * repository and organization, which `--validation off` does not read.
+   */
+  pub fn route_reading_payload(&self) -> Option<&str> {
+    self
+      .routes
+      .iter()
+      .find(|route| route.ref_pattern.is_some() || route.base_branch.is_some())
+      .map(|route| route.name.as_str())
+  }
+
+  /**
+   * Whether any route lists `event_type` among its events, rather than
+   * matching it only by listing none.
+   */
+  pub fn names_event(&self, event_type: &str) -> bool {
+    self
+      .routes
+      .iter()
+      .any(|route| route.events.iter().any(|e| e == event_type))
+  }
+
+  /**
+   * Picks the first route that matches, returning its name for logging along
+   * with what to do with the event.
+   */
+  pub fn route(
+    &self,
+    event_type: &str,
+    payload: &GitHubWebhookPayload,
+  ) -> (&str, &RouteDecision) {
+    self
+      .routes
+      .iter()
+      .find(|route| route.matches(event_type, payload))
+      .map(|route| (route.name.as_str(), &route.decision))
+      .unwrap_or(("default", &self.default))
+  }
+}
+
+impl Route {
+  /**
+   * Every condition that is set has to hold.  A condition on a field the
+   * event does not have (such as a base branch on a push) never holds.
+   */
+  fn matches(&self, event_type: &str, payload: &GitHubWebhookPayload) -> bool {
+    (self.events.is_empty() || self.events.iter().any(|e| e == event_type))
+      && matches_opt(&self.repository, payload.repository_full_name())
+      && matches_opt(&self.organization, payload.organization_login())
+      && matches_opt(&self.base_branch, payload.pull_request_base_branch())
+      && self
+        .ref_pattern
+        .as_ref()
+        .is_none_or(|p| payload.git_ref().is_some_and(|r| matches_ref(p, &r)))
+  }
+}
+
+fn matches_opt(pattern: &Option<Pattern>, value: Option<&str>) -> bool {
+  match pattern {
+    None => true,
+    Some(p) => value.is_some_and(|v| p.matches_with(v, CASE_INSENSITIVE)),
+  }
+}
+
+/**
+ * Ref patterns that start with `refs/` match the full ref.  Anything else is
+ * taken to be a branch or tag name, so branches can be matched without
+ * spelling out `refs/heads/`.
+ */
+fn matches_ref(pattern: &Pattern, git_ref: &str) -> bool {
+  if pattern.as_str().starts_with("refs/") {
+    pattern.matches(git_ref)
+  } else {
+    let short = git_ref
+      .strip_prefix("refs/heads/")
+      .or_else(|| git_ref.strip_prefix("refs/tags/"))
+      .unwrap_or(git_ref);
+    pattern.matches(short)
+  }
+}
+
+fn pattern(route: &str, glob: Option<&str>) -> Result<Option<Pattern>, String> {
+  glob
+    .map(|g| {
+      Pattern::new(g).map_err(|e| {
+        format!("Route '{}' has an invalid pattern '{}': {}", route, g, e)
+      })
+    })
+    .transpose()
+}
+
+fn decision(
+  route: &str,
+  action: RouteAction,
+  targets: &[String],
+  options: &RouteEndpointOptions,
+  default_targets: &[JenkinsTarget],
+  named_targets: &BTreeMap<String, JenkinsTarget>,
+) -> Result<RouteDecision, String> {
+  let targets = match action {
+    RouteAction::Drop if !targets.is_empty() => {
+      return Err(format!(
+        "Route '{}' drops events but also lists targets",
+        route,
+      ))
+    }
+    RouteAction::Drop => return Ok(RouteDecision::Drop),
+    RouteAction::Forward if targets.is_empty() => {
+      if default_targets.is_empty() {
+        return Err(format!(
+          "Route '{}' forwards events but has no targets and no --jenkins-url \
+           was given",
+          route,
+        ));
+      }
+      default_targets.to_vec()
+    }
+    // Targets are either the name of a configured target or a URL.
+    RouteAction::Forward => targets
+      .iter()
+      .map(|target| match named_targets.get(target) {
+        Some(named) => Ok(named.clone()),
+        None => JenkinsTarget::from_base_url(target).map_err(|e| {
+          format!(
+            "Route '{}' has target '{}' which is neither a configured target \
+             nor a valid Jenkins URL: {}",
+            route, target, e,
+          )
+        }),
+      })
+      .collect::<Result<Vec<_>, String>>()?,
+  };
+  let has_target = |endpoint: fn(&Endpoint) -> bool| {
+    targets.iter().any(|target| endpoint(&target.endpoint))
+  };
+  if options.token.is_some()
+    && !has_target(|e| matches!(e, Endpoint::GenericWebhookTrigger { .. }))
+  {
+    return Err(format!(
+      "Route '{}' has a token but no Generic Webhook Trigger targets",
+      route,
+    ));
+  }
+  if (options.job.is_some() || !options.parameters.is_empty())
+    && !has_target(|e| matches!(e, Endpoint::BuildWithParameters { .. }))
+  {
+    return Err(format!(
+      "Route '{}' has a job or parameters but no buildWithParameters targets",
+      route,
+    ));
+  }
+  if let Some((parameter, variable)) = options
+    .parameters
+    .iter()
+    .find(|(_, variable)| !VARIABLE_NAMES.contains(&variable.as_str()))
+  {
+    return Err(format!(
+      "Route '{}' maps parameter '{}' to unknown variable '{}' (known: {})",
+      route,
+      parameter,
+      variable,
+      VARIABLE_NAMES.join(", "),
+    ));
+  }
+  targets
+    .into_iter()
+    .map(|target| target.for_route(route, options))
+    .collect::<Result<Vec<_>, String>>()
+    .map(RouteDecision::Forward)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::test_support::fixture;
+
+  fn table(routes: &str) -> Result<RouteTable, String> {
+    let config: RoutesConfig = toml::from_str(routes).unwrap();
+    let default =
+      JenkinsTarget::named("default", "https://ci.example.com/").unwrap();
+    let named = BTreeMap::from([(
+      "releases".to_string(),
+      JenkinsTarget::named("releases", "https://releases.example.com/")
+        .unwrap(),
+    )]);
+    RouteTable::from_config(&config, &[default], &named)
+  }
+
+  fn push() -> GitHubWebhookPayload {
+    GitHubWebhookPayload::Push(fixture(include_bytes!(
+      "../tests/fixtures/push.json"
+    )))
+  }
+
+  fn tag_push() -> GitHubWebhookPayload {
+    GitHubWebhookPayload::Push(fixture(include_bytes!(
+      "../tests/fixtures/push.tag.json"
+    )))
+  }
+
+  fn pull_request() -> GitHubWebhookPayload {
+    GitHubWebhookPayload::PullRequest(fixture(include_bytes!(
+      "../tests/fixtures/pull_request.opened.json"
+    )))
+  }
+
+  fn routed<'a>(
+    table: &'a RouteTable,
+    event_type: &str,
+    payload: &GitHubWebhookPayload,
+  ) -> (&'a str, Vec<String>) {
+    let (route, decision) = table.route(event_type, payload);
+    let targets = match decision {
+      RouteDecision::Forward(targets) => {
+        targets.iter().map(|t| t.name.clone()).collect()
+      }
+      RouteDecision::Drop => vec!["<dropped>".to_string()],
+    };
+    (route, targets)
+  }
+
+  #[test]
+  fn short_ref_patterns_match_branch_and_tag_names() {
+    let matches = |pattern: &str, git_ref: &str| {
+      matches_ref(&Pattern::new(pattern).unwrap(), git_ref)
+    };
+    assert!(matches("master", "refs/heads/master"));
+    assert!(matches("release/*", "refs/heads/release/1.2"));
+    assert!(matches("v*", "refs/tags/v1.0.0"));
+    assert!(!matches("main", "refs/heads/master"));
+    assert!(matches("refs/tags/*", "refs/tags/v1.0.0"));
+    assert!(!matches("refs/tags/*", "refs/heads/v1.0.0"));
+    assert!(!matches("refs/heads/*", "refs/tags/v1.0.0"));
+  }
+
+  #[test]
+  fn first_matching_route_wins() {
+    let table = table(
+      r#"
+      [[route]]
+      name = "tags"
+      events = ["push"]
+      ref = "refs/tags/*"
+      targets = ["releases"]
+
+      [[route]]
+      name = "hello-world"
+      repository = "codertocat/*"
+      targets = ["https://hello.example.com/"]
+      "#,
+    )
+    .unwrap();
+    assert_eq!(
+      routed(&table, "push", &tag_push()),
+      ("tags", vec!["releases".to_string()])
+    );
+    assert_eq!(
+      routed(&table, "push", &push()),
+      (
+        "hello-world",
+        vec!["https://hello.example.com/".to_string()]
+      )
+    );
+  }
+
+  #[test]
+  fn unmatched_events_take_the_default() {
+    let table = table(
+      r#"
+      [default]
+      action = "drop"
+
+      [[route]]
+      name = "pull requests into master"
+      events = ["pull_request"]
+      base_branch = "master"
+      "#,
+    )
+    .unwrap();
+    assert_eq!(
+      routed(&table, "pull_request", &pull_request()),
+      ("pull requests into master", vec!["default".to_string()])
+    );
+    // A push has no base branch, so the condition cannot hold.
+    assert_eq!(
+      routed(&table, "push", &push()),
+      ("default", vec!["<dropped>".to_string()])
+    );
+  }
+
+  #[test]
+  fn conditions_must_all_hold() {
+    let table = table(
+      r#"
+      [[route]]
+      name = "other organization"
+      repository = "Codertocat/Hello-World"
+      organization = "github"
+      action = "drop"
+      "#,
+    )
+    .unwrap();
+    assert_eq!(routed(&table, "push", &push()).0, "default");
+  }
+
+  #[test]
+  fn names_event_ignores_routes_for_every_event() {
+    let table = table(
+      r#"
+      [[route]]
+      name = "pings"
+      events = ["ping"]
+
+      [[route]]
+      name = "everything"
+      "#,
+    )
+    .unwrap();
+    assert!(table.names_event("ping"));
+    assert!(!table.names_event("push"));
+  }
+
+  #[test]
+  fn inconsistent_routes_are_rejected() {
+    for routes in [
+      "[[route]]\naction = \"drop\"\ntargets = [\"releases\"]",
+      "[[route]]\ntargets = [\"not a url\"]",
+      "[[route]]\nref = \"[\"",
+    ] {
+      assert!(table(routes).is_err(), "{} was accepted", routes);
+    }
+  }
+}
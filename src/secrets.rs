@@ -0,0 +1,265 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+use crate::github_types::PayloadIdentity;
+
+/**
+ * A webhook secret paired with an identifier that is safe to log.  The key ID
+ * lets operators see which secret matched a delivery, so an old secret can be
+ * retired once nothing is signed with it anymore.
+ */
+#[derive(Clone)]
+pub struct WebhookSecret {
+  pub key_id: String,
+  pub value: String,
+}
+
+impl fmt::Debug for WebhookSecret {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.debug_struct("WebhookSecret")
+      .field("key_id", &self.key_id)
+      .field("value", &"<redacted>")
+      .finish()
+  }
+}
+
+pub fn read_secret_file(path: &Path) -> Result<WebhookSecret, String> {
+  let value = fs::read_to_string(path)
+    .map(|s| s.trim().to_string())
+    .map_err(|e| {
+      format!(
+        "Failed to read GitHub secret from file '{}': {}",
+        path.display(),
+        e,
+      )
+    })?;
+  if value.is_empty() {
+    return Err(format!("GitHub secret file '{}' is empty", path.display()));
+  }
+  let key_id = path
+    .file_name()
+    .map(|n| n.to_string_lossy().to_string())
+    .unwrap_or_else(|| path.display().to_string());
+  Ok(WebhookSecret { key_id, value })
+}
+
+/**
+ * Reads every regular file in `dir` as a secret.  Dotfiles are skipped so that
+ * Kubernetes-style secret mounts (with their `..data` links) work as-is.
+ */
+pub fn read_secret_dir(dir: &Path) -> Result<Vec<WebhookSecret>, String> {
+  let entries = fs::read_dir(dir).map_err(|e| {
+    format!(
+      "Failed to read GitHub secret directory '{}': {}",
+      dir.display(),
+      e,
+    )
+  })?;
+  let mut paths = Vec::new();
+  for entry in entries {
+    let entry = entry.map_err(|e| {
+      format!(
+        "Failed to read GitHub secret directory '{}': {}",
+        dir.display(),
+        e,
+      )
+    })?;
+    let path = entry.path();
+    let hidden = entry.file_name().to_string_lossy().starts_with('.');
+    // Follow symlinks, since secret mounts are usually links to files.
+    if !hidden && path.metadata().map(|m| m.is_file()).unwrap_or(false) {
+      paths.push(path);
+    }
+  }
+  paths.sort();
+  paths.iter().map(|p| read_secret_file(p)).collect()
+}
+
+/**
+ * Secrets keyed by repository `full_name` and organization login, with a
+ * default set for everything else.  Keys are stored lowercased since GitHub
+ * treats owner and repository names case-insensitively.
+ */
+#[derive(Clone, Debug, Default)]
+pub struct SecretStore {
+  pub default: Vec<WebhookSecret>,
+  pub repositories: HashMap<String, Vec<WebhookSecret>>,
+  pub organizations: HashMap<String, Vec<WebhookSecret>>,
+}
+
+impl SecretStore {
+  pub fn is_empty(&self) -> bool {
+    self.default.is_empty()
+      && self.repositories.is_empty()
+      && self.organizations.is_empty()
+  }
+
+  pub fn add_repository_secret(&mut self, name: &str, secret: WebhookSecret) {
+    self
+      .repositories
+      .entry(name.to_lowercase())
+      .or_default()
+      .push(secret);
+  }
+
+  pub fn add_organization_secret(&mut self, name: &str, secret: WebhookSecret) {
+    self
+      .organizations
+      .entry(name.to_lowercase())
+      .or_default()
+      .push(secret);
+  }
+
+  /**
+   * Picks the secrets to verify a delivery against, along with a description
+   * of the scope they came from for logging.  The identity comes from an
+   * unverified body, which is fine: it only narrows which secrets are tried,
+   * and a forged identity still needs that secret to produce a valid
+   * signature.  A repository or organization with its own secrets never falls
+   * back to the default set.
+   */
+  pub fn candidates(
+    &self,
+    identity: &PayloadIdentity,
+  ) -> (String, &[WebhookSecret]) {
+    if let Some(full_name) = identity.repository_full_name() {
+      if let Some(secrets) = self.repositories.get(&full_name.to_lowercase()) {
+        return (format!("repository {}", full_name), secrets);
+      }
+    }
+    if let Some(login) = identity.organization_login() {
+      if let Some(secrets) = self.organizations.get(&login.to_lowercase()) {
+        return (format!("organization {}", login), secrets);
+      }
+    }
+    ("default".to_string(), &self.default)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::test_support::TempDir;
+
+  #[test]
+  fn secret_dir_reads_every_visible_file_in_order() {
+    let dir = TempDir::new();
+    dir.write("2-new", "new-secret\n");
+    dir.write("1-old", "  old-secret  ");
+    dir.write("..data", "not a secret");
+    std::fs::create_dir(dir.path().join("nested")).unwrap();
+
+    let secrets = read_secret_dir(dir.path()).unwrap();
+    let read: Vec<(&str, &str)> = secrets
+      .iter()
+      .map(|s| (s.key_id.as_str(), s.value.as_str()))
+      .collect();
+    assert_eq!(read, [("1-old", "old-secret"), ("2-new", "new-secret")]);
+  }
+
+  #[test]
+  fn empty_secret_file_is_an_error() {
+    let dir = TempDir::new();
+    let path = dir.write("blank", "\n");
+    assert!(read_secret_file(&path).is_err());
+  }
+
+  #[test]
+  fn secret_value_is_not_debug_printed() {
+    let secret = WebhookSecret {
+      key_id: "current".to_string(),
+      value: "hunter2".to_string(),
+    };
+    assert!(!format!("{:?}", secret).contains("hunter2"));
+  }
+
+  fn secret(key_id: &str) -> WebhookSecret {
+    WebhookSecret {
+      key_id: key_id.to_string(),
+      value: format!("{}-secret", key_id),
+    }
+  }
+
+  fn identity(payload: serde_json::Value) -> PayloadIdentity {
+    serde_json::from_value(payload).unwrap()
+  }
+
+  fn store() -> SecretStore {
+    let mut store = SecretStore {
+      default: vec![secret("default")],
+      ..SecretStore::default()
+    };
+    store.add_repository_secret("Octo-Org/Hello-World", secret("repo"));
+    store.add_organization_secret("octo-org", secret("org"));
+    store.add_organization_secret("octocat", secret("user"));
+    store
+  }
+
+  fn picked(store: &SecretStore, payload: serde_json::Value) -> String {
+    let (scope, secrets) = store.candidates(&identity(payload));
+    format!("{} {}", scope, secrets[0].key_id)
+  }
+
+  #[test]
+  fn repository_wins_over_organization_and_default() {
+    let store = store();
+    assert_eq!(
+      picked(
+        &store,
+        serde_json::json!({
+          "repository": { "full_name": "octo-org/hello-world" },
+          "organization": { "login": "octo-org" },
+        }),
+      ),
+      "repository octo-org/hello-world repo"
+    );
+    assert_eq!(
+      picked(
+        &store,
+        serde_json::json!({
+          "repository": { "full_name": "octo-org/other" },
+          "organization": { "login": "Octo-Org" },
+        }),
+      ),
+      "organization Octo-Org org"
+    );
+  }
+
+  #[test]
+  fn user_repositories_fall_back_to_their_owner() {
+    assert_eq!(
+      picked(
+        &store(),
+        serde_json::json!({ "repository": { "full_name": "octocat/spoon" } }),
+      ),
+      "organization octocat user"
+    );
+  }
+
+  #[test]
+  fn everything_else_uses_the_default_secrets() {
+    let store = store();
+    assert_eq!(
+      picked(
+        &store,
+        serde_json::json!({ "repository": { "full_name": "someone/else" } }),
+      ),
+      "default default"
+    );
+    assert_eq!(picked(&store, serde_json::json!({})), "default default");
+  }
+
+  #[test]
+  fn scoped_secrets_do_not_fall_back_to_the_default() {
+    let mut store = SecretStore::default();
+    store.add_organization_secret("octo-org", secret("org"));
+    let identity = identity(serde_json::json!({
+      "organization": { "login": "octo-org" },
+    }));
+    let (_, candidates) = store.candidates(&identity);
+    assert_eq!(candidates.len(), 1);
+    assert_eq!(candidates[0].key_id, "org");
+  }
+}
@@ -0,0 +1,269 @@
+use arc_swap::ArcSwap;
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+use tokio::signal::unix::{signal, SignalKind};
+use tracing::{error, info, warn};
+
+use crate::args::Args;
+use crate::filters::EventFilter;
+use crate::jenkins::FanOutMode;
+use crate::routing::RouteTable;
+use crate::secrets::SecretStore;
+use crate::webhook::Validation;
+
+/**
+ * Everything a request needs, built from one reading of the arguments, config
+ * file and secrets.  A reload builds a whole new `AppState` rather than
+ * changing this one.
+ */
+pub struct AppState {
+  pub generation: u64,
+  pub routes: RouteTable,
+  pub filter: EventFilter,
+  pub fan_out_mode: FanOutMode,
+  pub github_secrets: SecretStore,
+  pub allow_legacy_sha1_signature: bool,
+  pub forward_unknown_events: bool,
+  pub validation: Validation,
+  pub reject_invalid_payloads: bool,
+  pub admin_token: Option<String>,
+  pub client: reqwest::Client,
+}
+
+impl AppState {
+  pub fn build(args: &Args, generation: u64) -> Result<AppState, String> {
+    let jenkins_targets = args.get_jenkins_targets()?;
+    let routes = args.get_route_table(&jenkins_targets)?;
+    let filter = args.get_event_filter()?;
+    let fan_out_mode = args.get_fan_out_mode()?;
+    let validation = args.get_validation()?;
+    let github_secrets = args.get_secret_store()?;
+    let admin_token = args.get_admin_token()?;
+    let client = args.get_jenkins_client()?;
+
+    info!(
+      "Configuration generation {}: forwarding webhooks to [{}] ({:?})",
+      generation,
+      args.jenkins_url.join(", "),
+      fan_out_mode,
+    );
+    info!(
+      "Loaded {} default GitHub secret(s), secrets for {} repositories and \
+       {} organizations",
+      github_secrets.default.len(),
+      github_secrets.repositories.len(),
+      github_secrets.organizations.len(),
+    );
+    if !filter.is_empty() {
+      info!("Skipping events for: {}", filter.describe());
+    }
+    if validation == Validation::Off {
+      if let Some(route) = routes.route_reading_payload() {
+        return Err(format!(
+          "Route '{}' matches on a ref or base branch, which --validation off \
+           does not read from payloads",
+          route,
+        ));
+      }
+      if !filter.is_empty() {
+        return Err(format!(
+          "--validation off does not read payloads, so it cannot skip events \
+           for: {}",
+          filter.describe(),
+        ));
+      }
+    }
+    if validation != Validation::Strict {
+      warn!(
+        "Payloads are not checked against their schema (--validation {})",
+        validation.name(),
+      );
+    }
+    if args.allow_legacy_sha1_signature.unwrap_or(false) {
+      warn!("Legacy X-Hub-Signature (SHA-1) signatures will be accepted");
+    }
+    if let Some(user) = &args.jenkins_user {
+      info!("Authenticating to the --jenkins-url targets as {}", user);
+    }
+    if let Some(cert) = &args.jenkins_client_cert {
+      info!(
+        "Presenting client certificate {} to Jenkins",
+        cert.display()
+      );
+    }
+    if args.jenkins_insecure_skip_verify.unwrap_or(false) {
+      warn!(
+        "!!! TLS CERTIFICATE VERIFICATION FOR JENKINS IS DISABLED !!! \
+         Webhooks, including their secrets' signatures, can be read and \
+         altered by anyone in the network path. Never use \
+         --jenkins-insecure-skip-verify outside a lab."
+      );
+    }
+
+    Ok(AppState {
+      generation,
+      routes,
+      filter,
+      fan_out_mode,
+      github_secrets,
+      allow_legacy_sha1_signature: args
+        .allow_legacy_sha1_signature
+        .unwrap_or(false),
+      forward_unknown_events: args.forward_unknown_events.unwrap_or(false),
+      validation,
+      reject_invalid_payloads: args.reject_invalid_payloads.unwrap_or(false),
+      admin_token,
+      client,
+    })
+  }
+}
+
+/**
+ * The current `AppState`, swapped atomically on reload.  Handlers take a
+ * snapshot with `current()` when they start, so a request that is already in
+ * flight finishes on the configuration it started with.
+ */
+pub struct StateHandle {
+  state: ArcSwap<AppState>,
+  /** The arguments as parsed, before the config file was merged in. */
+  cli_args: Args,
+  /** Why the latest reload failed, cleared by the next one to succeed. */
+  last_reload_error: Mutex<Option<String>>,
+}
+
+impl StateHandle {
+  pub fn new(state: AppState, cli_args: Args) -> StateHandle {
+    StateHandle {
+      state: ArcSwap::from_pointee(state),
+      cli_args,
+      last_reload_error: Mutex::new(None),
+    }
+  }
+
+  pub fn current(&self) -> Arc<AppState> {
+    self.state.load_full()
+  }
+
+  pub fn last_reload_error(&self) -> Option<String> {
+    self.last_reload_error.lock().unwrap().clone()
+  }
+
+  /**
+   * Re-reads the config file and secrets and swaps in the result.  On any
+   * failure the old state stays in place.
+   */
+  pub fn reload(&self, reason: &str) -> Result<Args, String> {
+    let generation = self.current().generation + 1;
+    info!("Reloading configuration ({})", reason);
+    let result = self.cli_args.clone().merge_config_file().and_then(|args| {
+      AppState::build(&args, generation).map(|state| (args, state))
+    });
+    match result {
+      Ok((args, state)) => {
+        self.state.store(Arc::new(state));
+        *self.last_reload_error.lock().unwrap() = None;
+        info!("Configuration generation {} is now active", generation);
+        Ok(args)
+      }
+      Err(e) => {
+        error!(
+          "RELOAD FAILED, still serving configuration generation {}: {}",
+          generation - 1,
+          e,
+        );
+        *self.last_reload_error.lock().unwrap() = Some(e.clone());
+        Err(e)
+      }
+    }
+  }
+}
+
+/**
+ * Every file whose change should trigger a reload.  Directories are listed
+ * too, since adding or removing a secret in a secret directory changes the
+ * directory's modification time.
+ */
+fn watched_paths(args: &Args) -> Vec<PathBuf> {
+  let mut paths: Vec<PathBuf> = args.config.iter().cloned().collect();
+  paths.extend(args.routes_file.iter().cloned());
+  paths.extend(args.github_secret_file.iter().cloned());
+  if let Some(dir) = &args.github_secret_dir {
+    paths.push(dir.clone());
+    if let Ok(entries) = std::fs::read_dir(dir) {
+      paths.extend(entries.flatten().map(|e| e.path()));
+    }
+  }
+  paths.extend(args.repository_secret_file.iter().map(|(_, p)| p.clone()));
+  paths.extend(args.organization_secret_file.iter().map(|(_, p)| p.clone()));
+  paths.extend(args.admin_token_file.iter().cloned());
+  paths.extend(args.jenkins_api_token_file.iter().cloned());
+  paths.extend(
+    args
+      .targets
+      .values()
+      .filter_map(|target| target.api_token_file.clone()),
+  );
+  paths.extend(args.jenkins_ca_bundle.iter().cloned());
+  paths.extend(args.jenkins_client_cert.iter().cloned());
+  paths.extend(args.jenkins_client_key.iter().cloned());
+  paths
+}
+
+pub fn modification_times(
+  paths: &[PathBuf],
+) -> BTreeMap<PathBuf, Option<SystemTime>> {
+  paths
+    .iter()
+    .map(|p| (p.clone(), p.metadata().and_then(|m| m.modified()).ok()))
+    .collect()
+}
+
+/**
+ * Reloads on SIGHUP, and whenever one of the watched files changes if
+ * `poll_interval` is set.  Files are polled rather than watched through the
+ * OS so that symlink swaps (as done by Kubernetes and agenix) are noticed.
+ */
+pub fn spawn_reloader(
+  handle: Arc<StateHandle>,
+  args: Args,
+  poll_interval: Option<Duration>,
+) -> Result<(), std::io::Error> {
+  let mut hangups = signal(SignalKind::hangup())?;
+  let initial_bind_address = args.bind_address();
+  tokio::spawn(async move {
+    let mut watched = watched_paths(&args);
+    let mut seen = modification_times(&watched);
+    let mut ticker =
+      tokio::time::interval(poll_interval.unwrap_or(Duration::from_secs(3600)));
+    ticker.tick().await;
+    loop {
+      let reason = tokio::select! {
+        _ = hangups.recv() => "SIGHUP".to_string(),
+        _ = ticker.tick(), if poll_interval.is_some() => {
+          let now = modification_times(&watched);
+          match now.iter().find(|(path, time)| seen.get(*path) != Some(time)) {
+            Some((path, _)) => format!("{} changed", path.display()),
+            None => continue,
+          }
+        }
+      };
+      // Remember what was seen even if the reload fails, so a broken file is
+      // reported once rather than on every poll.
+      seen = modification_times(&watched);
+      if let Ok(args) = handle.reload(&reason) {
+        if args.bind_address() != initial_bind_address {
+          warn!(
+            "The listen address changed to {} but only takes effect on \
+             restart",
+            args.bind_address(),
+          );
+        }
+        watched = watched_paths(&args);
+        seen = modification_times(&watched);
+      }
+    }
+  });
+  Ok(())
+}
@@ -0,0 +1,90 @@
+use opentelemetry::trace::TracerProvider;
+use opentelemetry_otlp::{SpanExporter, WithExportConfig};
+use opentelemetry_sdk::trace::SdkTracerProvider;
+use opentelemetry_sdk::Resource;
+use tracing::Level;
+use tracing_subscriber::filter::{EnvFilter, LevelFilter};
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::Layer;
+
+use crate::args::Args;
+use crate::error::ProxyError;
+
+/**
+ * `Full`, `Compact` and `Pretty` are the human-readable formats of
+ * `tracing_subscriber`.  `Json` writes one object per line, with the fields of
+ * the enclosing spans (delivery ID, event, repository, target, ...) as keys.
+ */
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LogFormat {
+  Full,
+  Compact,
+  Pretty,
+  Json,
+}
+
+/**
+ * Sets up logging, and span export over OTLP/HTTP when `--otlp-endpoint` is
+ * given.  Directives in `RUST_LOG` take precedence over `log_level`.  The
+ * returned provider must be shut down on exit so that the last batch of
+ * spans is sent.
+ */
+pub fn init(
+  args: &Args,
+  log_level: Level,
+  log_format: LogFormat,
+) -> Result<Option<SdkTracerProvider>, ProxyError> {
+  let provider = match &args.otlp_endpoint {
+    Some(endpoint) => {
+      let exporter = SpanExporter::builder()
+        .with_http()
+        .with_endpoint(format!("{}/v1/traces", endpoint.trim_end_matches('/')))
+        .build()
+        .map_err(|e| {
+          ProxyError::Configuration(format!(
+            "Invalid OTLP endpoint '{}': {}",
+            endpoint, e,
+          ))
+        })?;
+      Some(
+        SdkTracerProvider::builder()
+          .with_batch_exporter(exporter)
+          .with_resource(
+            Resource::builder()
+              .with_service_name(args.get_otel_service_name())
+              .build(),
+          )
+          .build(),
+      )
+    }
+    None => None,
+  };
+  let otel_layer = provider.as_ref().map(|provider| {
+    tracing_opentelemetry::layer()
+      .with_tracer(provider.tracer(env!("CARGO_PKG_NAME")))
+  });
+
+  let fmt_layer = tracing_subscriber::fmt::layer();
+  let fmt_layer = match log_format {
+    LogFormat::Full => fmt_layer.boxed(),
+    LogFormat::Compact => fmt_layer.compact().boxed(),
+    LogFormat::Pretty => fmt_layer.pretty().boxed(),
+    LogFormat::Json => fmt_layer.json().with_span_list(true).boxed(),
+  };
+
+  tracing_subscriber::registry()
+    .with(
+      EnvFilter::builder()
+        .with_default_directive(LevelFilter::from_level(log_level).into())
+        .from_env_lossy(),
+    )
+    .with(fmt_layer)
+    .with(otel_layer)
+    .init();
+
+  if let Some(endpoint) = &args.otlp_endpoint {
+    tracing::info!("Exporting traces to {}", endpoint);
+  }
+  Ok(provider)
+}
@@ -0,0 +1,151 @@
+use serde::de::DeserializeOwned;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+/**
+ * Parses a fixture from `tests/fixtures` as `T`, naming the path of the
+ * first field that does not fit if it fails.
+ */
+pub fn fixture<T: DeserializeOwned>(fixture: &[u8]) -> T {
+  let mut de = serde_json::Deserializer::from_slice(fixture);
+  serde_path_to_error::deserialize(&mut de)
+    .unwrap_or_else(|e| panic!("fixture fails at {}: {}", e.path(), e))
+}
+
+/**
+ * A fixture from `tests/fixtures` with `edit` applied, for payloads that
+ * differ from a real one in a single field.
+ */
+pub fn edited_fixture(
+  fixture: &[u8],
+  edit: impl FnOnce(&mut serde_json::Value),
+) -> Vec<u8> {
+  let mut payload: serde_json::Value = serde_json::from_slice(fixture).unwrap();
+  edit(&mut payload);
+  serde_json::to_vec(&payload).unwrap()
+}
+
+/**
+ * A directory under the system temporary directory, removed again when
+ * dropped.  Tests run in parallel, so every one gets a name of its own.
+ */
+pub struct TempDir(PathBuf);
+
+impl TempDir {
+  pub fn new() -> TempDir {
+    static NEXT: AtomicUsize = AtomicUsize::new(0);
+    let path = std::env::temp_dir().join(format!(
+      "{}-{}-{}",
+      env!("CARGO_PKG_NAME"),
+      std::process::id(),
+      NEXT.fetch_add(1, Ordering::Relaxed),
+    ));
+    std::fs::create_dir_all(&path).unwrap();
+    TempDir(path)
+  }
+
+  pub fn path(&self) -> &Path {
+    &self.0
+  }
+
+  /** Writes `contents` to `name` in the directory and returns its path. */
+  pub fn write(&self, name: &str, contents: &str) -> PathBuf {
+    let path = self.0.join(name);
+    std::fs::write(&path, contents).unwrap();
+    path
+  }
+}
+
+impl Drop for TempDir {
+  fn drop(&mut self) {
+    let _ = std::fs::remove_dir_all(&self.0);
+  }
+}
+
+/**
+ * A request as `FakeJenkins` received it.
+ */
+#[derive(Clone, Debug)]
+pub struct ReceivedRequest {
+  pub path: String,
+  pub body: String,
+}
+
+/**
+ * An HTTP server standing in for Jenkins.  The first segment of the request
+ * path picks the answer: `/ok/...` gets a 200, `/slow/...` a 200 after two
+ * seconds, and anything else a 500.  Every request is recorded.
+ */
+pub struct FakeJenkins {
+  pub url: String,
+  received: Arc<Mutex<Vec<ReceivedRequest>>>,
+}
+
+impl FakeJenkins {
+  pub fn start() -> FakeJenkins {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    let received = Arc::new(Mutex::new(vec![]));
+    let recorder = received.clone();
+    thread::spawn(move || {
+      for stream in listener.incoming().flatten() {
+        let recorder = recorder.clone();
+        thread::spawn(move || answer(stream, &recorder));
+      }
+    });
+    FakeJenkins { url, received }
+  }
+
+  pub fn received(&self) -> Vec<ReceivedRequest> {
+    self.received.lock().unwrap().clone()
+  }
+}
+
+fn answer(mut stream: TcpStream, recorder: &Mutex<Vec<ReceivedRequest>>) {
+  let mut reader = BufReader::new(stream.try_clone().unwrap());
+  let mut request_line = String::new();
+  if reader.read_line(&mut request_line).is_err() {
+    return;
+  }
+  let path = request_line
+    .split_whitespace()
+    .nth(1)
+    .unwrap_or("/")
+    .to_string();
+  let mut content_length = 0;
+  loop {
+    let mut line = String::new();
+    if reader.read_line(&mut line).unwrap_or(0) == 0 || line == "\r\n" {
+      break;
+    }
+    if let Some((name, value)) = line.split_once(':') {
+      if name.eq_ignore_ascii_case("content-length") {
+        content_length = value.trim().parse().unwrap_or(0);
+      }
+    }
+  }
+  let mut body = vec![0; content_length];
+  let _ = reader.read_exact(&mut body);
+  recorder.lock().unwrap().push(ReceivedRequest {
+    path: path.clone(),
+    body: String::from_utf8_lossy(&body).to_string(),
+  });
+  let status = match path.split('/').nth(1) {
+    Some("ok") => "200 OK",
+    Some("slow") => {
+      thread::sleep(Duration::from_secs(2));
+      "200 OK"
+    }
+    _ => "500 Internal Server Error",
+  };
+  let _ = write!(
+    stream,
+    "HTTP/1.1 {}\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+    status,
+  );
+}
@@ -0,0 +1,132 @@
+use arc_swap::ArcSwap;
+use rustls::crypto::CryptoProvider;
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use rustls::server::{ClientHello, ResolvesServerCert};
+use rustls::sign::CertifiedKey;
+use rustls::ServerConfig;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::signal::unix::{signal, SignalKind};
+use tracing::{error, info};
+
+use crate::state::modification_times;
+
+/**
+ * Serves the listener's certificate, and swaps in a new one when the files
+ * change so that renewals (certbot, cert-manager, ...) need no restart.
+ * Handshakes in progress keep the certificate they started with.
+ */
+#[derive(Debug)]
+pub struct CertificateResolver {
+  cert: PathBuf,
+  key: PathBuf,
+  current: ArcSwap<CertifiedKey>,
+}
+
+impl CertificateResolver {
+  pub fn new(cert: &Path, key: &Path) -> Result<CertificateResolver, String> {
+    Ok(CertificateResolver {
+      cert: cert.to_path_buf(),
+      key: key.to_path_buf(),
+      current: ArcSwap::from_pointee(load_certified_key(cert, key)?),
+    })
+  }
+
+  /**
+   * On failure the old certificate stays in use.
+   */
+  fn reload(&self, reason: &str) {
+    match load_certified_key(&self.cert, &self.key) {
+      Ok(certified_key) => {
+        self.current.store(Arc::new(certified_key));
+        info!(
+          "Reloaded TLS certificate {} ({})",
+          self.cert.display(),
+          reason
+        );
+      }
+      Err(e) => error!(
+        "TLS CERTIFICATE RELOAD FAILED, still serving the previous one: {}",
+        e,
+      ),
+    }
+  }
+
+  pub fn server_config(self: &Arc<Self>) -> ServerConfig {
+    ServerConfig::builder()
+      .with_no_client_auth()
+      .with_cert_resolver(self.clone())
+  }
+}
+
+impl ResolvesServerCert for CertificateResolver {
+  fn resolve(
+    &self,
+    _client_hello: ClientHello<'_>,
+  ) -> Option<Arc<CertifiedKey>> {
+    Some(self.current.load_full())
+  }
+}
+
+fn load_certified_key(cert: &Path, key: &Path) -> Result<CertifiedKey, String> {
+  let chain = CertificateDer::pem_file_iter(cert)
+    .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+    .map_err(|e| {
+      format!("Failed to read TLS certificate '{}': {}", cert.display(), e)
+    })?;
+  if chain.is_empty() {
+    return Err(format!(
+      "TLS certificate '{}' contains no certificates",
+      cert.display()
+    ));
+  }
+  let private_key = PrivateKeyDer::from_pem_file(key).map_err(|e| {
+    format!("Failed to read TLS key '{}': {}", key.display(), e)
+  })?;
+  let provider = CryptoProvider::get_default()
+    .cloned()
+    .unwrap_or_else(|| Arc::new(rustls::crypto::aws_lc_rs::default_provider()));
+  CertifiedKey::from_der(chain, private_key, &provider).map_err(|e| {
+    format!(
+      "TLS certificate '{}' and key '{}' do not make a usable pair: {}",
+      cert.display(),
+      key.display(),
+      e,
+    )
+  })
+}
+
+/**
+ * Reloads the certificate on SIGHUP, and when its files change if
+ * `poll_interval` is set, the same way the configuration is reloaded.
+ */
+pub fn spawn_certificate_reloader(
+  resolver: Arc<CertificateResolver>,
+  poll_interval: Option<Duration>,
+) -> Result<(), std::io::Error> {
+  let mut hangups = signal(SignalKind::hangup())?;
+  tokio::spawn(async move {
+    let watched = vec![resolver.cert.clone(), resolver.key.clone()];
+    let mut seen = modification_times(&watched);
+    let mut ticker =
+      tokio::time::interval(poll_interval.unwrap_or(Duration::from_secs(3600)));
+    ticker.tick().await;
+    loop {
+      let reason = tokio::select! {
+        _ = hangups.recv() => "SIGHUP".to_string(),
+        _ = ticker.tick(), if poll_interval.is_some() => {
+          let now = modification_times(&watched);
+          match now.iter().find(|(path, time)| seen.get(*path) != Some(time)) {
+            Some((path, _)) => format!("{} changed", path.display()),
+            None => continue,
+          }
+        }
+      };
+      seen = modification_times(&watched);
+      resolver.reload(&reason);
+    }
+  });
+  Ok(())
+}
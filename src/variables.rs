@@ -0,0 +1,110 @@
+use actix_web::web;
+use reqwest::header::HeaderMap as RHeaderMap;
+use std::collections::BTreeMap;
+
+use crate::error::ProxyError;
+use crate::github_types::GitHubWebhookPayload;
+use crate::webhook::{
+  parse_forwarded_payload, GITHUB_DELIVERY_HEADER, GITHUB_EVENT_HEADER,
+};
+
+/** Every variable `payload_variables` can produce. */
+pub const VARIABLE_NAMES: [&str; 15] = [
+  "event",
+  "delivery_id",
+  "action",
+  "repository",
+  "repository_name",
+  "organization",
+  "clone_url",
+  "ssh_url",
+  "sender",
+  "ref",
+  "branch",
+  "tag",
+  "sha",
+  "pr_number",
+  "base_branch",
+];
+
+/**
+ * A webhook boiled down to the handful of values a Jenkins job is usually
+ * parameterized with.  Values the event does not have (a branch on a release,
+ * a pull request number on a push) are left out rather than sent empty.
+ */
+pub fn payload_variables(
+  event_type: &str,
+  delivery_id: Option<&str>,
+  payload: &GitHubWebhookPayload,
+) -> BTreeMap<&'static str, String> {
+  [
+    ("event", Some(event_type.to_string())),
+    ("delivery_id", delivery_id.map(str::to_string)),
+    ("action", payload.action().map(str::to_string)),
+    (
+      "repository",
+      payload.repository_full_name().map(str::to_string),
+    ),
+    (
+      "repository_name",
+      payload.repository_name().map(str::to_string),
+    ),
+    (
+      "organization",
+      payload.organization_login().map(str::to_string),
+    ),
+    ("clone_url", payload.clone_url().map(str::to_string)),
+    ("ssh_url", payload.ssh_url().map(str::to_string)),
+    ("sender", payload.sender_login().map(str::to_string)),
+    ("ref", payload.git_ref()),
+    ("branch", payload.branch()),
+    ("tag", payload.tag()),
+    ("sha", payload.sha().map(str::to_string)),
+    (
+      "pr_number",
+      payload.pull_request_number().map(|n| n.to_string()),
+    ),
+    (
+      "base_branch",
+      payload.pull_request_base_branch().map(str::to_string),
+    ),
+  ]
+  .into_iter()
+  .filter_map(|(name, value)| value.map(|v| (name, v)))
+  .collect()
+}
+
+/**
+ * The variables of a webhook that is being forwarded.  The payload is parsed
+ * again from what is being forwarded, so that queued deliveries come out the
+ * same way on every attempt.
+ */
+pub fn forwarded_variables(
+  headers: &RHeaderMap,
+  body: &web::Bytes,
+) -> Result<BTreeMap<&'static str, String>, ProxyError> {
+  let header = |name: &str| headers.get(name).and_then(|v| v.to_str().ok());
+  let event_type = header(GITHUB_EVENT_HEADER).ok_or_else(|| {
+    ProxyError::InvalidPayload(
+      "Cannot read the variables of a payload without an event type"
+        .to_string(),
+    )
+  })?;
+  let payload = parse_forwarded_payload(event_type, body)?;
+  Ok(payload_variables(
+    event_type,
+    header(GITHUB_DELIVERY_HEADER),
+    &payload,
+  ))
+}
+
+/**
+ * The variables of a forwarded webhook as a flat JSON object.
+ */
+pub fn flattened_payload(
+  headers: &RHeaderMap,
+  body: &web::Bytes,
+) -> Result<web::Bytes, ProxyError> {
+  let variables = forwarded_variables(headers, body)?;
+  Ok(web::Bytes::from(serde_json::to_vec(&variables)?))
+}
@@ -1,100 +1,440 @@
 use actix_web::{web, HttpRequest, HttpResponse};
 use hmac::{Hmac, Mac};
-use reqwest::header::{
-  HeaderMap as RHeaderMap, HeaderName as RHeaderName,
-  HeaderValue as RHeaderValue,
-};
 use serde::de::DeserializeOwned;
+use serde_json::error::Category;
+use sha1::Sha1;
 use sha2::Sha256;
-use tracing::{debug, error, info, warn};
-use url::Url;
+use tracing::{debug, error, field, info, info_span, instrument, warn};
+use tracing::{Instrument, Span};
 
+use crate::dedup::Deduplicator;
+use crate::drift::{Mismatch, SchemaDrift};
 use crate::error::ProxyError;
-use crate::github_types::GitHubWebhookPayload;
-use crate::AppState;
+use crate::filters::FilterDecision;
+use crate::github_types::{
+  GitHubWebhookPayload, MinimalPayload, PayloadIdentity,
+};
+use crate::jenkins::{fan_out, forwarded_headers};
+use crate::metrics::METRICS;
+use crate::queue::DeliveryQueue;
+use crate::routing::RouteDecision;
+use crate::secrets::{SecretStore, WebhookSecret};
+use crate::state::{AppState, StateHandle};
 
 type HmacSha256 = Hmac<Sha256>;
+type HmacSha1 = Hmac<Sha1>;
 
 const GITHUB_SIGNATURE_HEADER: &str = "X-Hub-Signature-256";
-const GITHUB_EVENT_HEADER: &str = "X-GitHub-Event";
+const GITHUB_LEGACY_SIGNATURE_HEADER: &str = "X-Hub-Signature";
+pub const GITHUB_EVENT_HEADER: &str = "X-GitHub-Event";
+pub const GITHUB_DELIVERY_HEADER: &str = "X-GitHub-Delivery";
+/** Set to `true` to forward a delivery even if its ID was seen before. */
+const REDELIVER_HEADER: &str = "X-Webhook-Proxy-Redeliver";
 const MAX_PAYLOAD_SIZE: usize = 25 * 1024 * 1024;
 
+/** The event types with a payload type of their own. */
+pub const EVENT_TYPES: [&str; 20] = [
+  "push",
+  "pull_request",
+  "pull_request_review",
+  "pull_request_review_comment",
+  "issues",
+  "issue_comment",
+  "create",
+  "delete",
+  "fork",
+  "release",
+  "ping",
+  "status",
+  "check_run",
+  "check_suite",
+  "workflow_run",
+  "workflow_dispatch",
+  "repository_dispatch",
+  "merge_group",
+  "deployment",
+  "deployment_status",
+];
+
 /**
- * These both use the headers from `http` under the hood, but there are
- * disagreements on the version of `http` that reqwest and actix use.
+ * How much of a payload has to match the types in `github_types` before it
+ * is forwarded.
  */
-fn actix_to_reqwest_headers(req: &HttpRequest) -> RHeaderMap {
-  let mut out = RHeaderMap::new();
-  for (name, value) in req.headers().iter() {
-    if let (Ok(n), Ok(v)) = (
-      RHeaderName::from_bytes(name.as_str().as_bytes()),
-      RHeaderValue::from_bytes(value.as_bytes()),
-    ) {
-      out.insert(n, v);
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Validation {
+  /** The whole payload is parsed, and mismatches are reported. */
+  Strict,
+  /** Only the fields the proxy uses are parsed. */
+  Lenient,
+  /**
+   * Nothing is parsed beyond what picked the secret, so routes and filters
+   * can only go by the event type, repository and organization.
+   */
+  Off,
+}
+
+impl Validation {
+  pub fn name(&self) -> &'static str {
+    match self {
+      Validation::Strict => "strict",
+      Validation::Lenient => "lenient",
+      Validation::Off => "off",
     }
   }
-  out
 }
 
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum SignatureAlgorithm {
+  Sha256,
+  Sha1,
+}
+
+/**
+ * Every webhook is traced as a `webhook` span carrying the delivery ID, event,
+ * repository, sender and final outcome, with a child span per phase:
+ * `receive`, `verify`, `parse`, `route`, and then `forward` (holding a
+ * `jenkins` span per target) or `enqueue`.  Log lines inherit these as
+ * structured fields, so they are not repeated in the messages.
+ */
+#[instrument(
+  name = "webhook",
+  skip_all,
+  fields(
+    delivery_id = field::Empty,
+    event = field::Empty,
+    repository = field::Empty,
+    sender = field::Empty,
+    outcome = field::Empty,
+  ),
+)]
 pub async fn handle_webhook(
   req: HttpRequest,
   body: web::Bytes,
-  state: web::Data<AppState>,
+  state: web::Data<StateHandle>,
+  queue: Option<web::Data<DeliveryQueue>>,
+  dedup: Option<web::Data<Deduplicator>>,
+  drift: web::Data<SchemaDrift>,
 ) -> Result<HttpResponse, ProxyError> {
-  if body.len() > MAX_PAYLOAD_SIZE {
-    error!("Payload size {} exceeds maximum allowed size", body.len());
-    return Err(ProxyError::PayloadTooLarge);
+  let state = state.current();
+  let header = |name| req.headers().get(name).and_then(|h| h.to_str().ok());
+
+  let span = Span::current();
+
+  let (event_type, delivery_id) = info_span!("receive", size = body.len())
+    .in_scope(|| {
+      if body.len() > MAX_PAYLOAD_SIZE {
+        error!(size = body.len(), "Payload exceeds maximum allowed size");
+        return Err(ProxyError::PayloadTooLarge);
+      }
+      let event_type = header(GITHUB_EVENT_HEADER).unwrap_or("unknown");
+      let delivery_id = header(GITHUB_DELIVERY_HEADER);
+      span.record("event", event_type);
+      if let Some(delivery_id) = delivery_id {
+        span.record("delivery_id", delivery_id);
+      }
+      info!("Received GitHub webhook");
+      Ok((event_type, delivery_id))
+    })?;
+
+  let identity = info_span!("verify").in_scope(|| {
+    authenticate(
+      &req,
+      &body,
+      &state.github_secrets,
+      state.allow_legacy_sha1_signature,
+    )
+  })?;
+  if let Some(repository) = identity.repository_full_name() {
+    span.record("repository", repository);
+  }
+
+  METRICS.event_received(
+    event_type,
+    identity.repository_full_name(),
+    body.len(),
+  );
+
+  // Only claimed once the signature has passed, so that unsigned requests
+  // cannot mark an ID as seen.  The claim is given up again unless the
+  // delivery is forwarded or queued, so that GitHub's redelivery can retry.
+  let mut claim = None;
+  if let (Some(dedup), Some(id)) = (dedup, delivery_id) {
+    let redeliver =
+      header(REDELIVER_HEADER).is_some_and(|h| h.eq_ignore_ascii_case("true"));
+    claim = dedup.into_inner().claim(id);
+    if claim.is_none() {
+      if redeliver {
+        info!(
+          "Forwarding duplicate delivery as requested by {}",
+          REDELIVER_HEADER
+        );
+      } else {
+        span.record("outcome", "duplicate");
+        info!("Ignoring duplicate delivery");
+        return Ok(
+          HttpResponse::Ok()
+            .body(format!("Duplicate delivery {}, ignored", id)),
+        );
+      }
+    }
+  }
+
+  // GitHub pings a hook when it is created.  Routes that match every event
+  // would otherwise start builds for it, so it is only forwarded when a route
+  // asks for it by name.  It is told apart by its event type, since what the
+  // payload parses as depends on --validation.
+  if event_type == "ping" && !state.routes.names_event(event_type) {
+    let ping: MinimalPayload =
+      serde_json::from_slice(&body).unwrap_or_default();
+    span.record("outcome", "pong");
+    info!(hook_id = ?ping.hook_id, "Answered ping");
+    return Ok(HttpResponse::Ok().body(ping.zen.unwrap_or_default()));
+  }
+
+  let payload = info_span!("parse").in_scope(|| {
+    parse_verified_payload(event_type, &body, &identity, &state, &drift)
+  })?;
+  if let Some(sender) = payload.sender_login() {
+    span.record("sender", sender);
+  }
+
+  let decision = info_span!("filter").in_scope(|| {
+    let decision = state.filter.check(&payload);
+    match &decision {
+      FilterDecision::Forward if state.filter.is_empty() => {}
+      FilterDecision::Forward => info!("Event passed the filters"),
+      FilterDecision::Skip(reason) => {
+        span.record("outcome", "filtered");
+        info!(reason = %reason, "Skipping event");
+      }
+    }
+    decision
+  });
+  if let FilterDecision::Skip(reason) = decision {
+    return Ok(HttpResponse::Accepted().json(serde_json::json!({
+      "skipped": reason,
+    })));
   }
 
-  let event_type = req
-    .headers()
-    .get(GITHUB_EVENT_HEADER)
-    .and_then(|h| h.to_str().ok())
-    .unwrap_or("unknown");
+  let route_span = info_span!("route", route = field::Empty);
+  let (route, decision) =
+    route_span.in_scope(|| state.routes.route(event_type, &payload));
+  route_span.record("route", route);
+  let targets = route_span.in_scope(|| match decision {
+    RouteDecision::Drop => {
+      span.record("outcome", "dropped");
+      info!("Dropping event as directed by route");
+      None
+    }
+    RouteDecision::Forward(targets) => {
+      info!(targets = targets.len(), "Route matched, forwarding");
+      Some(targets)
+    }
+  });
+  let Some(targets) = targets else {
+    return Ok(
+      HttpResponse::Accepted().body(format!("Dropped by route '{}'", route)),
+    );
+  };
 
-  info!("Received GitHub webhook event: {}", event_type);
+  let headers = forwarded_headers(&req);
+
+  if let Some(queue) = queue {
+    let id = queue
+      .enqueue(
+        delivery_id,
+        event_type,
+        payload.repository_full_name(),
+        &headers,
+        &body,
+        route,
+        targets,
+      )
+      .instrument(info_span!("enqueue"))
+      .await
+      .map_err(|e| {
+        error!(error = %e, "Failed to queue webhook");
+        ProxyError::ServerError(format!("Failed to queue webhook: {}", e))
+      })?;
+    span.record("outcome", "queued");
+    info!(queue_id = %id, "Queued webhook");
+    if let Some(claim) = claim {
+      claim.keep().await;
+    }
+    return Ok(HttpResponse::Accepted().json(serde_json::json!({
+      "queued": id,
+    })));
+  }
 
-  let signature_header = req
-    .headers()
-    .get(GITHUB_SIGNATURE_HEADER)
-    .ok_or(ProxyError::MissingSignature)?;
+  let summary =
+    fan_out(&state.client, targets, headers, body, state.fan_out_mode)
+      .instrument(info_span!("forward", mode = state.fan_out_mode.name()))
+      .await;
+
+  if summary.delivered {
+    span.record("outcome", "delivered");
+    info!("Webhook delivered");
+    if let Some(claim) = claim {
+      claim.keep().await;
+    }
+    Ok(HttpResponse::Ok().json(summary))
+  } else {
+    span.record("outcome", "failed");
+    error!(mode = summary.mode, "Webhook was not delivered");
+    Ok(HttpResponse::BadGateway().json(summary))
+  }
+}
+
+/**
+ * Checks the request's signature against the secrets for the repository or
+ * organization the payload claims to come from, and returns that claim once it
+ * is known to be genuine.
+ */
+fn authenticate(
+  req: &HttpRequest,
+  body: &web::Bytes,
+  github_secrets: &SecretStore,
+  allow_legacy_sha1_signature: bool,
+) -> Result<PayloadIdentity, ProxyError> {
+  let signature_header = match req.headers().get(GITHUB_SIGNATURE_HEADER) {
+    Some(header) => Some((SignatureAlgorithm::Sha256, header)),
+    None if allow_legacy_sha1_signature => req
+      .headers()
+      .get(GITHUB_LEGACY_SIGNATURE_HEADER)
+      .map(|header| (SignatureAlgorithm::Sha1, header)),
+    None => None,
+  };
+  let Some((algorithm, signature_header)) = signature_header else {
+    METRICS.signature_failure(&ProxyError::MissingSignature);
+    return Err(ProxyError::MissingSignature);
+  };
 
   let signature = signature_header.to_str().map_err(|_| {
     ProxyError::InvalidHeader("Invalid signature header".to_string())
   })?;
 
-  if !verify_signature(&body, signature, &state.github_secret)? {
-    error!("Invalid signature from GitHub webhook");
+  // Phase one: look at only the claimed repository and organization to pick
+  // secrets.  Nothing else is read from the body until the HMAC passes.
+  let identity: PayloadIdentity =
+    serde_json::from_slice(body).unwrap_or_default();
+  let (secret_scope, secrets) = github_secrets.candidates(&identity);
+
+  if secrets.is_empty() {
+    error!(scope = %secret_scope, "No GitHub secret configured");
+    METRICS.signature_failure(&ProxyError::InvalidSignature);
     return Err(ProxyError::InvalidSignature);
   }
 
+  match verify_signature(body, signature, algorithm, secrets)
+    .inspect_err(|e| METRICS.signature_failure(e))?
+  {
+    Some(secret) if algorithm == SignatureAlgorithm::Sha1 => {
+      let count = METRICS.legacy_sha1_signature();
+      warn!(
+        key_id = %secret.key_id,
+        scope = %secret_scope,
+        sha1_deliveries = count,
+        "Signature verified using legacy SHA-1 X-Hub-Signature",
+      );
+    }
+    Some(secret) => {
+      info!(
+        key_id = %secret.key_id,
+        scope = %secret_scope,
+        "Signature verified",
+      );
+    }
+    None => {
+      error!(scope = %secret_scope, "Invalid signature from GitHub webhook");
+      METRICS.signature_failure(&ProxyError::InvalidSignature);
+      return Err(ProxyError::InvalidSignature);
+    }
+  }
+
+  Ok(identity)
+}
+
+/**
+ * Parses the payload as far as `--validation` asks.  In strict mode a payload
+ * that does not fit its type is reported with the path of the first field
+ * that does not, recorded in `drift`, and then forwarded from its minimal
+ * fields unless `--reject-invalid-payloads` is set.
+ */
+fn parse_verified_payload(
+  event_type: &str,
+  body: &web::Bytes,
+  identity: &PayloadIdentity,
+  state: &AppState,
+  drift: &SchemaDrift,
+) -> Result<GitHubWebhookPayload, ProxyError> {
   debug!(
     "Webhook payload (first 1000 chars): {}",
     String::from_utf8_lossy(&body[..body.len().min(1000)]),
   );
 
-  let payload: GitHubWebhookPayload =
-    parse_payload_from_header(event_type, &body)?;
-
-  if !payload.validate_required_fields() {
-    error!("GitHub webhook payload missing required fields");
-    return Err(ProxyError::InvalidPayload(
-      "Payload missing required fields".to_string(),
-    ));
+  if state.validation != Validation::Strict
+    && !state.forward_unknown_events
+    && !EVENT_TYPES.contains(&event_type)
+  {
+    let e = unsupported_event(event_type);
+    error!(error = %e, "Failed to parse GitHub webhook payload");
+    METRICS.parse_failure(&e);
+    return Err(e);
   }
 
-  info!("Valid GitHub webhook payload received");
+  let payload = match state.validation {
+    Validation::Strict => {
+      match typed_payload(event_type, body, state.forward_unknown_events) {
+        Ok(payload) => payload,
+        Err(ProxyError::SerdePath { path, source }) => {
+          // Syntax errors are a broken body, not a drifting schema.
+          if source.classify() == Category::Data {
+            drift.record(Mismatch::new(event_type, &path, &source));
+          }
+          if state.reject_invalid_payloads {
+            return Err(parse_error(ProxyError::SerdePath { path, source }));
+          }
+          warn!(
+            path = %path,
+            error = %source,
+            "Payload does not match the {} schema, forwarding it anyway",
+            event_type,
+          );
+          METRICS.parse_failure(&ProxyError::SerdePath { path, source });
+          minimal_payload(body)?
+        }
+        Err(e) => return Err(parse_error(e)),
+      }
+    }
+    Validation::Lenient => minimal_payload(body)?,
+    Validation::Off => {
+      return Ok(GitHubWebhookPayload::Minimal(
+        MinimalPayload::from_identity(identity),
+      ))
+    }
+  };
 
-  if !validate_jenkins_url(&state.jenkins_url)? {
-    error!("Invalid Jenkins URL configuration");
-    return Err(ProxyError::InvalidJenkinsUrl);
+  // Phase two: the typed payload must name the same repository and
+  // organization that picked the secret.
+  if payload.repository_full_name() != identity.repository_full_name()
+    || payload.explicit_organization_login()
+      != identity.organization.as_ref().map(|o| o.login.as_str())
+  {
+    error!("Payload identity does not match the one used to select a secret");
+    METRICS.signature_failure(&ProxyError::InvalidSignature);
+    return Err(ProxyError::InvalidSignature);
   }
 
-  let jenkins_webhook_path = construct_jenkins_url(&state.jenkins_url)?;
-
-  info!("Forwarding to Jenkins: {}", jenkins_webhook_path);
+  if !payload.validate_required_fields() {
+    error!("GitHub webhook payload missing required fields");
+    let e =
+      ProxyError::InvalidPayload("Payload missing required fields".to_string());
+    METRICS.parse_failure(&e);
+    return Err(e);
+  }
 
-  forward_to_jenkins(&req, &body, &jenkins_webhook_path).await
+  info!("Valid GitHub webhook payload received");
+  Ok(payload)
 }
 
 fn from_slice_with_path<T: DeserializeOwned>(
@@ -110,163 +450,394 @@ fn from_slice_with_path<T: DeserializeOwned>(
   }
 }
 
-fn parse_payload_from_header(
+/**
+ * Parses the payload as the type `event_type` names.  Events without a type of
+ * their own are rejected, unless `forward_unknown_events` is set, in which
+ * case they get only the fields every event shares.  Nothing is logged here,
+ * that is up to the caller.
+ */
+fn typed_payload(
   event_type: &str,
   body: &web::Bytes,
+  forward_unknown_events: bool,
 ) -> Result<GitHubWebhookPayload, ProxyError> {
   match event_type {
-    "push" => Ok(GitHubWebhookPayload::Push(from_slice_with_path(&body)?)),
-    "pull_request" => Ok(GitHubWebhookPayload::PullRequest(
-      from_slice_with_path(&body)?,
-    )),
-    "issues" => Ok(GitHubWebhookPayload::Issues(from_slice_with_path(&body)?)),
-    "issue_comment" => Ok(GitHubWebhookPayload::IssueComment(
-      from_slice_with_path(&body)?,
-    )),
-    "create" => Ok(GitHubWebhookPayload::Create(from_slice_with_path(&body)?)),
-    "delete" => Ok(GitHubWebhookPayload::Delete(from_slice_with_path(&body)?)),
-    "fork" => Ok(GitHubWebhookPayload::Fork(from_slice_with_path(&body)?)),
-    "release" => {
-      Ok(GitHubWebhookPayload::Release(from_slice_with_path(&body)?))
-    }
-    _ => Err(ProxyError::InvalidPayload(format!(
-      "Event type `{}' not supported.",
-      event_type,
-    ))),
+    "push" => from_slice_with_path(body).map(GitHubWebhookPayload::Push),
+    "pull_request" => {
+      from_slice_with_path(body).map(GitHubWebhookPayload::PullRequest)
+    }
+    "issues" => from_slice_with_path(body).map(GitHubWebhookPayload::Issues),
+    "issue_comment" => {
+      from_slice_with_path(body).map(GitHubWebhookPayload::IssueComment)
+    }
+    "create" => from_slice_with_path(body).map(GitHubWebhookPayload::Create),
+    "delete" => from_slice_with_path(body).map(GitHubWebhookPayload::Delete),
+    "fork" => from_slice_with_path(body).map(GitHubWebhookPayload::Fork),
+    "release" => from_slice_with_path(body).map(GitHubWebhookPayload::Release),
+    "ping" => from_slice_with_path(body).map(GitHubWebhookPayload::Ping),
+    "pull_request_review" => {
+      from_slice_with_path(body).map(GitHubWebhookPayload::PullRequestReview)
+    }
+    "pull_request_review_comment" => from_slice_with_path(body)
+      .map(GitHubWebhookPayload::PullRequestReviewComment),
+    "status" => from_slice_with_path(body).map(GitHubWebhookPayload::Status),
+    "check_run" => {
+      from_slice_with_path(body).map(GitHubWebhookPayload::CheckRun)
+    }
+    "check_suite" => {
+      from_slice_with_path(body).map(GitHubWebhookPayload::CheckSuite)
+    }
+    "workflow_run" => {
+      from_slice_with_path(body).map(GitHubWebhookPayload::WorkflowRun)
+    }
+    "workflow_dispatch" => {
+      from_slice_with_path(body).map(GitHubWebhookPayload::WorkflowDispatch)
+    }
+    "repository_dispatch" => {
+      from_slice_with_path(body).map(GitHubWebhookPayload::RepositoryDispatch)
+    }
+    "merge_group" => {
+      from_slice_with_path(body).map(GitHubWebhookPayload::MergeGroup)
+    }
+    "deployment" => {
+      from_slice_with_path(body).map(GitHubWebhookPayload::Deployment)
+    }
+    "deployment_status" => {
+      from_slice_with_path(body).map(GitHubWebhookPayload::DeploymentStatus)
+    }
+    _ if forward_unknown_events => {
+      debug!("Passing through event type without a typed payload");
+      from_slice_with_path(body).map(GitHubWebhookPayload::Generic)
+    }
+    _ => Err(unsupported_event(event_type)),
   }
-  .map_err(|e| {
-    error!("Failed to parse GitHub webhook payload: {}", e);
-    ProxyError::InvalidPayload(format!("Invalid GitHub webhook payload: {}", e))
-  })
 }
 
-fn verify_signature(
-  payload: &[u8],
-  signature: &str,
-  secret: &str,
-) -> Result<bool, ProxyError> {
-  if !signature.starts_with("sha256=") {
-    return Ok(false);
-  }
+fn unsupported_event(event_type: &str) -> ProxyError {
+  ProxyError::InvalidPayload(format!(
+    "Event type `{}' not supported.",
+    event_type,
+  ))
+}
 
-  let signature = &signature[7..];
+/**
+ * Logs and counts a payload that is being rejected.
+ */
+fn parse_error(e: ProxyError) -> ProxyError {
+  error!(error = %e, "Failed to parse GitHub webhook payload");
+  METRICS.parse_failure(&e);
+  ProxyError::InvalidPayload(format!("Invalid GitHub webhook payload: {}", e))
+}
 
-  let mut mac = HmacSha256::new_from_slice(secret.as_bytes())
-    .map_err(|_| ProxyError::HmacComputation)?;
+fn minimal_payload(
+  body: &web::Bytes,
+) -> Result<GitHubWebhookPayload, ProxyError> {
+  from_slice_with_path(body)
+    .map(GitHubWebhookPayload::Minimal)
+    .map_err(parse_error)
+}
 
-  mac.update(payload);
+/**
+ * The payload of a delivery that was accepted already, for reading the
+ * variables it is forwarded with.  It may have been accepted in lenient mode,
+ * or by a proxy with a different `--validation`, so this falls back to the
+ * minimal fields rather than failing.
+ */
+pub fn parse_forwarded_payload(
+  event_type: &str,
+  body: &web::Bytes,
+) -> Result<GitHubWebhookPayload, ProxyError> {
+  typed_payload(event_type, body, true).or_else(|_| minimal_payload(body))
+}
 
-  let result = mac.finalize();
-  let expected = hex::encode(result.into_bytes());
+/**
+ * Returns the first secret that produces the received signature, so that
+ * several secrets can be accepted while a webhook secret is being rotated.
+ * Comparison happens on the decoded bytes through `Mac::verify_slice`, which
+ * runs in constant time.
+ */
+fn verify_signature<'a>(
+  payload: &[u8],
+  signature: &str,
+  algorithm: SignatureAlgorithm,
+  secrets: &'a [WebhookSecret],
+) -> Result<Option<&'a WebhookSecret>, ProxyError> {
+  let prefix = match algorithm {
+    SignatureAlgorithm::Sha256 => "sha256=",
+    SignatureAlgorithm::Sha1 => "sha1=",
+  };
+  let Some(signature) = signature.strip_prefix(prefix) else {
+    return Ok(None);
+  };
 
-  debug!("Expected signature: {}", expected);
   debug!("Received signature: {}", signature);
 
-  Ok(expected == signature)
+  let Ok(signature) = hex::decode(signature) else {
+    warn!("Signature header is not valid hex");
+    return Ok(None);
+  };
+
+  for secret in secrets {
+    let matches = match algorithm {
+      SignatureAlgorithm::Sha256 => {
+        mac_matches::<HmacSha256>(payload, &signature, secret)?
+      }
+      SignatureAlgorithm::Sha1 => {
+        mac_matches::<HmacSha1>(payload, &signature, secret)?
+      }
+    };
+    if matches {
+      return Ok(Some(secret));
+    }
+    debug!("Signature does not match secret '{}'", secret.key_id);
+  }
+
+  Ok(None)
 }
 
-fn validate_jenkins_url(jenkins_url: &str) -> Result<bool, ProxyError> {
-  let url =
-    Url::parse(jenkins_url).map_err(|_| ProxyError::InvalidJenkinsUrl)?;
+fn mac_matches<M: Mac + hmac::digest::KeyInit>(
+  payload: &[u8],
+  signature: &[u8],
+  secret: &WebhookSecret,
+) -> Result<bool, ProxyError> {
+  let mut mac = <M as Mac>::new_from_slice(secret.value.as_bytes())
+    .map_err(|_| ProxyError::HmacComputation)?;
+  mac.update(payload);
+  Ok(mac.verify_slice(signature).is_ok())
+}
 
-  if url.scheme() != "http" && url.scheme() != "https" {
-    warn!("Jenkins URL has invalid scheme: {}", url.scheme());
-    return Ok(false);
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::args::Args;
+  use crate::test_support::{FakeJenkins, TempDir};
+  use actix_web::http::StatusCode;
+  use actix_web::{test as actix_test, App};
+  use clap::Parser;
+
+  const PUSH: &[u8] = include_bytes!("../tests/fixtures/push.json");
+  const PING: &[u8] = include_bytes!("../tests/fixtures/ping.json");
+  const CHECK_RUN: &[u8] =
+    include_bytes!("../tests/fixtures/check_run.completed.json");
+
+  fn secret(key_id: &str, value: &str) -> WebhookSecret {
+    WebhookSecret {
+      key_id: key_id.to_string(),
+      value: value.to_string(),
+    }
   }
 
-  if url.host_str().is_none() {
-    warn!("Jenkins URL has no host");
-    return Ok(false);
+  fn sign_sha256(secret: &str, payload: &[u8]) -> String {
+    let mut mac = HmacSha256::new_from_slice(secret.as_bytes()).unwrap();
+    mac.update(payload);
+    format!("sha256={}", hex::encode(mac.finalize().into_bytes()))
   }
 
-  let path = url.path();
-  if path.contains("..") || path.contains("//") {
-    warn!("Jenkins URL contains suspicious path traversal patterns");
-    return Ok(false);
+  fn sign_sha1(secret: &str, payload: &[u8]) -> String {
+    let mut mac = HmacSha1::new_from_slice(secret.as_bytes()).unwrap();
+    mac.update(payload);
+    format!("sha1={}", hex::encode(mac.finalize().into_bytes()))
   }
 
-  if url.query().is_some() || url.fragment().is_some() {
-    warn!("Jenkins URL should not contain query parameters or fragments");
-    return Ok(false);
+  #[test]
+  fn any_configured_secret_verifies_during_rotation() {
+    let payload = br#"{"zen":"Design for failure."}"#;
+    let secrets = [secret("old", "old-secret"), secret("new", "new-secret")];
+    for (signed_with, key_id) in [("old-secret", "old"), ("new-secret", "new")]
+    {
+      let signature = sign_sha256(signed_with, payload);
+      let matched = verify_signature(
+        payload,
+        &signature,
+        SignatureAlgorithm::Sha256,
+        &secrets,
+      )
+      .unwrap();
+      assert_eq!(matched.map(|s| s.key_id.as_str()), Some(key_id));
+    }
   }
 
-  Ok(true)
-}
+  #[test]
+  fn retired_secret_no_longer_verifies() {
+    let payload = br#"{"zen":"Design for failure."}"#;
+    let signature = sign_sha256("old-secret", payload);
+    let secrets = [secret("new", "new-secret")];
+    let matched = verify_signature(
+      payload,
+      &signature,
+      SignatureAlgorithm::Sha256,
+      &secrets,
+    )
+    .unwrap();
+    assert!(matched.is_none());
+  }
 
-fn construct_jenkins_url(base_url: &str) -> Result<String, ProxyError> {
-  let mut url =
-    Url::parse(base_url).map_err(|_| ProxyError::InvalidJenkinsUrl)?;
+  #[test]
+  fn altered_payload_does_not_verify() {
+    let signature = sign_sha256("secret", br#"{"ref":"refs/heads/main"}"#);
+    let secrets = [secret("current", "secret")];
+    let matched = verify_signature(
+      br#"{"ref":"refs/heads/release"}"#,
+      &signature,
+      SignatureAlgorithm::Sha256,
+      &secrets,
+    )
+    .unwrap();
+    assert!(matched.is_none());
+  }
 
-  let path = url.path().to_string();
+  #[test]
+  fn legacy_sha1_signature_verifies_only_as_sha1() {
+    let payload = br#"{"zen":"Keep it logically awesome."}"#;
+    let secrets = [secret("current", "secret")];
+    let signature = sign_sha1("secret", payload);
+    let as_sha1 =
+      verify_signature(payload, &signature, SignatureAlgorithm::Sha1, &secrets)
+        .unwrap();
+    assert_eq!(as_sha1.map(|s| s.key_id.as_str()), Some("current"));
+
+    let as_sha256 = verify_signature(
+      payload,
+      &signature,
+      SignatureAlgorithm::Sha256,
+      &secrets,
+    )
+    .unwrap();
+    assert!(as_sha256.is_none());
+  }
 
-  let new_path = if !path.ends_with('/') {
-    format!("{}/", path)
-  } else {
-    path.clone()
-  };
+  #[test]
+  fn malformed_signatures_do_not_verify() {
+    let payload = br#"{"zen":"Keep it logically awesome."}"#;
+    let secrets = [secret("current", "secret")];
+    let signature = sign_sha256("secret", payload);
+    let truncated = &signature[..signature.len() - 2];
+    let bare = signature.trim_start_matches("sha256=");
+    for malformed in [truncated, bare, "sha256=not-hex", "sha256=", ""] {
+      let matched = verify_signature(
+        payload,
+        malformed,
+        SignatureAlgorithm::Sha256,
+        &secrets,
+      )
+      .unwrap();
+      assert!(matched.is_none(), "{:?} verified", malformed);
+    }
+  }
 
-  if !new_path.contains("/github-webhook/") && !new_path.contains("/ghprbhook/")
-  {
-    url.set_path(&format!("{}github-webhook/", new_path));
-  } else {
-    url.set_path(&new_path);
+  /**
+   * A configuration forwarding to `jenkins` with `--validation` set to
+   * `validation`, plus the routes in `routes` and any further `flags`.
+   */
+  fn configure(
+    jenkins: &FakeJenkins,
+    validation: &str,
+    routes: &str,
+    flags: &[&str],
+  ) -> Result<AppState, String> {
+    let dir = TempDir::new();
+    let routes = dir.write("routes.toml", routes);
+    let url = format!("{}/ok/", jenkins.url);
+    let args = [
+      "github-to-jenkins-webhook",
+      "--github-secret",
+      "secret",
+      "--jenkins-url",
+      &url,
+      "--validation",
+      validation,
+      "--routes-file",
+      routes.to_str().unwrap(),
+    ];
+    AppState::build(&Args::try_parse_from(args.iter().chain(flags)).unwrap(), 0)
   }
 
-  Ok(url.to_string())
-}
+  async fn send(
+    state: AppState,
+    event: &str,
+    body: &'static [u8],
+  ) -> (StatusCode, String) {
+    let args = Args::try_parse_from(["github-to-jenkins-webhook"]).unwrap();
+    let app = actix_test::init_service(
+      App::new()
+        .app_data(web::Data::new(StateHandle::new(state, args)))
+        .app_data(web::Data::new(SchemaDrift::new(None).unwrap()))
+        .route("/", web::post().to(handle_webhook)),
+    )
+    .await;
+    let request = actix_test::TestRequest::post()
+      .uri("/")
+      .insert_header((GITHUB_EVENT_HEADER, event))
+      .insert_header((GITHUB_SIGNATURE_HEADER, sign_sha256("secret", body)))
+      .set_payload(body)
+      .to_request();
+    let response = actix_test::call_service(&app, request).await;
+    let status = response.status();
+    let body = actix_test::read_body(response).await;
+    (status, String::from_utf8_lossy(&body).to_string())
+  }
 
-async fn forward_to_jenkins(
-  original_req: &HttpRequest,
-  body: &web::Bytes,
-  jenkins_url: &str,
-) -> Result<HttpResponse, ProxyError> {
-  let client = reqwest::Client::builder()
-    .timeout(std::time::Duration::from_secs(30))
-    .build()
-    .map_err(|e| ProxyError::ForwardRequest(e))?;
-
-  let mut req_builder = client.post(jenkins_url).body(body.to_vec());
-
-  let headers = actix_to_reqwest_headers(&original_req);
-  for (header_name, header_value) in headers.iter() {
-    // if header_name == GITHUB_SIGNATURE_HEADER {
-    //   continue;
-    // }
-
-    let header_name_str = header_name.as_str();
-    if header_name_str.to_lowercase().starts_with("x-github-")
-      || header_name_str.to_lowercase().starts_with("x-hub-")
-      || header_name_str.to_lowercase().starts_with("x-forwarded-")
-      || header_name_str.to_lowercase() == "host"
-      || header_name_str.to_lowercase() == "accept"
-      || header_name_str.to_lowercase() == "content-type"
-    {
-      debug!(
-        "Passing header - {}: {:?}",
-        header_name,
-        header_value.to_str()
-      );
-      req_builder = req_builder.header(header_name.clone(), header_value);
-    } else {
-      debug!(
-        "Dropping header - {}: {:?}",
-        header_name,
-        header_value.to_str()
-      );
+  #[actix_web::test]
+  async fn pings_are_answered_in_every_mode() {
+    let jenkins = FakeJenkins::start();
+    for validation in ["strict", "lenient", "off"] {
+      let state = configure(&jenkins, validation, "", &[]).unwrap();
+      let (status, body) = send(state, "ping", PING).await;
+      assert_eq!(status, StatusCode::OK, "--validation {}", validation);
+      assert_eq!(body, "Keep it logically awesome.");
     }
+    assert!(jenkins.received().is_empty());
   }
 
-  let response = req_builder.send().await?;
-
-  let status = response.status();
-  let body = response.bytes().await?;
+  #[actix_web::test]
+  async fn lenient_routes_like_strict() {
+    let jenkins = FakeJenkins::start();
+    let routes = r#"
+      [[route]]
+      name = "changes"
+      ref = "changes"
+      action = "drop"
+      "#;
+    for validation in ["strict", "lenient"] {
+      let state = configure(&jenkins, validation, routes, &[]).unwrap();
+      let (status, body) = send(state, "check_run", CHECK_RUN).await;
+      assert_eq!(status, StatusCode::ACCEPTED, "--validation {}", validation);
+      assert_eq!(body, "Dropped by route 'changes'");
+    }
+    assert!(jenkins.received().is_empty());
+  }
 
-  info!("Forwarded webhook to Jenkins. Response status: {}", status,);
+  #[actix_web::test]
+  async fn off_routes_on_the_repository() {
+    let jenkins = FakeJenkins::start();
+    let routes = r#"
+      [[route]]
+      name = "codertocat"
+      repository = "Codertocat/*"
+      action = "drop"
+      "#;
+    let state = configure(&jenkins, "off", routes, &[]).unwrap();
+    let (status, body) = send(state, "push", PUSH).await;
+    assert_eq!(status, StatusCode::ACCEPTED);
+    assert_eq!(body, "Dropped by route 'codertocat'");
+
+    let state = configure(&jenkins, "off", "", &[]).unwrap();
+    let (status, _) = send(state, "push", PUSH).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(jenkins.received().len(), 1);
+  }
 
-  Ok(
-    HttpResponse::build(
-      actix_web::http::StatusCode::from_u16(status.as_u16()).unwrap(),
-    )
-    .body(body),
-  )
+  #[test]
+  fn off_rejects_what_it_cannot_evaluate() {
+    let jenkins = FakeJenkins::start();
+    for route in ["ref = \"main\"", "base_branch = \"main\""] {
+      let routes = format!("[[route]]\nname = \"main\"\n{}\n", route);
+      assert!(configure(&jenkins, "lenient", &routes, &[]).is_ok());
+      assert!(configure(&jenkins, "off", &routes, &[]).is_err());
+    }
+    for filter in ["--honor-skip-ci", "--include-path=src/**"] {
+      assert!(configure(&jenkins, "lenient", "", &[filter]).is_ok());
+      assert!(configure(&jenkins, "off", "", &[filter]).is_err());
+    }
+  }
 }